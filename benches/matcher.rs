@@ -0,0 +1,58 @@
+/* benches/matcher.rs */
+
+//! Compares the linear and trie lookups of `PrefixMatcher`, to pick the
+//! number of prefixes above which `PrefixMatcher::new` switches to the trie.
+//!
+//! Run with `cargo bench --bench matcher`.
+
+use axum_governor::{PrefixMatcher, RuleMatcher};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+
+/// `count` prefixes of two to four segments, like a real route table.
+fn prefixes(count: usize) -> Vec<String> {
+    (0..count)
+        .map(|i| match i % 3 {
+            0 => format!("/api/v{}/resource{i}", i % 4),
+            1 => format!("/api/v{}/resource{i}/items", i % 4),
+            _ => format!("/static{i}"),
+        })
+        .collect()
+}
+
+/// Request paths hitting the first, middle and last prefix, plus a miss.
+fn paths(count: usize) -> Vec<String> {
+    let prefixes = prefixes(count);
+    vec![
+        format!("{}/42", prefixes[0]),
+        format!("{}/42/details", prefixes[count / 2]),
+        format!("{}/x", prefixes[count - 1]),
+        "/api/v9/unknown/path/deep".to_string(),
+    ]
+}
+
+fn lookup(c: &mut Criterion) {
+    let mut group = c.benchmark_group("prefix_matcher");
+    for count in [8, 16, 32, 40, 48, 56, 64, 128, 256, 512] {
+        let paths = paths(count);
+        let linear = PrefixMatcher::linear(prefixes(count));
+        let trie = PrefixMatcher::trie(prefixes(count));
+        group.bench_with_input(BenchmarkId::new("linear", count), &paths, |b, paths| {
+            b.iter(|| {
+                for path in paths {
+                    black_box(linear.resolve(black_box(path)));
+                }
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("trie", count), &paths, |b, paths| {
+            b.iter(|| {
+                for path in paths {
+                    black_box(trie.resolve(black_box(path)));
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, lookup);
+criterion_main!(benches);
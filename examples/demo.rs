@@ -6,11 +6,10 @@ use axum::{
     routing::{get, post},
     Router,
 };
-use axum_governor::{GovernorConfig, GovernorLayer};
+use axum_governor::{Governor, GovernorConfig};
 use lazy_limit::{init_rate_limiter, Duration, HttpMethod, RuleConfig};
 use real::RealIpLayer;
 use std::net::SocketAddr;
-use tower::ServiceBuilder;
 use tracing::info;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
@@ -66,18 +65,18 @@ async fn main() {
 
     // --- Layer Configurations ---
 
-    // Default layer: respects both global and route-specific rules.
-    let default_limiter = ServiceBuilder::new()
-        .layer(RealIpLayer::default())
-        .layer(GovernorLayer::default());
-
-    // Override layer: ignores global rules, only applies route-specific rules.
-    let override_limiter =
-        ServiceBuilder::new()
-            .layer(RealIpLayer::default())
-            .layer(GovernorLayer::new(
-                GovernorConfig::new().override_mode(true),
-            ));
+    // Groups are defined once and selected by each sub-router below.
+    let governor = Governor::new()
+        // Default group: respects both global and route-specific rules.
+        .group("default", GovernorConfig::new())
+        // Premium group: ignores global rules, only applies route-specific rules,
+        // and keeps its counters apart from the default group's.
+        .group(
+            "premium",
+            GovernorConfig::new()
+                .override_mode(true)
+                .key_namespace("premium"),
+        );
 
     // --- Router Definitions ---
 
@@ -88,15 +87,19 @@ async fn main() {
         .route("/api/login", get(login_handler))
         .route("/api/prefix/{test}", get(prefix_handler))
         .route("/api/contact", post(contact_handler))
-        .layer(default_limiter);
+        .layer(governor.layer_for_group("default"));
 
     // Routes with override rate limiting
     let premium_routes = Router::new()
         .route("/api/premium", get(premium_api_handler))
-        .layer(override_limiter);
+        .layer(governor.layer_for_group("premium"));
 
     // Combine all routers into a single app
-    let app = Router::new().merge(default_routes).merge(premium_routes);
+    // `RealIpLayer` wraps both groups, so it runs before every `GovernorLayer`.
+    let app = Router::new()
+        .merge(default_routes)
+        .merge(premium_routes)
+        .layer(RealIpLayer::default());
 
     // --- Server Startup ---
     let addr = SocketAddr::from(([127, 0, 0, 1], 3000));
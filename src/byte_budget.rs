@@ -0,0 +1,35 @@
+/* src/byte_budget.rs */
+
+use crate::count_budget::CountBudget;
+use std::time::Duration;
+
+/// A per-key budget of request body bytes per window.
+///
+/// Each key may send at most `max_bytes` of request bodies, as declared by
+/// `Content-Length`, within a fixed `window`. A request that would exceed the
+/// budget is denied without being charged. This complements the request
+/// counts of `lazy-limit` on ingestion endpoints, where a few huge uploads
+/// hurt more than many small requests.
+///
+/// Requests without `Content-Length` (e.g. chunked uploads) are charged
+/// nothing, so pair the budget with a body size limit such as axum's
+/// `DefaultBodyLimit`. Clones share the same budget.
+#[derive(Debug, Clone)]
+pub struct ByteBudget {
+    bytes: CountBudget,
+}
+
+impl ByteBudget {
+    /// Creates a budget of `max_bytes` per `window`, e.g.
+    /// `ByteBudget::new(100 * 1024 * 1024, Duration::from_secs(3600))`.
+    pub fn new(max_bytes: u64, window: Duration) -> Self {
+        Self {
+            bytes: CountBudget::new(max_bytes, window),
+        }
+    }
+
+    /// Returns the byte counts per key.
+    pub(crate) fn bytes(&self) -> &CountBudget {
+        &self.bytes
+    }
+}
@@ -0,0 +1,1577 @@
+/* src/check.rs */
+
+use crate::{
+    format, forwarded, is_extension_method, key, levels, map_method, matcher, methods, path,
+    reservation::Charge, AppliedRules, AsyncKeyExtractor, ClientClass, Criticality, Decision,
+    DeniedInfo, ErrorFormat, GovernorConfig, GovernorError, GovernorFlags, GovernorTags, KeyError,
+    LoadShed, MatchSource, MethodPolicy, MissingIpPolicy, PolicyQuery, PolicyVerdict,
+    PrivateTraffic, Rejection, REJECTION_HEADER,
+};
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, MatchedPath, OriginalUri},
+    http::{
+        header::{ALLOW, CONNECTION, CONTENT_LENGTH},
+        request::Parts,
+        Extensions, HeaderMap, HeaderValue, Method, Response, StatusCode, Uri, Version,
+    },
+};
+use real::RealIp;
+use std::{net::SocketAddr, time::Instant};
+use tracing::{debug, warn};
+
+/// A request the limiter let through.
+pub(crate) struct Admitted {
+    /// The client key, used to attribute the response afterwards.
+    pub(crate) key: String,
+    /// The classification to insert into the request extensions.
+    pub(crate) tags: GovernorTags,
+    /// The budget charges of the request, refunded if its handler panics.
+    pub(crate) charges: Vec<Charge>,
+}
+
+/// Runs every check of `config` against a request.
+///
+/// Shared by `GovernorMiddleware` and the `RateLimited` extractor. Returns the
+/// finished rejection response if the request must not proceed.
+pub(crate) async fn check(
+    config: &GovernorConfig,
+    parts: &Parts,
+) -> Result<Admitted, Response<Body>> {
+    let config = match &config.anonymous {
+        Some((is_authenticated, anonymous)) if !is_authenticated(parts) => anonymous.as_ref(),
+        _ => config,
+    };
+    let flags = match (parts.extensions.get::<GovernorFlags>(), &config.flags_from) {
+        (Some(flags), _) => *flags,
+        (None, Some(resolve)) => resolve(parts),
+        (None, None) => GovernorFlags::default(),
+    };
+
+    decide(config, parts, flags).await.map_err(|mut response| {
+        if flags.skip_headers {
+            response.headers_mut().remove(REJECTION_HEADER);
+        }
+        response
+    })
+}
+
+/// Decides on a request with the given per-request `flags`.
+async fn decide(
+    config: &GovernorConfig,
+    parts: &Parts,
+    flags: GovernorFlags,
+) -> Result<Admitted, Response<Body>> {
+    let method = &parts.method;
+    let version = parts.version;
+    let format = config.error_format.negotiate(&parts.headers);
+
+    if config.strict_forwarded_headers
+        && let Err(reason) = forwarded::validate(&parts.headers, config.trusted_hops)
+    {
+        debug!("Rejecting request with suspicious forwarding headers: {reason}");
+        let response = error(
+            config,
+            format,
+            StatusCode::BAD_REQUEST,
+            Rejection::MalformedHeaders,
+            &format!("Bad Request: {reason}"),
+        );
+        return Err(reject(
+            config,
+            version,
+            GovernorError::MalformedHeaders(reason),
+            response,
+        ));
+    }
+
+    let trace_connect = if methods::is_trace_or_connect(method) {
+        config.trace_connect
+    } else {
+        MethodPolicy::Limit
+    };
+    if let Some(status) = trace_connect.rejection_status() {
+        debug!("Rejecting {method} request by method policy");
+        let response = error(
+            config,
+            format,
+            status,
+            Rejection::ForbiddenMethod,
+            status.canonical_reason().unwrap_or_default(),
+        );
+        return Err(reject(
+            config,
+            version,
+            GovernorError::ForbiddenMethod(method.clone()),
+            response,
+        ));
+    }
+
+    let path = rule_path(config, &parts.uri, &parts.extensions);
+    let tenant = config
+        .tenant_resolver
+        .as_ref()
+        .and_then(|resolver| resolver.resolve(parts));
+
+    if let Some(allowed) = config.allowed_methods.get(&path)
+        && !methods::allows(allowed, method)
+    {
+        debug!("Rejecting {method} {path}: method not allowed");
+        let mut response = error(
+            config,
+            format,
+            StatusCode::METHOD_NOT_ALLOWED,
+            Rejection::ForbiddenMethod,
+            "Method Not Allowed",
+        );
+        response
+            .headers_mut()
+            .insert(ALLOW, methods::allow_header(allowed));
+        return Err(reject(
+            config,
+            version,
+            GovernorError::ForbiddenMethod(method.clone()),
+            response,
+        ));
+    }
+
+    // Extract the RealIp extension. This should be present.
+    // Ensure `RealIpLayer` is added *before* `GovernorLayer`.
+    let client_ip = match (
+        parts.extensions.get::<RealIp>(),
+        &config.on_missing_ip,
+        parts.extensions.get::<ConnectInfo<SocketAddr>>(),
+    ) {
+        (Some(real_ip), _, _) => real_ip.ip(),
+        (None, MissingIpPolicy::UseConnectInfo, Some(ConnectInfo(addr))) => addr.ip(),
+        (None, MissingIpPolicy::FailOpen, _) => {
+            warn!("RealIp extension not found, letting the request through unlimited.");
+            return Ok(Admitted {
+                key: String::new(),
+                charges: Vec::new(),
+                tags: GovernorTags {
+                    tier: config.tier.clone(),
+                    tenant,
+                    client_class: ClientClass::Public,
+                    key_source: None,
+                    rules: AppliedRules::Skipped,
+                    shadow_hit: false,
+                    seen: None,
+                },
+            });
+        }
+        (None, policy, _) => {
+            warn!(
+                "RealIp extension not found. Make sure RealIpLayer is installed before GovernorLayer."
+            );
+            let response = match policy {
+                MissingIpPolicy::CustomResponse(respond) => respond(),
+                _ => error(
+                    config,
+                    format,
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Rejection::Misconfigured,
+                    "Internal Server Error: Rate limiter misconfigured",
+                ),
+            };
+            return Err(reject(config, version, GovernorError::MissingIp, response));
+        }
+    };
+
+    let route_extractor = config
+        .route_extractors
+        .iter()
+        .filter(|(prefix, _)| matcher::covers(prefix, &path))
+        .max_by_key(|(prefix, _)| prefix.len())
+        .map(|(_, extractor)| extractor);
+    let extracted = match (
+        route_extractor,
+        &config.async_key_extractor,
+        &config.key_extractor,
+    ) {
+        (Some(extractor), _, _) => Some(extractor.extract_named(parts)),
+        (None, Some(extractor), _) => Some(
+            extract_async(config, extractor.as_ref(), parts)
+                .await
+                .map(|key| (key, None)),
+        ),
+        (None, None, Some(extractor)) => Some(extractor.extract_named(parts)),
+        (None, None, None) => None,
+    };
+    let (client_key, key_source) = match extracted {
+        Some(Ok((client_key, source))) => {
+            if let Some(source) = source {
+                debug!("Keyed {method} {path} by `{source}`");
+            }
+            (client_key, source.map(str::to_string))
+        }
+        None | Some(Err(KeyError::Missing)) => (key::ip_key(client_ip, config), None),
+        Some(Err(KeyError::Invalid(reason))) => {
+            debug!("Rejecting request with invalid key: {reason}");
+            let response = error(
+                config,
+                format,
+                StatusCode::BAD_REQUEST,
+                Rejection::InvalidKey,
+                &format!("Bad Request: {reason}"),
+            );
+            return Err(reject(
+                config,
+                version,
+                GovernorError::InvalidKey(reason),
+                response,
+            ));
+        }
+    };
+    // Namespace the key, so nothing keyed by it is shared across tenants.
+    let (client_key, untenanted_key) = match &tenant {
+        Some(tenant) => (format!("{tenant}/{client_key}"), Some(client_key)),
+        None => (client_key, None),
+    };
+    let peer = parts
+        .extensions
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip());
+    let private_source = key::is_private_source(client_ip, peer);
+    let private_traffic = if private_source {
+        config.private_traffic
+    } else {
+        PrivateTraffic::Limit
+    };
+
+    let governor = config.governor.as_ref();
+    let allowlisted = governor.is_some_and(|governor| governor.is_allowlisted(client_ip));
+    let denylisted = governor.is_some_and(|governor| governor.is_denylisted(client_ip));
+    // Bans of the plain key apply in every tenant, and bans of the client
+    // IP whatever the request is keyed by, so a made-up API key can't get
+    // around them. With an IP prefix set, bans of the plain address apply
+    // as well as bans of the prefix, as in `GovernorListener`.
+    let ban = governor.filter(|_| !allowlisted).and_then(|governor| {
+        governor
+            .ban_for(&client_key)
+            .or_else(|| untenanted_key.and_then(|key| governor.ban_for(&key)))
+            .or_else(|| {
+                let ip_key = key::ip_key(client_ip, config);
+                let plain = client_ip.to_canonical().to_string();
+                let ip_keys = if plain == ip_key {
+                    vec![ip_key]
+                } else {
+                    vec![ip_key, plain]
+                };
+                ip_keys.into_iter().find_map(|ip_key| {
+                    let tenanted = tenant.as_ref().map(|tenant| format!("{tenant}/{ip_key}"));
+                    tenanted
+                        .and_then(|key| governor.ban_for(&key))
+                        .or_else(|| governor.ban_for(&ip_key))
+                })
+            })
+    });
+
+    let service = config
+        .service_auth
+        .as_ref()
+        .and_then(|auth| auth.verify(&parts.headers).map(|name| (auth, name)));
+    let exempt_service = service
+        .as_ref()
+        .is_some_and(|(auth, _)| auth.is_unlimited());
+
+    let exempt_retry = config
+        .retry_policy
+        .as_ref()
+        .is_some_and(|policy| policy.is_exempt(&parts.headers, &client_key));
+
+    let exempt = allowlisted
+        || flags.trusted
+        || exempt_service
+        || exempt_retry
+        || private_traffic == PrivateTraffic::Exempt
+        || trace_connect == MethodPolicy::Exempt;
+
+    let verdict = match &config.external_policy {
+        Some(policy) if ban.is_none() && !denylisted && !exempt => {
+            let query = PolicyQuery {
+                key: client_key.clone(),
+                method: method.clone(),
+                path: path.clone(),
+                tenant: tenant.clone(),
+            };
+            policy.decide(query).await
+        }
+        _ => PolicyVerdict::Allow,
+    };
+
+    let mut rules = AppliedRules::Skipped;
+    let mut charges = Vec::new();
+    // When the limiter frees a slot for a denied request, if it knows.
+    let mut retry_at = None;
+    let mut decision = if ban.is_some() || denylisted {
+        Decision::Banned
+    } else if verdict == PolicyVerdict::Deny {
+        debug!("External policy denied {method} {path} for {client_key}");
+        Decision::Denied
+    } else if exempt || verdict == PolicyVerdict::Exempt {
+        Decision::Allowed
+    } else {
+        let key = match &service {
+            Some((_, name)) => format!("service:{name}"),
+            None => client_key.clone(),
+        };
+        let key = match &config.key_template {
+            Some(template) => {
+                key::render_template(template, config, &key, client_ip, method, &path)
+            }
+            None => key,
+        };
+        let key = if config.user_agent_in_key {
+            format!("{key}|{}", key::user_agent_family(&parts.headers))
+        } else {
+            key
+        };
+        let key = if config.separate_extension_methods && is_extension_method(method) {
+            format!("{key}#{method}")
+        } else {
+            key
+        };
+        let key = match &config.key_namespace {
+            Some(namespace) => format!("{namespace}:{key}"),
+            None => key,
+        };
+        let key = match &config.key_codec {
+            Some(codec) => codec.encode(&key),
+            None => key,
+        };
+        let http_method = match &config.method_mapper {
+            Some(mapper) => mapper(method),
+            None => map_method(method.clone()),
+        };
+
+        let deny_cache = config
+            .deny_cache
+            .as_ref()
+            .map(|cache| (cache, format!("{key} {method} {path}")));
+
+        if let Some(until) = deny_cache.as_ref().and_then(|(cache, id)| cache.denied(id)) {
+            // Still within the hold time of a recent denial.
+            retry_at = until;
+            Decision::Denied
+        } else {
+            let override_mode =
+                config.override_mode || private_traffic == PrivateTraffic::RouteRulesOnly;
+
+            rules = if override_mode {
+                AppliedRules::RouteOnly
+            } else {
+                AppliedRules::GlobalAndRoute
+            };
+            // The first sighting of a key under a first-seen filter skips the
+            // limiter, so one-hit clients never reach its store. Every other
+            // check below still applies.
+            let first_sighting = config
+                .first_seen_filter
+                .as_ref()
+                .is_some_and(|filter| !filter.check_and_insert(&client_key));
+            let (allowed, denied_until) = if first_sighting {
+                (true, None)
+            } else {
+                match engine_check(
+                    config,
+                    tenant.as_deref(),
+                    &key,
+                    &path,
+                    method,
+                    override_mode,
+                ) {
+                    Some(Ok(())) => (true, None),
+                    Some(Err(until)) => (false, Some(until)),
+                    None if override_mode => (
+                        lazy_limit::limit_override!(&key, &path, http_method).await,
+                        None,
+                    ),
+                    None => (lazy_limit::limit!(&key, &path, http_method).await, None),
+                }
+            };
+
+            if !allowed {
+                if let Some((cache, id)) = deny_cache {
+                    cache.deny(id, denied_until);
+                }
+                retry_at = denied_until;
+                Decision::Denied
+            } else if !levels::charge_all(
+                &config.key_levels,
+                parts,
+                tenant.as_deref(),
+                &mut charges,
+            ) {
+                Decision::Denied
+            } else if let Some(budget) = &config.byte_budget
+                && !Charge::record(
+                    budget.bytes(),
+                    &client_key,
+                    content_length(&parts.headers),
+                    &mut charges,
+                )
+            {
+                debug!("Byte budget exhausted for {client_key}");
+                Decision::Denied
+            } else if let Some(name) = config.budgets.get(&path)
+                && governor.is_some_and(|governor| {
+                    !governor.record_budget(name, &client_key, 1, &mut charges)
+                })
+            {
+                debug!("Budget `{name}` exhausted for {client_key}");
+                Decision::Denied
+            } else if config
+                .response_budget
+                .as_ref()
+                .is_some_and(|budget| budget.bytes().is_exhausted(&client_key))
+            {
+                debug!("Response byte budget exhausted for {client_key}");
+                Decision::Denied
+            } else {
+                Decision::Allowed
+            }
+        }
+    };
+
+    if decision == Decision::Allowed
+        && rules != AppliedRules::Skipped
+        && config
+            .slo_guard
+            .as_ref()
+            .is_some_and(|guard| !guard.admit(&path, criticality(config, &path)))
+    {
+        debug!("Error budget low: throttling {method} {path} for {client_key}");
+        decision = Decision::Denied;
+    }
+
+    let tenant_exhausted = decision == Decision::Allowed
+        && rules != AppliedRules::Skipped
+        && config
+            .tenant_cap
+            .as_ref()
+            .zip(tenant.as_ref())
+            .is_some_and(|(cap, tenant)| !cap.charge(tenant, &mut charges));
+    if tenant_exhausted {
+        debug!("Tenant quota exhausted for {client_key}");
+        decision = Decision::Denied;
+    }
+
+    if decision == Decision::Allowed
+        && rules != AppliedRules::Skipped
+        && let Some(pacer) = &config.pacer
+    {
+        match pacer.reserve(&client_key) {
+            Some(wait) if !wait.is_zero() => {
+                debug!("Pacing {client_key}: holding request for {wait:?}");
+                tokio::time::sleep(wait).await;
+            }
+            Some(_) => {}
+            None => {
+                debug!("Pacing queue full for {client_key}");
+                decision = Decision::Denied;
+            }
+        }
+    }
+
+    if decision != Decision::Allowed {
+        // A request that doesn't get through uses up no budget, whichever
+        // check turned it down.
+        for charge in charges.drain(..) {
+            charge.refund();
+        }
+    }
+
+    if let Some(tarpit) = &config.tarpit {
+        // A tenant over its cap is not the fault of this client.
+        if decision == Decision::Denied && !tenant_exhausted {
+            tarpit.strike(&client_key);
+        }
+        if decision != Decision::Banned
+            && let Some(delay) = tarpit.delay_for(&client_key)
+        {
+            debug!("Tarpitting {client_key} for {delay:?}");
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    let seen = config
+        .seen_tracker
+        .as_ref()
+        .map(|tracker| tracker.touch(&client_key));
+
+    if let Some(log) = &config.decision_log {
+        let first_seen = seen.map(|seen| seen.first_seen);
+        log.record(&client_key, method.clone(), &path, decision, first_seen);
+    }
+    if let Some(reports) = &config.abuse_reports {
+        // Route templates and rule paths keep the number of tallied routes
+        // bounded, unlike raw paths with IDs in them.
+        let route = parts
+            .extensions
+            .get::<MatchedPath>()
+            .map_or(path.as_str(), MatchedPath::as_str);
+        match &tenant {
+            Some(tenant) => reports.record(&client_key, &format!("{tenant}:{route}"), decision),
+            None => reports.record(&client_key, route, decision),
+        }
+    }
+    if let Some(usage) = &config.usage_log {
+        usage.record(&client_key, decision);
+    }
+
+    let mut tags = GovernorTags {
+        tier: config.tier.clone(),
+        tenant: tenant.clone(),
+        client_class: if allowlisted {
+            ClientClass::Allowlisted
+        } else if flags.trusted {
+            ClientClass::Trusted
+        } else if service.is_some() {
+            ClientClass::Service
+        } else if private_source {
+            ClientClass::Private
+        } else {
+            ClientClass::Public
+        },
+        key_source,
+        rules,
+        shadow_hit: false,
+        seen,
+    };
+
+    match decision {
+        Decision::Allowed => Ok(Admitted {
+            key: client_key,
+            tags,
+            charges,
+        }),
+        // Shadow mode: record the denial but let the request through.
+        Decision::Denied
+            if flags.force_shadow
+                || config
+                    .governor
+                    .as_ref()
+                    .is_some_and(|governor| governor.shadow_mode()) =>
+        {
+            debug!("Shadow mode: would have denied {method} {path} for {client_key}");
+            tags.shadow_hit = true;
+            Ok(Admitted {
+                key: client_key,
+                tags,
+                charges,
+            })
+        }
+        // The tenant used up its aggregate cap.
+        Decision::Denied if tenant_exhausted => {
+            let tenant = tenant.unwrap_or_default();
+            let response = error(
+                config,
+                format,
+                StatusCode::TOO_MANY_REQUESTS,
+                Rejection::TenantQuota,
+                "Too Many Requests: tenant quota exhausted",
+            );
+            Err(reject(
+                config,
+                version,
+                GovernorError::TenantQuota(tenant),
+                response,
+            ))
+        }
+        // Request is denied, return `429 Too Many Requests` or the configured
+        // status.
+        Decision::Denied => {
+            let info = DeniedInfo {
+                key: client_key,
+                method: method.clone(),
+                path,
+                tier: config.tier.clone(),
+                tenant,
+                retry_after: retry_at.map(|at| at.saturating_duration_since(Instant::now())),
+            };
+            let response = match &config.on_denied {
+                Some(handler) => handler(&info),
+                None => {
+                    let status = config
+                        .denied_status
+                        .unwrap_or(StatusCode::TOO_MANY_REQUESTS);
+                    format.response(
+                        status,
+                        Rejection::RateLimited,
+                        status.canonical_reason().unwrap_or("Too Many Requests"),
+                        info.retry_after.map(format::whole_seconds),
+                        &config.error_templates,
+                    )
+                }
+            };
+            Err(reject(
+                config,
+                version,
+                GovernorError::Denied(info),
+                response,
+            ))
+        }
+        // Client is banned or denylisted, return the configured ban response.
+        Decision::Banned => {
+            let response =
+                config
+                    .ban_response
+                    .render(ban.as_ref(), format, &config.error_templates);
+            Err(reject(
+                config,
+                version,
+                GovernorError::Banned(ban),
+                response,
+            ))
+        }
+    }
+}
+
+/// Returns the path rules are looked up by for a request to `uri`.
+pub(crate) fn rule_path(config: &GovernorConfig, uri: &Uri, extensions: &Extensions) -> String {
+    let path = match config.match_on {
+        MatchSource::CurrentUri => uri.path(),
+        MatchSource::OriginalUri => extensions
+            .get::<OriginalUri>()
+            .map_or(uri.path(), |OriginalUri(original)| original.path()),
+        MatchSource::MatchedPath => extensions
+            .get::<MatchedPath>()
+            .map_or(uri.path(), MatchedPath::as_str),
+    };
+    let path = path::rule_path(path, config.normalize_path).into_owned();
+    match &config.rule_matcher {
+        Some(matcher) => matcher.resolve(&path).unwrap_or(path),
+        None => path,
+    }
+}
+
+/// Returns the criticality tier of the rule path `path`.
+pub(crate) fn criticality(config: &GovernorConfig, path: &str) -> Criticality {
+    config.criticality.get(path).copied().unwrap_or_default()
+}
+
+/// Decides with the built-in engine of `tenant` or of `config`, if there is
+/// one.
+#[cfg(feature = "engine")]
+fn engine_check(
+    config: &GovernorConfig,
+    tenant: Option<&str>,
+    key: &str,
+    path: &str,
+    method: &Method,
+    override_mode: bool,
+) -> Option<Result<(), Instant>> {
+    tenant
+        .and_then(|tenant| config.tenant_engines.get(tenant))
+        .or(config.engine.as_ref())
+        .map(|engine| engine.check(key, path, method, override_mode))
+}
+
+#[cfg(not(feature = "engine"))]
+fn engine_check(
+    _config: &GovernorConfig,
+    _tenant: Option<&str>,
+    _key: &str,
+    _path: &str,
+    _method: &Method,
+    _override_mode: bool,
+) -> Option<Result<(), Instant>> {
+    None
+}
+
+/// Runs `extractor`, bounded by `key_timeout`.
+async fn extract_async(
+    config: &GovernorConfig,
+    extractor: &dyn AsyncKeyExtractor,
+    parts: &Parts,
+) -> Result<String, KeyError> {
+    let Some(timeout) = config.key_timeout else {
+        return extractor.extract(parts).await;
+    };
+    match tokio::time::timeout(timeout, extractor.extract(parts)).await {
+        Ok(extracted) => extracted,
+        Err(_) => {
+            warn!("Key extraction timed out after {timeout:?}");
+            config.key_fallback.clone().ok_or(KeyError::Missing)
+        }
+    }
+}
+
+/// Builds the response for a request shed by `shed`.
+pub(crate) fn shed(
+    config: &GovernorConfig,
+    shed: &LoadShed,
+    version: Version,
+    headers: &HeaderMap,
+) -> Response<Body> {
+    let response = config.error_format.negotiate(headers).response(
+        StatusCode::SERVICE_UNAVAILABLE,
+        Rejection::Overloaded,
+        "Service Unavailable",
+        Some(shed.retry_after_secs()),
+        &config.error_templates,
+    );
+    reject(config, version, GovernorError::Overloaded, response)
+}
+
+/// Returns the declared body size of a request, or 0 if unknown.
+fn content_length(headers: &HeaderMap) -> u64 {
+    headers
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(0)
+}
+
+/// Builds an error response in `format`.
+fn error(
+    config: &GovernorConfig,
+    format: ErrorFormat,
+    status: StatusCode,
+    rejection: Rejection,
+    message: &str,
+) -> Response<Body> {
+    format.response(status, rejection, message, None, &config.error_templates)
+}
+
+/// Finishes a response produced by the governor instead of the inner service.
+fn reject(
+    config: &GovernorConfig,
+    version: Version,
+    error: GovernorError,
+    response: Response<Body>,
+) -> Response<Body> {
+    let response = match &config.error_handler {
+        Some(handler) => handler.handle(&error, response),
+        None => response,
+    };
+    let mut response = error.rejection().mark(response);
+    // `Connection` is HTTP/1 only; HTTP/2 rejects connection-specific headers.
+    if config.close_on_reject && version <= Version::HTTP_11 {
+        response
+            .headers_mut()
+            .insert(CONNECTION, HeaderValue::from_static("close"));
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_util, ByteBudget, ExternalPolicy, FirstSeenFilter, Governor, Pacer, SloGuard,
+        REJECTION_HEADER,
+    };
+    use axum::{
+        http::{
+            header::{AUTHORIZATION, CONTENT_LENGTH, HOST, RETRY_AFTER},
+            Request,
+        },
+        response::IntoResponse,
+    };
+    use std::{
+        net::IpAddr,
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    fn upload(path: &str) -> Parts {
+        let mut parts = test_util::parts(path);
+        parts.headers.insert(CONTENT_LENGTH, HeaderValue::from(10));
+        parts
+    }
+
+    /// A codec passing limiter keys through unchanged, recording each one.
+    fn recording_codec() -> (
+        Arc<Mutex<Vec<String>>>,
+        impl Fn(&str) -> String + Clone + Send + Sync + 'static,
+    ) {
+        let keys = Arc::new(Mutex::new(Vec::new()));
+        let recorded = keys.clone();
+        let codec = move |key: &str| {
+            recorded.lock().unwrap().push(key.to_string());
+            key.to_string()
+        };
+        (keys, codec)
+    }
+
+    /// Returns whether the budgets are still untouched by requests of
+    /// `CLIENT`, by spending them in full.
+    fn budgets_untouched(bytes: &ByteBudget, governor: &Governor) -> bool {
+        bytes.bytes().charge(test_util::CLIENT, 10)
+            && governor.charge_budget("exports", test_util::CLIENT, 1)
+    }
+
+    #[tokio::test]
+    async fn first_sighting_runs_every_other_check() {
+        test_util::init_limiter().await;
+        let filter = || FirstSeenFilter::new(1_000, 0.01);
+        let governor = Governor::new().budget("once", 1, Duration::from_secs(60));
+        let config = test_util::config()
+            .first_seen_filter(filter())
+            .governor(governor)
+            .budget("/", "once");
+        // The first request skips the limiter but is charged to the budget.
+        assert!(check(&config, &test_util::parts("/")).await.is_ok());
+        assert!(check(&config, &test_util::parts("/")).await.is_err());
+
+        let config = test_util::config()
+            .first_seen_filter(filter())
+            .slo_guard(SloGuard::new(|| 0.0).floor(0.0));
+        assert!(check(&config, &test_util::parts("/")).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn budgets_are_charged_to_the_client_key() {
+        test_util::init_limiter().await;
+        let bytes = ByteBudget::new(10, Duration::from_secs(60));
+        let governor = Governor::new().budget("exports", 1, Duration::from_secs(60));
+        let config = test_util::config()
+            .byte_budget(bytes.clone())
+            .governor(governor.clone())
+            .budget("/export", "exports")
+            .key_namespace("api")
+            .key_codec(|key: &str| format!("encoded:{key}"));
+
+        assert!(check(&config, &upload("/export")).await.is_ok());
+        // Both budgets were charged to the plain client key, not to the
+        // namespaced and encoded limiter key.
+        assert!(!bytes.bytes().charge(test_util::CLIENT, 1));
+        assert!(!governor.charge_budget("exports", test_util::CLIENT, 1));
+    }
+
+    #[tokio::test]
+    async fn slo_denial_refunds_budgets() {
+        test_util::init_limiter().await;
+        let bytes = ByteBudget::new(10, Duration::from_secs(60));
+        let governor = Governor::new().budget("exports", 1, Duration::from_secs(60));
+        let config = test_util::config()
+            .byte_budget(bytes.clone())
+            .governor(governor.clone())
+            .budget("/export", "exports")
+            .slo_guard(SloGuard::new(|| 0.0).floor(0.0));
+
+        assert!(check(&config, &upload("/export")).await.is_err());
+        assert!(budgets_untouched(&bytes, &governor));
+    }
+
+    #[tokio::test]
+    async fn pacer_denial_refunds_budgets() {
+        test_util::init_limiter().await;
+        let bytes = ByteBudget::new(20, Duration::from_secs(60));
+        let governor = Governor::new().budget("exports", 2, Duration::from_secs(60));
+        let config = test_util::config()
+            .byte_budget(bytes.clone())
+            .governor(governor.clone())
+            .budget("/export", "exports")
+            .pacer(Pacer::new(Duration::from_secs(60), 0));
+
+        // The first request takes the only slot, the second finds the queue
+        // full.
+        assert!(check(&config, &upload("/export")).await.is_ok());
+        assert!(check(&config, &upload("/export")).await.is_err());
+        assert!(budgets_untouched(&bytes, &governor));
+    }
+
+    #[tokio::test]
+    async fn rejects_methods_not_allowed_on_route() {
+        test_util::init_limiter().await;
+        let governor = Governor::new().budget("once", 1, Duration::from_secs(60));
+        let config = test_util::config()
+            .governor(governor)
+            .budget("/upload", "once")
+            .allow_methods("/upload", [Method::POST]);
+
+        let mut parts = test_util::parts("/upload");
+        parts.method = Method::DELETE;
+        let response = check(&config, &parts).await.err().unwrap();
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(response.headers()[ALLOW], "POST");
+        assert_eq!(response.headers()[REJECTION_HEADER], "forbidden-method");
+
+        // The rejection charged nothing, so the budget still has room.
+        parts.method = Method::POST;
+        assert!(check(&config, &parts).await.is_ok());
+        assert!(check(&config, &test_util::parts("/other")).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn trace_and_connect_policies() {
+        test_util::init_limiter().await;
+        let governor = Governor::new().budget("once", 1, Duration::from_secs(60));
+        let config = test_util::config().governor(governor).budget("/", "once");
+
+        for (policy, status) in [
+            (MethodPolicy::Reject, StatusCode::METHOD_NOT_ALLOWED),
+            (MethodPolicy::Forbid, StatusCode::FORBIDDEN),
+        ] {
+            let config = config.clone().trace_connect(policy);
+            for method in [Method::TRACE, Method::CONNECT] {
+                let mut parts = test_util::parts("/");
+                parts.method = method;
+                let response = check(&config, &parts).await.err().unwrap();
+                assert_eq!(response.status(), status);
+                assert_eq!(response.headers()[REJECTION_HEADER], "forbidden-method");
+            }
+        }
+
+        let exempt = config.clone().trace_connect(MethodPolicy::Exempt);
+        let mut parts = test_util::parts("/");
+        parts.method = Method::TRACE;
+        for _ in 0..2 {
+            assert!(check(&exempt, &parts).await.is_ok());
+        }
+        // Other methods are limited as usual.
+        assert!(check(&exempt, &test_util::parts("/")).await.is_ok());
+        assert!(check(&exempt, &test_util::parts("/")).await.is_err());
+    }
+
+    #[test]
+    fn shed_responses_advise_retry() {
+        let config = test_util::config();
+        let load_shed = LoadShed::new(1).retry_after(Duration::from_secs(5));
+        let response = shed(&config, &load_shed, Version::HTTP_11, &HeaderMap::new());
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()[RETRY_AFTER], "5");
+        assert_eq!(response.headers()[REJECTION_HEADER], "overloaded");
+    }
+
+    #[tokio::test]
+    async fn separates_extension_methods() {
+        test_util::init_limiter().await;
+        let (keys, codec) = recording_codec();
+        let config = test_util::config()
+            .key_codec(codec)
+            .separate_extension_methods(true);
+
+        // Each extension method is limited under a key of its own.
+        for method in ["PROPFIND", "REPORT"] {
+            let mut parts = test_util::parts("/dav");
+            parts.method = Method::from_bytes(method.as_bytes()).unwrap();
+            assert!(check(&config, &parts).await.is_ok());
+        }
+        assert!(check(&config, &test_util::parts("/dav")).await.is_ok());
+        let keys = keys.lock().unwrap();
+        assert!(keys.contains(&format!("{}#PROPFIND", test_util::CLIENT)));
+        assert!(keys.contains(&format!("{}#REPORT", test_util::CLIENT)));
+        assert!(keys.contains(&test_util::CLIENT.to_string()));
+    }
+
+    #[tokio::test]
+    async fn closes_http1_connections_on_reject() {
+        test_util::init_limiter().await;
+        let governor = Governor::new();
+        governor.ban(test_util::CLIENT, Duration::from_secs(60), "abuse");
+        let config = test_util::config().governor(governor).close_on_reject(true);
+
+        let mut parts = test_util::parts("/");
+        let response = check(&config, &parts).await.err().unwrap();
+        assert_eq!(response.headers()[CONNECTION], "close");
+
+        parts.version = Version::HTTP_2;
+        let response = check(&config, &parts).await.err().unwrap();
+        assert!(!response.headers().contains_key(CONNECTION));
+
+        let config = config.close_on_reject(false);
+        let response = check(&config, &test_util::parts("/")).await.err().unwrap();
+        assert!(!response.headers().contains_key(CONNECTION));
+    }
+
+    #[tokio::test]
+    async fn charges_declared_body_bytes() {
+        test_util::init_limiter().await;
+        let bytes = ByteBudget::new(15, Duration::from_secs(60));
+        let config = test_util::config().byte_budget(bytes.clone());
+
+        assert!(check(&config, &upload("/upload")).await.is_ok());
+        // A request that would exceed the budget is denied without charge.
+        let response = check(&config, &upload("/upload")).await.err().unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        // Requests without a declared length are charged nothing.
+        assert!(check(&config, &test_util::parts("/upload")).await.is_ok());
+        assert!(bytes.bytes().charge(test_util::CLIENT, 5));
+        assert!(!bytes.bytes().charge(test_util::CLIENT, 1));
+    }
+
+    #[tokio::test]
+    async fn applies_runtime_lists() {
+        test_util::init_limiter().await;
+        let governor = Governor::new().budget("once", 1, Duration::from_secs(60));
+        let config = test_util::config()
+            .governor(governor.clone())
+            .budget("/", "once");
+        let client: IpAddr = test_util::CLIENT.parse().unwrap();
+
+        governor.denylist_add(client);
+        let response = check(&config, &test_util::parts("/")).await.err().unwrap();
+        assert_eq!(response.headers()[REJECTION_HEADER], "banned");
+
+        governor.allowlist_add(client);
+        for _ in 0..2 {
+            let admitted = check(&config, &test_util::parts("/")).await.ok().unwrap();
+            assert_eq!(admitted.tags.client_class, ClientClass::Allowlisted);
+        }
+    }
+
+    /// A config denying every request to `/`.
+    fn denying_config() -> GovernorConfig {
+        let governor = Governor::new().budget("none", 0, Duration::from_secs(60));
+        test_util::config().governor(governor).budget("/", "none")
+    }
+
+    #[tokio::test]
+    async fn builds_denials_with_on_denied() {
+        test_util::init_limiter().await;
+        let config = denying_config().on_denied(|info| {
+            let body = format!("Slow down on {}, {}", info.path, info.key);
+            (StatusCode::SERVICE_UNAVAILABLE, body).into_response()
+        });
+
+        let response = check(&config, &test_util::parts("/")).await.err().unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()[REJECTION_HEADER], "rate-limited");
+        assert_eq!(
+            body(response).await,
+            format!("Slow down on /, {}", test_util::CLIENT)
+        );
+    }
+
+    #[tokio::test]
+    async fn denies_with_configured_status() {
+        test_util::init_limiter().await;
+        let config = denying_config().denied_status(StatusCode::SERVICE_UNAVAILABLE);
+        let response = check(&config, &test_util::parts("/")).await.err().unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()[REJECTION_HEADER], "rate-limited");
+        assert_eq!(body(response).await, "Service Unavailable");
+
+        // Responses built by `on_denied` keep their own status.
+        let config = config.on_denied(|_| StatusCode::IM_A_TEAPOT.into_response());
+        let response = check(&config, &test_util::parts("/")).await.err().unwrap();
+        assert_eq!(response.status(), StatusCode::IM_A_TEAPOT);
+    }
+
+    #[tokio::test]
+    async fn passes_rejections_through_error_handler() {
+        test_util::init_limiter().await;
+        let config =
+            denying_config().error_handler(|error: &GovernorError, default: Response<Body>| {
+                match error {
+                    GovernorError::Denied(info) => {
+                        format!("Denied {} {}", info.method, info.path).into_response()
+                    }
+                    _ => default,
+                }
+            });
+
+        let response = check(&config, &test_util::parts("/")).await.err().unwrap();
+        // The rewritten response is still marked.
+        assert_eq!(response.headers()[REJECTION_HEADER], "rate-limited");
+        assert_eq!(body(response).await, "Denied GET /");
+
+        let mut parts = test_util::parts("/");
+        parts.method = Method::TRACE;
+        let config = config.trace_connect(MethodPolicy::Reject);
+        let response = check(&config, &parts).await.err().unwrap();
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+    }
+
+    #[tokio::test]
+    async fn handles_missing_client_ip() {
+        test_util::init_limiter().await;
+        let anonymous = || Request::get("/").body(()).unwrap().into_parts().0;
+
+        let config = GovernorConfig::new();
+        let response = check(&config, &anonymous()).await.err().unwrap();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(response.headers()[REJECTION_HEADER], "misconfigured");
+
+        let config = config.on_missing_ip(MissingIpPolicy::FailOpen);
+        assert!(check(&config, &anonymous()).await.is_ok());
+
+        // Without `ConnectInfo` either, `UseConnectInfo` fails closed.
+        let config = config.on_missing_ip(MissingIpPolicy::UseConnectInfo);
+        assert!(check(&config, &anonymous()).await.is_err());
+        let admitted = check(&config, &test_util::parts("/")).await.ok().unwrap();
+        assert_eq!(admitted.key, test_util::CLIENT);
+
+        let config = config.on_missing_ip(MissingIpPolicy::CustomResponse(Arc::new(|| {
+            StatusCode::SERVICE_UNAVAILABLE.into_response()
+        })));
+        let response = check(&config, &anonymous()).await.err().unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()[REJECTION_HEADER], "misconfigured");
+    }
+
+    #[tokio::test]
+    async fn shadow_mode_through_the_shared_handle() {
+        test_util::init_limiter().await;
+        let mut config = test_util::config().budget("/", "none");
+        let governor = config.handle().budget("none", 0, Duration::from_secs(60));
+        // Clones made afterwards share the attached handle.
+        let layer_config = config.clone();
+        assert!(check(&layer_config, &test_util::parts("/")).await.is_err());
+
+        governor.set_shadow_mode(true);
+        let admitted = check(&layer_config, &test_util::parts("/"))
+            .await
+            .ok()
+            .unwrap();
+        assert!(admitted.tags.shadow_hit);
+
+        governor.set_shadow_mode(false);
+        assert!(check(&config, &test_util::parts("/")).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn tags_client_class_and_rules() {
+        test_util::init_limiter().await;
+        let config = test_util::config().tier("premium");
+        let tags = check(&config, &test_util::parts("/"))
+            .await
+            .ok()
+            .unwrap()
+            .tags;
+        assert_eq!(tags.tier.as_deref(), Some("premium"));
+        assert_eq!(tags.client_class, ClientClass::Public);
+        assert_eq!(tags.rules, AppliedRules::GlobalAndRoute);
+        assert!(!tags.shadow_hit);
+
+        let private = test_util::parts_from("/", "10.0.0.1");
+        let tags = check(&config, &private).await.ok().unwrap().tags;
+        assert_eq!(tags.client_class, ClientClass::Private);
+
+        let route_only = config.clone().override_mode(true);
+        let tags = check(&route_only, &test_util::parts("/"))
+            .await
+            .ok()
+            .unwrap()
+            .tags;
+        assert_eq!(tags.rules, AppliedRules::RouteOnly);
+
+        let exempt = config.private_traffic(PrivateTraffic::Exempt);
+        let tags = check(&exempt, &private).await.ok().unwrap().tags;
+        assert_eq!(tags.client_class, ClientClass::Private);
+        assert_eq!(tags.rules, AppliedRules::Skipped);
+    }
+
+    #[tokio::test]
+    async fn normalizes_rule_paths_when_asked() {
+        test_util::init_limiter().await;
+        let governor = Governor::new().budget("exports", 1, Duration::from_secs(60));
+        let config = test_util::config()
+            .governor(governor)
+            .budget("/export", "exports");
+        // Unnormalized spellings of the path escape its budget.
+        assert!(check(&config, &test_util::parts("/export")).await.is_ok());
+        assert!(check(&config, &test_util::parts("//export/")).await.is_ok());
+
+        let config = config.normalize_path(true);
+        assert!(check(&config, &test_util::parts("/export/")).await.is_err());
+        assert!(check(&config, &test_util::parts("//export")).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn private_traffic_can_be_exempt() {
+        test_util::init_limiter().await;
+        let governor = Governor::new().budget("once", 1, Duration::from_secs(60));
+        let config = test_util::config()
+            .governor(governor)
+            .budget("/internal", "once")
+            .private_traffic(PrivateTraffic::Exempt);
+
+        for _ in 0..3 {
+            let parts = test_util::parts_from("/internal", "10.0.0.5");
+            assert!(check(&config, &parts).await.is_ok());
+        }
+        // Public clients are still limited.
+        assert!(check(&config, &test_util::parts("/internal")).await.is_ok());
+        assert!(check(&config, &test_util::parts("/internal"))
+            .await
+            .is_err());
+    }
+
+    async fn body(response: Response<Body>) -> String {
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn keys_by_extractor() {
+        test_util::init_limiter().await;
+        let config = test_util::config().key_extractor(|parts: &Parts| {
+            match parts.headers.get("x-user").map(HeaderValue::to_str) {
+                Some(Ok(user)) => Ok(format!("user:{user}")),
+                Some(Err(_)) => Err(KeyError::Invalid("user is not ASCII".into())),
+                None => Err(KeyError::Missing),
+            }
+        });
+
+        let mut parts = test_util::parts("/");
+        parts
+            .headers
+            .insert("x-user", HeaderValue::from_static("42"));
+        assert_eq!(check(&config, &parts).await.ok().unwrap().key, "user:42");
+
+        // Without a key the request is keyed by its client IP.
+        let admitted = check(&config, &test_util::parts("/")).await.ok().unwrap();
+        assert_eq!(admitted.key, test_util::CLIENT);
+
+        parts
+            .headers
+            .insert("x-user", HeaderValue::from_bytes(b"caf\xc3\xa9").unwrap());
+        let response = check(&config, &parts).await.err().unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(response.headers()[REJECTION_HEADER], "invalid-key");
+    }
+
+    #[tokio::test]
+    async fn merges_external_verdicts() {
+        test_util::init_limiter().await;
+        let governor = Governor::new().budget("once", 1, Duration::from_secs(60));
+        let policy = ExternalPolicy::new(|query: PolicyQuery| async move {
+            Some(match query.path.as_str() {
+                "/admin" => PolicyVerdict::Deny,
+                "/health" => PolicyVerdict::Exempt,
+                _ => PolicyVerdict::Allow,
+            })
+        });
+        let config = test_util::config()
+            .governor(governor)
+            .budget("/health", "once")
+            .budget("/", "once")
+            .external_policy(policy);
+
+        let response = check(&config, &test_util::parts("/admin"))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        for _ in 0..2 {
+            assert!(check(&config, &test_util::parts("/health")).await.is_ok());
+        }
+        assert!(check(&config, &test_util::parts("/")).await.is_ok());
+        assert!(check(&config, &test_util::parts("/")).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn anonymous_requests_use_their_config() {
+        test_util::init_limiter().await;
+        let governor = Governor::new().budget("once", 1, Duration::from_secs(60));
+        let anonymous = test_util::config()
+            .tier("anonymous")
+            .governor(governor)
+            .budget("/", "once");
+        let config = test_util::config().tier("user").anonymous(
+            |parts: &Parts| parts.headers.contains_key(AUTHORIZATION),
+            anonymous,
+        );
+
+        let mut authenticated = test_util::parts("/");
+        authenticated
+            .headers
+            .insert(AUTHORIZATION, HeaderValue::from_static("Bearer t"));
+        for _ in 0..2 {
+            let admitted = check(&config, &authenticated).await.ok().unwrap();
+            assert_eq!(admitted.tags.tier.as_deref(), Some("user"));
+        }
+
+        let admitted = check(&config, &test_util::parts("/")).await.ok().unwrap();
+        assert_eq!(admitted.tags.tier.as_deref(), Some("anonymous"));
+        assert!(check(&config, &test_util::parts("/")).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn route_extractors_by_longest_prefix() {
+        test_util::init_limiter().await;
+        let config = test_util::config()
+            .key_extractor(|_: &Parts| Ok("global".to_string()))
+            .key_extractor_for("/api", crate::ApiKeyExtractor::default())
+            .key_extractor_for("/api/admin", crate::CookieExtractor::new("admin"));
+
+        for (path, key) in [
+            ("/api/items", "api-key:abc"),
+            ("/api/admin/users", "admin:a1"),
+            ("/apix", "global"),
+            ("/other", "global"),
+        ] {
+            let mut parts = test_util::parts(path);
+            parts
+                .headers
+                .insert("x-api-key", HeaderValue::from_static("abc"));
+            parts
+                .headers
+                .insert("cookie", HeaderValue::from_static("admin=a1"));
+            let admitted = check(&config, &parts).await.ok().unwrap();
+            assert_eq!(admitted.key, key, "{path}");
+        }
+    }
+
+    #[tokio::test]
+    async fn ip_ban_holds_for_api_keys() {
+        test_util::init_limiter().await;
+        let governor = Governor::new();
+        governor.ban(test_util::CLIENT, Duration::from_secs(60), "abuse");
+        let config = test_util::config()
+            .governor(governor)
+            .key_extractor(crate::ApiKeyExtractor::default());
+
+        let mut parts = test_util::parts("/");
+        parts
+            .headers
+            .insert("x-api-key", HeaderValue::from_static("made-up"));
+        let response = check(&config, &parts).await.err().unwrap();
+        assert_eq!(response.headers()[REJECTION_HEADER], "banned");
+    }
+
+    #[tokio::test]
+    async fn address_ban_holds_under_an_ip_prefix() {
+        test_util::init_limiter().await;
+        let governor = Governor::new();
+        governor.ban(test_util::CLIENT, Duration::from_secs(60), "abuse");
+        let config = test_util::config()
+            .governor(governor)
+            .ipv4_prefix(24)
+            .ipv6_prefix(64);
+
+        let response = check(&config, &test_util::parts("/")).await.err().unwrap();
+        assert_eq!(response.headers()[REJECTION_HEADER], "banned");
+        // Other addresses in the prefix aren't covered by the address ban.
+        let neighbour = test_util::parts_from("/", "203.0.113.8");
+        assert!(check(&config, &neighbour).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn applies_per_request_flags() {
+        test_util::init_limiter().await;
+        let governor = Governor::new().budget("none", 0, Duration::from_secs(60));
+        let config = test_util::config()
+            .governor(governor.clone())
+            .budget("/", "none")
+            .flags_from(|parts: &Parts| GovernorFlags {
+                force_shadow: parts.headers.contains_key("x-canary"),
+                ..GovernorFlags::default()
+            });
+
+        let response = check(&config, &test_util::parts("/")).await.err().unwrap();
+        assert!(response.headers().contains_key(REJECTION_HEADER));
+
+        let mut canary = test_util::parts("/");
+        canary
+            .headers
+            .insert("x-canary", HeaderValue::from_static("1"));
+        assert!(check(&config, &canary).await.ok().unwrap().tags.shadow_hit);
+
+        // An extension takes precedence over `flags_from`.
+        let mut trusted = canary;
+        trusted.extensions.insert(GovernorFlags {
+            trusted: true,
+            ..GovernorFlags::default()
+        });
+        let admitted = check(&config, &trusted).await.ok().unwrap();
+        assert!(!admitted.tags.shadow_hit);
+        assert_eq!(admitted.tags.client_class, ClientClass::Trusted);
+
+        let mut quiet = test_util::parts("/");
+        quiet.extensions.insert(GovernorFlags {
+            skip_headers: true,
+            ..GovernorFlags::default()
+        });
+        let response = check(&config, &quiet).await.err().unwrap();
+        assert!(!response.headers().contains_key(REJECTION_HEADER));
+
+        // Bans still apply to trusted requests.
+        governor.ban(test_util::CLIENT, Duration::from_secs(60), "abuse");
+        assert!(check(&config, &trusted).await.is_err());
+    }
+
+    #[cfg(feature = "engine")]
+    #[tokio::test]
+    async fn engine_denial_advises_retry() {
+        use crate::{DenyCache, Engine, Rule};
+
+        test_util::init_limiter().await;
+        let config = test_util::config()
+            .engine(Engine::new(Rule::new(Duration::from_secs(30), 1)))
+            .deny_cache(DenyCache::new(Duration::from_secs(1)))
+            .error_format(ErrorFormat::Json);
+
+        assert!(check(&config, &test_util::parts("/")).await.is_ok());
+        // Denied by the engine, then straight from the deny cache.
+        for _ in 0..2 {
+            let response = check(&config, &test_util::parts("/")).await.err().unwrap();
+            assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+            assert_eq!(response.headers()[RETRY_AFTER], "30");
+            assert!(body(response).await.ends_with(",\"retry_after\":30}"));
+        }
+    }
+
+    #[tokio::test]
+    async fn budget_denial_omits_retry() {
+        test_util::init_limiter().await;
+        let governor = Governor::new().budget("exports", 1, Duration::from_secs(60));
+        let config = test_util::config()
+            .governor(governor)
+            .budget("/export", "exports")
+            .error_format(ErrorFormat::Json);
+
+        assert!(check(&config, &test_util::parts("/export")).await.is_ok());
+        let response = check(&config, &test_util::parts("/export"))
+            .await
+            .err()
+            .unwrap();
+        assert!(!response.headers().contains_key(RETRY_AFTER));
+        assert_eq!(
+            body(response).await,
+            "{\"error\":\"rate_limited\",\"message\":\"Too Many Requests\"}"
+        );
+    }
+
+    /// A key lookup answering after `delay`.
+    fn slow_lookup(delay: Duration) -> impl AsyncKeyExtractor {
+        move |_: &Parts| async move {
+            tokio::time::sleep(delay).await;
+            Ok("tenant:acme".to_string())
+        }
+    }
+
+    #[tokio::test]
+    async fn keys_by_async_lookup() {
+        test_util::init_limiter().await;
+        let config = test_util::config()
+            .async_key_extractor(slow_lookup(Duration::ZERO))
+            .key_timeout(Duration::from_secs(60));
+
+        let admitted = check(&config, &test_util::parts("/")).await.ok().unwrap();
+        assert_eq!(admitted.key, "tenant:acme");
+    }
+
+    #[tokio::test]
+    async fn async_lookup_times_out() {
+        test_util::init_limiter().await;
+        let config = test_util::config()
+            .async_key_extractor(slow_lookup(Duration::from_secs(60)))
+            .key_timeout(Duration::from_millis(10));
+
+        // Without a fallback key the request is keyed by its client IP.
+        let admitted = check(&config, &test_util::parts("/")).await.ok().unwrap();
+        assert_eq!(admitted.key, test_util::CLIENT);
+
+        let config = config.key_fallback("lookup-timeout");
+        let admitted = check(&config, &test_util::parts("/")).await.ok().unwrap();
+        assert_eq!(admitted.key, "lookup-timeout");
+    }
+
+    fn parts_for_host(host: &'static str, client: &str) -> Parts {
+        let mut parts = test_util::parts_from("/", client);
+        parts.headers.insert(HOST, HeaderValue::from_static(host));
+        parts
+    }
+
+    #[tokio::test]
+    async fn caps_tenants_in_aggregate() {
+        test_util::init_limiter().await;
+        let config = test_util::config()
+            .per_host(["a.example.com", "b.example.com"])
+            .tenant_cap(crate::TenantCap::new(2, Duration::from_secs(60)));
+
+        for client in ["203.0.113.1", "203.0.113.2"] {
+            assert!(check(&config, &parts_for_host("a.example.com", client))
+                .await
+                .is_ok());
+        }
+        let response = check(&config, &parts_for_host("a.example.com", "203.0.113.3"))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[REJECTION_HEADER], "tenant-quota");
+
+        assert!(
+            check(&config, &parts_for_host("b.example.com", "203.0.113.3"))
+                .await
+                .is_ok()
+        );
+    }
+
+    #[tokio::test]
+    async fn isolates_tenants() {
+        test_util::init_limiter().await;
+        let governor = Governor::new().budget("once", 1, Duration::from_secs(60));
+        let config = test_util::config()
+            .per_host(["a.example.com", "b.example.com"])
+            .governor(governor.clone())
+            .budget("/", "once");
+
+        let admitted = check(&config, &parts_for_host("a.example.com", test_util::CLIENT))
+            .await
+            .ok()
+            .unwrap();
+        assert_eq!(admitted.key, format!("a.example.com/{}", test_util::CLIENT));
+        assert_eq!(admitted.tags.tenant.as_deref(), Some("a.example.com"));
+        // The same client has a budget of its own in every tenant.
+        assert!(
+            check(&config, &parts_for_host("b.example.com", test_util::CLIENT))
+                .await
+                .is_ok()
+        );
+        assert!(
+            check(&config, &parts_for_host("a.example.com", test_util::CLIENT))
+                .await
+                .is_err()
+        );
+
+        // A ban of the plain key holds in every tenant.
+        governor.ban("198.51.100.1", Duration::from_secs(60), "abuse");
+        let response = check(&config, &parts_for_host("b.example.com", "198.51.100.1"))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(response.headers()[REJECTION_HEADER], "banned");
+    }
+
+    #[tokio::test]
+    async fn namespaces_limiter_keys() {
+        test_util::init_limiter().await;
+        let (keys, codec) = recording_codec();
+        let premium = test_util::config()
+            .key_codec(codec.clone())
+            .key_namespace("premium");
+        let free = test_util::config().key_codec(codec).key_namespace("free");
+
+        // Each layer limits in buckets of its own.
+        assert!(check(&premium, &test_util::parts("/upload")).await.is_ok());
+        assert!(check(&free, &test_util::parts("/upload")).await.is_ok());
+        let keys = keys.lock().unwrap();
+        assert_eq!(
+            *keys,
+            [
+                format!("premium:{}", test_util::CLIENT),
+                format!("free:{}", test_util::CLIENT),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn tags_key_source() {
+        test_util::init_limiter().await;
+        let config = test_util::config().key_extractor(
+            crate::KeyChain::new().then("api-key", crate::ApiKeyExtractor::default()),
+        );
+
+        let mut parts = test_util::parts("/");
+        parts
+            .headers
+            .insert("x-api-key", HeaderValue::from_static("abc"));
+        let admitted = check(&config, &parts).await.ok().unwrap();
+        assert_eq!(admitted.key, "api-key:abc");
+        assert_eq!(admitted.tags.key_source.as_deref(), Some("api-key"));
+
+        let admitted = check(&config, &test_util::parts("/")).await.ok().unwrap();
+        assert_eq!(admitted.key, test_util::CLIENT);
+        assert_eq!(admitted.tags.key_source, None);
+    }
+}
@@ -0,0 +1,181 @@
+/* src/codec.rs */
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::{fmt, sync::Arc};
+
+/// Encodes a client key before it is handed to the rate-limit store.
+///
+/// The store only sees the encoded form, so a codec can add a namespace to
+/// keep several applications sharing one store apart, or hash keys to keep
+/// them short and uniform in size. To hash keys, prefer [`SaltedHashCodec`]:
+/// its digests can neither be reversed nor made to collide without the salt.
+///
+/// Any `Fn(&str) -> String` closure is also a `KeyCodec`.
+pub trait KeyCodec: Send + Sync + 'static {
+    /// Returns the key under which `key` is stored.
+    fn encode(&self, key: &str) -> String;
+}
+
+impl<F> KeyCodec for F
+where
+    F: Fn(&str) -> String + Send + Sync + 'static,
+{
+    fn encode(&self, key: &str) -> String {
+        self(key)
+    }
+}
+
+/// Prepends a fixed prefix to every key, e.g. `"billing:"`.
+#[derive(Debug, Clone)]
+pub struct PrefixCodec {
+    prefix: String,
+}
+
+impl PrefixCodec {
+    /// Creates a codec that prepends `prefix` to every key.
+    pub fn new(prefix: impl Into<String>) -> Self {
+        Self {
+            prefix: prefix.into(),
+        }
+    }
+}
+
+impl KeyCodec for PrefixCodec {
+    fn encode(&self, key: &str) -> String {
+        format!("{}{}", self.prefix, key)
+    }
+}
+
+/// Replaces every key by a fixed-width 64-bit FNV-1a hash, optionally prefixed.
+///
+/// The hash is stable across processes and Rust versions, so replicas that
+/// share a store agree on the encoded keys. It is not a cryptographic hash:
+/// anyone can compute it, so a client that controls its key (an API key,
+/// cookie or header value) can search for one that collides with another
+/// client's and share or exhaust that client's limit. Digests of IPv4 keys
+/// can also be reversed by hashing the whole address space. Use
+/// [`SaltedHashCodec`] unless keys are trusted and need not stay private.
+#[derive(Debug, Clone, Default)]
+pub struct HashedCodec {
+    prefix: String,
+}
+
+impl HashedCodec {
+    /// Creates a hashing codec without a prefix.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets a prefix placed in front of the hex digest.
+    pub fn prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+}
+
+impl KeyCodec for HashedCodec {
+    fn encode(&self, key: &str) -> String {
+        format!("{}{:016x}", self.prefix, fnv1a(key))
+    }
+}
+
+/// Replaces every key by its HMAC-SHA256 under a secret salt.
+///
+/// The recommended way to hash keys. Raw client IPs and identifiers never
+/// reach the rate-limit store (other per-client state, such as bans and
+/// budgets, still keeps them), without the salt the digests can't be
+/// reversed by hashing the small IPv4 address space, and clients can't
+/// craft keys that collide with someone else's. Use the same salt on every
+/// replica sharing a store. [`GovernorConfig::hash_keys`](crate::GovernorConfig::hash_keys)
+/// is a shorthand.
+#[derive(Clone)]
+pub struct SaltedHashCodec {
+    salt: Arc<[u8]>,
+}
+
+impl fmt::Debug for SaltedHashCodec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SaltedHashCodec").finish_non_exhaustive()
+    }
+}
+
+impl SaltedHashCodec {
+    /// Creates a codec hashing keys under `salt`.
+    pub fn new(salt: impl AsRef<[u8]>) -> Self {
+        Self {
+            salt: salt.as_ref().into(),
+        }
+    }
+}
+
+impl KeyCodec for SaltedHashCodec {
+    fn encode(&self, key: &str) -> String {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.salt).expect("HMAC accepts keys of any length");
+        mac.update(key.as_bytes());
+        mac.finalize()
+            .into_bytes()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect()
+    }
+}
+
+/// 64-bit FNV-1a, used wherever keys need a stable, compact fingerprint.
+pub(crate) fn fnv1a(key: &str) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in key.bytes() {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefixes_keys() {
+        assert_eq!(
+            PrefixCodec::new("billing:").encode("192.0.2.1"),
+            "billing:192.0.2.1"
+        );
+    }
+
+    #[test]
+    fn hashes_with_fnv1a() {
+        // Reference values of 64-bit FNV-1a.
+        assert_eq!(fnv1a(""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a("a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(
+            HashedCodec::new().prefix("ip:").encode("a"),
+            "ip:af63dc4c8601ec8c"
+        );
+    }
+
+    #[test]
+    fn hashes_with_hmac_sha256() {
+        // RFC 4231, test case 2.
+        let codec = SaltedHashCodec::new("Jefe");
+        assert_eq!(
+            codec.encode("what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        assert_ne!(
+            SaltedHashCodec::new("other").encode("192.0.2.1"),
+            codec.encode("192.0.2.1")
+        );
+    }
+
+    #[test]
+    fn hash_keys_installs_the_salted_codec() {
+        let config = crate::GovernorConfig::new().hash_keys("Jefe");
+        let codec = config.key_codec.unwrap();
+        assert_eq!(
+            codec.encode("192.0.2.1"),
+            SaltedHashCodec::new("Jefe").encode("192.0.2.1")
+        );
+    }
+}
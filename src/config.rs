@@ -1,9 +1,30 @@
 /* src/config.rs */
 
+use crate::{
+    AbuseReports, AsyncKeyExtractor, BanResponse, ByteBudget, Criticality, DecisionLog,
+    DeniedHandler, DeniedInfo, DenyCache, ErrorFormat, ErrorTemplates, ExternalPolicy,
+    FirstSeenFilter, FlagsResolver, Governor, GovernorErrorHandler, GovernorFlags, HostTenant,
+    KeyCodec, KeyExtractor, KeyLevel, LoadShed, MatchSource, MethodPolicy, MissingIpPolicy, Pacer,
+    PrivateTraffic, RetryPolicy, RuleMatcher, SaltedHashCodec, SeenTracker, ServiceAuth, SloGuard,
+    StreamCutoff, Tarpit, TenantCap, TenantResolver, UsageLog,
+};
+use axum::{
+    body::Body,
+    http::{request::Parts, Method, Response, StatusCode},
+};
+use lazy_limit::HttpMethod;
+use std::{collections::HashMap, fmt, sync::Arc, time::Duration};
+
+/// A function mapping request methods to `lazy-limit` methods.
+pub type MethodMapper = Arc<dyn Fn(&Method) -> HttpMethod + Send + Sync>;
+
+/// A function telling whether a request carries valid credentials.
+pub type AuthPredicate = Arc<dyn Fn(&Parts) -> bool + Send + Sync>;
+
 /// Configuration for `GovernorLayer`.
 ///
 /// This struct allows you to customize the behavior of the rate-limiting middleware.
-#[derive(Debug, Clone, Default)]
+#[derive(Clone, Default)]
 pub struct GovernorConfig {
     /// If `true`, the middleware will use `lazy_limit::limit_override!`,
     /// which ignores the global rate limit and only applies route-specific rules.
@@ -11,6 +32,315 @@ pub struct GovernorConfig {
     /// If `false` (default), it uses `lazy_limit::limit!`, which enforces the stricter
     /// of the global and route-specific rules.
     pub override_mode: bool,
+
+    /// Optional filter that lets the first request of a never-seen key skip
+    /// the limiter, without recording it in the `lazy-limit` store. Budgets
+    /// and the other checks still apply. See [`FirstSeenFilter`].
+    pub first_seen_filter: Option<FirstSeenFilter>,
+
+    /// Optional codec applied to every key before it reaches the rate-limit
+    /// store. See [`KeyCodec`].
+    pub key_codec: Option<Arc<dyn KeyCodec>>,
+
+    /// Optional ring buffer receiving every decision made by this layer.
+    pub decision_log: Option<DecisionLog>,
+
+    /// Optional runtime control handle, e.g. for banning clients.
+    pub governor: Option<Governor>,
+
+    /// Response sent to banned clients. See [`BanResponse`].
+    pub ban_response: BanResponse,
+
+    /// Optional replacement for [`map_method`](crate::map_method) when matching
+    /// method-specific rules.
+    pub method_mapper: Option<MethodMapper>,
+
+    /// If `true`, every extension method (anything `map_method` maps to
+    /// `HttpMethod::OTHER`) gets its own bucket per client instead of sharing
+    /// one. Defaults to `false`.
+    pub separate_extension_methods: bool,
+
+    /// If `true`, requests whose `X-Real-IP`, `X-Forwarded-For` or `Forwarded`
+    /// headers are malformed or conflicting are rejected with
+    /// `400 Bad Request` instead of silently falling back. Defaults to `false`.
+    pub strict_forwarded_headers: bool,
+
+    /// Number of trusted proxies in front of the server, limiting which
+    /// forwarded-IP entries strict validation checks. See
+    /// [`trusted_hops`](Self::trusted_hops).
+    pub trusted_hops: Option<usize>,
+
+    /// Optional policy exempting retries of internal clients. See
+    /// [`RetryPolicy`].
+    pub retry_policy: Option<RetryPolicy>,
+
+    /// Optional local cache short-circuiting repeated denials. See
+    /// [`DenyCache`].
+    pub deny_cache: Option<DenyCache>,
+
+    /// If `true`, rejections over HTTP/1.x carry `Connection: close`, so the
+    /// server closes the connection instead of draining an unread request
+    /// body. Defaults to `false`, keeping the connection alive.
+    pub close_on_reject: bool,
+
+    /// If `true`, IPv4-mapped IPv6 addresses (`::ffff:1.2.3.4`) are keyed as
+    /// is instead of being unwrapped to plain IPv4. Defaults to `false`.
+    pub preserve_ipv4_mapped: bool,
+
+    /// If set, IPv6 clients are keyed by their network prefix of this length
+    /// (e.g. `64`) instead of the full address.
+    pub ipv6_prefix: Option<u8>,
+
+    /// If set, IPv4 clients are keyed by their network prefix of this length
+    /// (e.g. `24`) instead of the full address.
+    pub ipv4_prefix: Option<u8>,
+
+    /// How requests from loopback, link-local and private address space are
+    /// limited, judged by both the client address and the connection peer.
+    /// Defaults to [`PrivateTraffic::Limit`].
+    pub private_traffic: PrivateTraffic,
+
+    /// Optional label reported as the tier in [`GovernorTags`](crate::GovernorTags).
+    /// Route groups default it to the group name.
+    pub tier: Option<String>,
+
+    /// If `true`, repeated and trailing slashes are removed from the path
+    /// before it is matched against rules. Defaults to `false`.
+    pub normalize_path: bool,
+
+    /// How `TRACE` and `CONNECT` requests are treated. Defaults to
+    /// [`MethodPolicy::Limit`].
+    pub trace_connect: MethodPolicy,
+
+    /// Optional per-key budget of request body bytes. See [`ByteBudget`].
+    pub byte_budget: Option<ByteBudget>,
+
+    /// Optional per-client budget of response body bytes. See
+    /// [`response_budget`](Self::response_budget).
+    pub response_budget: Option<ByteBudget>,
+
+    /// What happens to a response that exhausts the `response_budget`
+    /// mid-stream. Defaults to [`StreamCutoff::Finish`].
+    pub stream_cutoff: StreamCutoff,
+
+    /// Optional collector of periodic abuse summaries. See [`AbuseReports`].
+    pub abuse_reports: Option<AbuseReports>,
+
+    /// Optional per-key usage aggregation for export. See [`UsageLog`].
+    pub usage_log: Option<UsageLog>,
+
+    /// Optional verifier of signed service tokens. See [`ServiceAuth`].
+    pub service_auth: Option<ServiceAuth>,
+
+    /// Optional tracker of first-seen and last-seen timestamps per client.
+    /// See [`SeenTracker`].
+    pub seen_tracker: Option<SeenTracker>,
+
+    /// Methods allowed per path. Requests to a listed path with any other
+    /// method are rejected with `405 Method Not Allowed`.
+    pub allowed_methods: HashMap<String, Vec<Method>>,
+
+    /// Optional artificial latency for abusive clients. See [`Tarpit`].
+    pub tarpit: Option<Tarpit>,
+
+    /// Optional builder of the response for rate-limited requests, replacing
+    /// the plain `429 Too Many Requests`.
+    pub on_denied: Option<DeniedHandler>,
+
+    /// Optional per-key pacing of admitted requests. See [`Pacer`].
+    pub pacer: Option<Pacer>,
+
+    /// The body format of error responses produced by the governor. Defaults
+    /// to [`ErrorFormat::Text`].
+    pub error_format: ErrorFormat,
+
+    /// Named `Governor` budgets per path. See [`budget`](Self::budget).
+    pub budgets: HashMap<String, String>,
+
+    /// Status of rate-limited responses. Defaults to
+    /// `429 Too Many Requests` if unset.
+    pub denied_status: Option<StatusCode>,
+
+    /// Optional handler customizing every rejection. See
+    /// [`GovernorErrorHandler`].
+    pub error_handler: Option<Arc<dyn GovernorErrorHandler>>,
+
+    /// Optional server-wide cap on requests in flight. See [`LoadShed`].
+    pub load_shed: Option<LoadShed>,
+
+    /// Templates replacing the built-in error bodies. See
+    /// [`ErrorTemplates`].
+    pub error_templates: ErrorTemplates,
+
+    /// What happens to requests without a `RealIp` extension. Defaults to
+    /// [`MissingIpPolicy::FailClosed`].
+    pub on_missing_ip: MissingIpPolicy,
+
+    /// Optional extractor replacing the client IP as rate-limit key. See
+    /// [`KeyExtractor`].
+    pub key_extractor: Option<Arc<dyn KeyExtractor>>,
+
+    /// Optional matcher mapping request paths onto rule paths. See
+    /// [`RuleMatcher`].
+    pub rule_matcher: Option<Arc<dyn RuleMatcher>>,
+
+    /// Optional function deriving [`GovernorFlags`] for requests without a
+    /// `GovernorFlags` extension.
+    pub flags_from: Option<FlagsResolver>,
+
+    /// Optional template composing the limiter key, e.g.
+    /// `"{ip}:{method}:{path}"`. See [`key_template`](Self::key_template).
+    pub key_template: Option<String>,
+
+    /// Optional asynchronous key extractor, taking precedence over
+    /// `key_extractor`. See [`AsyncKeyExtractor`].
+    pub async_key_extractor: Option<Arc<dyn AsyncKeyExtractor>>,
+
+    /// How long `async_key_extractor` may take before the lookup is
+    /// cancelled. Defaults to `None`, waiting indefinitely.
+    pub key_timeout: Option<Duration>,
+
+    /// The key used when `async_key_extractor` times out. Defaults to `None`,
+    /// falling back to the client IP.
+    pub key_fallback: Option<String>,
+
+    /// Optional guard throttling non-critical routes as an SLO error budget
+    /// burns.
+    pub slo_guard: Option<SloGuard>,
+
+    /// Criticality tiers of routes, keyed by rule path. Routes without an
+    /// entry are `Criticality::Normal`.
+    pub criticality: HashMap<String, Criticality>,
+
+    /// Which path of a request rules are matched against. Defaults to
+    /// [`MatchSource::CurrentUri`].
+    pub match_on: MatchSource,
+
+    /// Optional resolver isolating requests by tenant. See
+    /// [`TenantResolver`].
+    pub tenant_resolver: Option<Arc<dyn TenantResolver>>,
+
+    /// Optional aggregate cap on the requests of each tenant.
+    pub tenant_cap: Option<TenantCap>,
+
+    /// Optional external policy service merged with the local rules.
+    pub external_policy: Option<ExternalPolicy>,
+
+    /// Optional predicate and configuration for anonymous requests. See
+    /// [`anonymous`](Self::anonymous).
+    pub anonymous: Option<(AuthPredicate, Arc<GovernorConfig>)>,
+
+    /// Additional keys every request is limited under, in order. See
+    /// [`KeyLevel`].
+    pub key_levels: Vec<KeyLevel>,
+
+    /// Key extractors for path prefixes, overriding `key_extractor` and
+    /// `async_key_extractor`. The longest matching prefix wins.
+    pub route_extractors: Vec<(String, Arc<dyn KeyExtractor>)>,
+
+    /// Optional namespace prefixed to the limiter keys of this layer. See
+    /// [`key_namespace`](Self::key_namespace).
+    pub key_namespace: Option<String>,
+
+    /// Whether budget charges are refunded when the handler panics. See
+    /// [`refund_on_panic`](Self::refund_on_panic).
+    pub refund_on_panic: bool,
+
+    /// Whether the client's `User-Agent` family is part of the limiter key.
+    /// See [`user_agent_in_key`](Self::user_agent_in_key).
+    pub user_agent_in_key: bool,
+
+    /// Optional built-in engine used instead of `lazy-limit`. See
+    /// [`Engine`](crate::Engine).
+    #[cfg(feature = "engine")]
+    pub engine: Option<crate::Engine>,
+
+    /// Built-in engines used instead of `engine` for single tenants, keyed
+    /// by tenant.
+    #[cfg(feature = "engine")]
+    pub tenant_engines: HashMap<String, crate::Engine>,
+}
+
+impl fmt::Debug for GovernorConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = f.debug_struct("GovernorConfig");
+        debug
+            .field("override_mode", &self.override_mode)
+            .field("first_seen_filter", &self.first_seen_filter)
+            .field("key_codec", &self.key_codec.is_some())
+            .field("decision_log", &self.decision_log)
+            .field("governor", &self.governor)
+            .field("ban_response", &self.ban_response)
+            .field("method_mapper", &self.method_mapper.is_some())
+            .field(
+                "separate_extension_methods",
+                &self.separate_extension_methods,
+            )
+            .field("strict_forwarded_headers", &self.strict_forwarded_headers)
+            .field("trusted_hops", &self.trusted_hops)
+            .field("retry_policy", &self.retry_policy)
+            .field("deny_cache", &self.deny_cache)
+            .field("close_on_reject", &self.close_on_reject)
+            .field("preserve_ipv4_mapped", &self.preserve_ipv4_mapped)
+            .field("ipv6_prefix", &self.ipv6_prefix)
+            .field("ipv4_prefix", &self.ipv4_prefix)
+            .field("private_traffic", &self.private_traffic)
+            .field("tier", &self.tier)
+            .field("normalize_path", &self.normalize_path)
+            .field("trace_connect", &self.trace_connect)
+            .field("byte_budget", &self.byte_budget)
+            .field("response_budget", &self.response_budget)
+            .field("stream_cutoff", &self.stream_cutoff)
+            .field("abuse_reports", &self.abuse_reports)
+            .field("usage_log", &self.usage_log)
+            .field("service_auth", &self.service_auth)
+            .field("seen_tracker", &self.seen_tracker)
+            .field("allowed_methods", &self.allowed_methods)
+            .field("tarpit", &self.tarpit)
+            .field("on_denied", &self.on_denied.is_some())
+            .field("pacer", &self.pacer)
+            .field("error_format", &self.error_format)
+            .field("budgets", &self.budgets)
+            .field("denied_status", &self.denied_status)
+            .field("error_handler", &self.error_handler.is_some())
+            .field("load_shed", &self.load_shed)
+            .field("error_templates", &self.error_templates)
+            .field("on_missing_ip", &self.on_missing_ip)
+            .field("key_extractor", &self.key_extractor.is_some())
+            .field("rule_matcher", &self.rule_matcher.is_some())
+            .field("flags_from", &self.flags_from.is_some())
+            .field("key_template", &self.key_template)
+            .field("async_key_extractor", &self.async_key_extractor.is_some())
+            .field("key_timeout", &self.key_timeout)
+            .field("key_fallback", &self.key_fallback)
+            .field("slo_guard", &self.slo_guard)
+            .field("criticality", &self.criticality)
+            .field("match_on", &self.match_on)
+            .field("tenant_resolver", &self.tenant_resolver.is_some())
+            .field("tenant_cap", &self.tenant_cap)
+            .field("external_policy", &self.external_policy)
+            .field(
+                "anonymous",
+                &self.anonymous.as_ref().map(|(_, config)| config),
+            )
+            .field("key_levels", &self.key_levels)
+            .field(
+                "route_extractors",
+                &self
+                    .route_extractors
+                    .iter()
+                    .map(|(prefix, _)| prefix)
+                    .collect::<Vec<_>>(),
+            )
+            .field("key_namespace", &self.key_namespace)
+            .field("refund_on_panic", &self.refund_on_panic)
+            .field("user_agent_in_key", &self.user_agent_in_key);
+        #[cfg(feature = "engine")]
+        debug
+            .field("engine", &self.engine)
+            .field("tenant_engines", &self.tenant_engines);
+        debug.finish()
+    }
 }
 
 impl GovernorConfig {
@@ -27,4 +357,642 @@ impl GovernorConfig {
         self.override_mode = override_mode;
         self
     }
+
+    /// Skips the rate-limit store for the first request of each new key.
+    ///
+    /// Only the limiter is skipped: key levels, budgets, the SLO guard, the
+    /// tenant cap and the pacer still apply to that request.
+    ///
+    /// Intended for layers guarding generous rules, where one extra request per
+    /// client is harmless but one-hit scanners would otherwise churn memory.
+    pub fn first_seen_filter(mut self, filter: FirstSeenFilter) -> Self {
+        self.first_seen_filter = Some(filter);
+        self
+    }
+
+    /// Sets the codec used to encode limiter keys before they reach the
+    /// rate-limit store. Other per-client state keeps the client key, see
+    /// [`hash_keys`](Self::hash_keys).
+    ///
+    /// Use a `PrefixCodec` to namespace keys or a `SaltedHashCodec` (see
+    /// [`hash_keys`](Self::hash_keys)) to hash them; any `Fn(&str) -> String`
+    /// closure works as well. `HashedCodec` is only suitable for trusted keys,
+    /// as clients can craft keys that collide under it.
+    pub fn key_codec(mut self, codec: impl KeyCodec) -> Self {
+        self.key_codec = Some(Arc::new(codec));
+        self
+    }
+
+    /// Records every decision of this layer in `log`.
+    ///
+    /// Keep a clone of the log to query recent decisions later.
+    pub fn decision_log(mut self, log: DecisionLog) -> Self {
+        self.decision_log = Some(log);
+        self
+    }
+
+    /// Returns the runtime control handle of this configuration.
+    ///
+    /// Attaches a new `Governor` first if none is attached yet. Every layer
+    /// built from this configuration (or a clone of it) afterwards shares the
+    /// returned handle, so e.g. shadow mode and bans can be toggled for all of
+    /// them at once.
+    pub fn handle(&mut self) -> Governor {
+        self.governor.get_or_insert_with(Governor::new).clone()
+    }
+
+    /// Attaches a runtime control handle to this layer.
+    ///
+    /// Bans placed through the handle take effect in every layer it is
+    /// attached to.
+    pub fn governor(mut self, governor: Governor) -> Self {
+        self.governor = Some(governor);
+        self
+    }
+
+    /// Sets the response sent to banned clients.
+    pub fn ban_response(mut self, response: BanResponse) -> Self {
+        self.ban_response = response;
+        self
+    }
+
+    /// Overrides how request methods are mapped to `lazy-limit` methods.
+    ///
+    /// Useful to map extension methods (e.g. WebDAV's `PROPFIND`) onto a
+    /// standard method so they share its rules.
+    pub fn method_mapper<F>(mut self, mapper: F) -> Self
+    where
+        F: Fn(&Method) -> HttpMethod + Send + Sync + 'static,
+    {
+        self.method_mapper = Some(Arc::new(mapper));
+        self
+    }
+
+    /// Gives each extension method its own bucket per client.
+    ///
+    /// By default all extension methods share a single bucket, so a burst of
+    /// `PROPFIND` also throttles `MKCOL`.
+    pub fn separate_extension_methods(mut self, separate: bool) -> Self {
+        self.separate_extension_methods = separate;
+        self
+    }
+
+    /// Rejects requests with spoof-prone forwarded-IP headers.
+    ///
+    /// Closes a quota evasion avenue where clients send garbage or several
+    /// differing addresses, hoping the IP extraction falls back to something
+    /// they control. Set [`trusted_hops`](Self::trusted_hops) to only check
+    /// the entries your own proxies appended.
+    pub fn strict_forwarded_headers(mut self, strict: bool) -> Self {
+        self.strict_forwarded_headers = strict;
+        self
+    }
+
+    /// Sets the number of trusted reverse proxies in front of the server.
+    ///
+    /// Each proxy appends one entry to `X-Forwarded-For` and `Forwarded`, so
+    /// only the last `hops` entries are trustworthy; earlier ones come from
+    /// the client. With [`strict_forwarded_headers`](Self::strict_forwarded_headers),
+    /// only those entries are checked for malformed values and for
+    /// `X-Forwarded-For` and `Forwarded` disagreeing, so a client's junk in
+    /// the untrusted part doesn't get its request rejected. By default every
+    /// entry is checked.
+    pub fn trusted_hops(mut self, hops: usize) -> Self {
+        self.trusted_hops = Some(hops);
+        self
+    }
+
+    /// Sets how retries identified by a request header are charged.
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = Some(policy);
+        self
+    }
+
+    /// Caches denials so repeated over-limit requests skip the limiter.
+    pub fn deny_cache(mut self, cache: DenyCache) -> Self {
+        self.deny_cache = Some(cache);
+        self
+    }
+
+    /// Closes the connection after rejecting a request.
+    ///
+    /// The middleware never reads the body of a rejected request. With this
+    /// enabled, a large upload is not drained to keep the connection alive
+    /// either, so it stops consuming bandwidth once the decision is "deny".
+    pub fn close_on_reject(mut self, close: bool) -> Self {
+        self.close_on_reject = close;
+        self
+    }
+
+    /// Keeps IPv4-mapped IPv6 addresses distinct from plain IPv4.
+    ///
+    /// By default `::ffff:1.2.3.4` and `1.2.3.4` share one bucket.
+    pub fn preserve_ipv4_mapped(mut self, preserve: bool) -> Self {
+        self.preserve_ipv4_mapped = preserve;
+        self
+    }
+
+    /// Keys IPv6 clients by their network prefix of `prefix` bits.
+    ///
+    /// A single IPv6 client usually controls a whole `/64`, so per-address
+    /// limits are easy to evade. Values of 128 or more key by full address.
+    pub fn ipv6_prefix(mut self, prefix: u8) -> Self {
+        self.ipv6_prefix = Some(prefix);
+        self
+    }
+
+    /// Keys IPv4 clients by their network prefix, e.g. `24` for a `/24`.
+    ///
+    /// Useful against clients rotating through a small provider block. Keys
+    /// look like `203.0.113.0/24`; values of 32 or more key by full address.
+    /// IPv4-mapped IPv6 addresses are grouped the same way unless
+    /// `preserve_ipv4_mapped` is set.
+    pub fn ipv4_prefix(mut self, prefix: u8) -> Self {
+        self.ipv4_prefix = Some(prefix);
+        self
+    }
+
+    /// Sets how requests from private address space are limited.
+    ///
+    /// Lets intra-cluster traffic and health checks bypass internet-facing
+    /// defaults without maintaining an allowlist. Only requests whose client
+    /// address and connection peer are both private qualify, so a forged
+    /// forwarding header can't claim a private address; this needs
+    /// `ConnectInfo<SocketAddr>`. See [`PrivateTraffic`].
+    pub fn private_traffic(mut self, policy: PrivateTraffic) -> Self {
+        self.private_traffic = policy;
+        self
+    }
+
+    /// Labels requests admitted by this layer with `tier`, e.g. `"premium"`.
+    pub fn tier(mut self, tier: impl Into<String>) -> Self {
+        self.tier = Some(tier.into());
+        self
+    }
+
+    /// Normalizes paths before matching them against rules.
+    ///
+    /// `NormalizePathLayer` only affects the governor if it runs first, which
+    /// requires wrapping the whole router rather than `Router::layer`. With
+    /// this enabled, `/api/special/` and `/api//special` hit the rules of
+    /// `/api/special` regardless of layer ordering.
+    pub fn normalize_path(mut self, normalize: bool) -> Self {
+        self.normalize_path = normalize;
+        self
+    }
+
+    /// Sets how `TRACE` and `CONNECT` requests are treated.
+    ///
+    /// Rejecting them keeps probes for these methods from reaching the
+    /// router at all.
+    pub fn trace_connect(mut self, policy: MethodPolicy) -> Self {
+        self.trace_connect = policy;
+        self
+    }
+
+    /// Limits the request body bytes each client may send per window.
+    ///
+    /// Like the response budget and named budgets, it is charged to the
+    /// client key (the extracted key or client IP, prefixed with the tenant),
+    /// not to the limiter key shaped by `key_template`, `key_namespace` and
+    /// the `KeyCodec`.
+    pub fn byte_budget(mut self, budget: ByteBudget) -> Self {
+        self.byte_budget = Some(budget);
+        self
+    }
+
+    /// Limits the response body bytes each client may receive per window.
+    ///
+    /// Bytes are charged as the response streams. Clients that used up their
+    /// budget are denied until the window resets; `stream_cutoff` decides
+    /// whether a response that crosses the limit is finished or aborted.
+    pub fn response_budget(mut self, budget: ByteBudget) -> Self {
+        self.response_budget = Some(budget);
+        self
+    }
+
+    /// Sets what happens to a response that exhausts the `response_budget`.
+    pub fn stream_cutoff(mut self, cutoff: StreamCutoff) -> Self {
+        self.stream_cutoff = cutoff;
+        self
+    }
+
+    /// Tallies sampled decisions of this layer into `reports`.
+    ///
+    /// Keep a clone of the collector to produce the reports.
+    pub fn abuse_reports(mut self, reports: AbuseReports) -> Self {
+        self.abuse_reports = Some(reports);
+        self
+    }
+
+    /// Counts the allowed and rejected requests of each key in `log`.
+    ///
+    /// Keep a clone of the log to export the counts.
+    pub fn usage_log(mut self, log: UsageLog) -> Self {
+        self.usage_log = Some(log);
+        self
+    }
+
+    /// Treats requests with a valid signed service token as service traffic.
+    pub fn service_auth(mut self, auth: ServiceAuth) -> Self {
+        self.service_auth = Some(auth);
+        self
+    }
+
+    /// Tracks when each client was first and last seen in `tracker`.
+    ///
+    /// Keep a clone of the tracker to look up clients later.
+    pub fn seen_tracker(mut self, tracker: SeenTracker) -> Self {
+        self.seen_tracker = Some(tracker);
+        self
+    }
+
+    /// Allows only `methods` on `path`, matched exactly like route rules.
+    ///
+    /// Other methods get `405 Method Not Allowed` with a correct `Allow`
+    /// header before any limit is charged, so the limiter doubles as a method
+    /// firewall in front of catch-all handlers. Allowing `GET` also allows
+    /// `HEAD`. Paths without an entry accept every method.
+    pub fn allow_methods(
+        mut self,
+        path: impl Into<String>,
+        methods: impl IntoIterator<Item = Method>,
+    ) -> Self {
+        self.allowed_methods
+            .insert(path.into(), methods.into_iter().collect());
+        self
+    }
+
+    /// Delays requests of clients that keep hitting their limit.
+    pub fn tarpit(mut self, tarpit: Tarpit) -> Self {
+        self.tarpit = Some(tarpit);
+        self
+    }
+
+    /// Builds the response for rate-limited requests with `handler`.
+    ///
+    /// The returned response is still marked with the
+    /// [`REJECTION_HEADER`](crate::REJECTION_HEADER):
+    ///
+    /// ```rust
+    /// # use axum::{http::StatusCode, response::IntoResponse};
+    /// # use axum_governor::GovernorConfig;
+    /// let config = GovernorConfig::new().on_denied(|info| {
+    ///     let body = format!("Slow down on {}, please.", info.path);
+    ///     (StatusCode::TOO_MANY_REQUESTS, body).into_response()
+    /// });
+    /// ```
+    pub fn on_denied<F>(mut self, handler: F) -> Self
+    where
+        F: Fn(&DeniedInfo) -> Response<Body> + Send + Sync + 'static,
+    {
+        self.on_denied = Some(Arc::new(handler));
+        self
+    }
+
+    /// Paces the requests of each client that pass the rules.
+    ///
+    /// Requests that skip the rules, e.g. of allowlisted clients, services or
+    /// exempt retries, are not paced.
+    pub fn pacer(mut self, pacer: Pacer) -> Self {
+        self.pacer = Some(pacer);
+        self
+    }
+
+    /// Sets the body format of error responses produced by the governor.
+    ///
+    /// Applies to every rejection except responses built by `on_denied`.
+    pub fn error_format(mut self, format: ErrorFormat) -> Self {
+        self.error_format = format;
+        self
+    }
+
+    /// Charges requests to `path`, matched exactly like route rules, to the
+    /// `Governor` budget `name`.
+    ///
+    /// The budget is defined with [`Governor::budget`] on the attached
+    /// handle; without a handle, budgets are not enforced.
+    pub fn budget(mut self, path: impl Into<String>, name: impl Into<String>) -> Self {
+        self.budgets.insert(path.into(), name.into());
+        self
+    }
+
+    /// Sets the status of rate-limited responses, e.g.
+    /// `503 Service Unavailable` for gateways that expect it.
+    ///
+    /// Responses built by `on_denied` keep their own status.
+    pub fn denied_status(mut self, status: StatusCode) -> Self {
+        self.denied_status = Some(status);
+        self
+    }
+
+    /// Passes every response produced by the governor through `handler`.
+    ///
+    /// Runs after `error_format`, `denied_status`, `on_denied` and the
+    /// `ban_response` have built the default response:
+    ///
+    /// ```rust
+    /// # use axum::{body::Body, http::Response, response::IntoResponse};
+    /// # use axum_governor::{GovernorConfig, GovernorError};
+    /// let config = GovernorConfig::new().error_handler(
+    ///     |error: &GovernorError, default: Response<Body>| match error {
+    ///         GovernorError::MissingIp => "Try again later".into_response(),
+    ///         _ => default,
+    ///     },
+    /// );
+    /// ```
+    pub fn error_handler(mut self, handler: impl GovernorErrorHandler) -> Self {
+        self.error_handler = Some(Arc::new(handler));
+        self
+    }
+
+    /// Answers with `503 Service Unavailable` once too many requests are in
+    /// flight.
+    pub fn load_shed(mut self, shed: LoadShed) -> Self {
+        self.load_shed = Some(shed);
+        self
+    }
+
+    /// Replaces the built-in error bodies of some formats with templates.
+    ///
+    /// Combine with `ErrorFormat::Negotiate` to serve browsers a branded page
+    /// and API clients JSON.
+    pub fn error_templates(mut self, templates: ErrorTemplates) -> Self {
+        self.error_templates = templates;
+        self
+    }
+
+    /// Decides with the built-in `engine` instead of `lazy-limit`.
+    ///
+    /// The engine's rules replace the `lazy-limit` rules for this layer;
+    /// `init_rate_limiter!` is not needed. Requires the `engine` feature.
+    #[cfg(feature = "engine")]
+    pub fn engine(mut self, engine: crate::Engine) -> Self {
+        self.engine = Some(engine);
+        self
+    }
+
+    /// Decides requests of `tenant` with `engine`, overriding the rules of
+    /// [`engine`](Self::engine) or `lazy-limit` for that tenant only.
+    ///
+    /// Requires a resolver set with [`tenants`](Self::tenants) and the
+    /// `engine` feature.
+    #[cfg(feature = "engine")]
+    pub fn tenant_engine(mut self, tenant: impl Into<String>, engine: crate::Engine) -> Self {
+        self.tenant_engines.insert(tenant.into(), engine);
+        self
+    }
+
+    /// Sets what happens to requests without a `RealIp` extension, so
+    /// deployments behind unusual setups aren't bricked by a `500`.
+    pub fn on_missing_ip(mut self, policy: MissingIpPolicy) -> Self {
+        self.on_missing_ip = policy;
+        self
+    }
+
+    /// Keys requests with `extractor` instead of by client IP:
+    ///
+    /// ```rust
+    /// # use axum_governor::{GovernorConfig, KeyError};
+    /// # use axum::http::request::Parts;
+    /// let config = GovernorConfig::new().key_extractor(|parts: &Parts| {
+    ///     match parts.headers.get("x-tenant") {
+    ///         Some(tenant) => tenant
+    ///             .to_str()
+    ///             .map(|tenant| format!("tenant:{tenant}"))
+    ///             .map_err(|_| KeyError::Invalid("tenant is not ASCII".into())),
+    ///         None => Err(KeyError::Missing),
+    ///     }
+    /// });
+    /// ```
+    pub fn key_extractor(mut self, extractor: impl KeyExtractor) -> Self {
+        self.key_extractor = Some(Arc::new(extractor));
+        self
+    }
+
+    /// Looks up rules by the rule path `matcher` resolves, instead of the
+    /// exact request path.
+    ///
+    /// Applies to `lazy-limit` route rules, the built-in engine, allowed
+    /// methods and budgets alike.
+    pub fn rule_matcher(mut self, matcher: impl RuleMatcher) -> Self {
+        self.rule_matcher = Some(Arc::new(matcher));
+        self
+    }
+
+    /// Derives [`GovernorFlags`] for requests that don't carry them as an
+    /// extension, e.g. from a header set by a trusted proxy.
+    pub fn flags_from<F>(mut self, resolve: F) -> Self
+    where
+        F: Fn(&Parts) -> GovernorFlags + Send + Sync + 'static,
+    {
+        self.flags_from = Some(Arc::new(resolve));
+        self
+    }
+
+    /// Composes the limiter key from parts of the request, so a client gets
+    /// independent quotas per method or path without separate rules.
+    ///
+    /// Supported placeholders are `{key}` (the client key, or `service:<name>`
+    /// for authenticated services), `{ip}` (the client IP), `{method}` and
+    /// `{path}` (the rule path). For example `"{key}:{method}"` limits `GET`
+    /// and `POST` separately. Bans, budgets and reports keep using the client
+    /// key.
+    pub fn key_template(mut self, template: impl Into<String>) -> Self {
+        self.key_template = Some(template.into());
+        self
+    }
+
+    /// Derives the rate-limit key with an asynchronous lookup, instead of
+    /// `key_extractor`.
+    pub fn async_key_extractor(mut self, extractor: impl AsyncKeyExtractor) -> Self {
+        self.async_key_extractor = Some(Arc::new(extractor));
+        self
+    }
+
+    /// Cancels `async_key_extractor` lookups taking longer than `timeout`.
+    pub fn key_timeout(mut self, timeout: Duration) -> Self {
+        self.key_timeout = Some(timeout);
+        self
+    }
+
+    /// Keys requests by `key` when `async_key_extractor` times out, instead
+    /// of by their client IP.
+    ///
+    /// All such requests share one bucket, which keeps a slow lookup backend
+    /// from turning into unlimited traffic.
+    pub fn key_fallback(mut self, key: impl Into<String>) -> Self {
+        self.key_fallback = Some(key.into());
+        self
+    }
+
+    /// Throttles non-critical routes while the error budget of `guard` burns.
+    pub fn slo_guard(mut self, guard: SloGuard) -> Self {
+        self.slo_guard = Some(guard);
+        self
+    }
+
+    /// Hashes limiter keys with HMAC-SHA256 under `salt` before they reach
+    /// the rate-limit store, so raw client IPs and identifiers are not kept
+    /// there. Shorthand for `key_codec(SaltedHashCodec::new(salt))`.
+    ///
+    /// Only the limiter key is hashed. All other per-client state of the
+    /// layer and its `Governor` keeps the raw client key, usually the client
+    /// IP, in memory: bans, the byte, response and named budgets, key
+    /// levels, the retry policy, the tarpit, the pacer, the seen tracker,
+    /// usage, reports and debug logs. Don't rely on this alone to keep client
+    /// IPs out of process memory or exported data.
+    pub fn hash_keys(self, salt: impl AsRef<[u8]>) -> Self {
+        self.key_codec(SaltedHashCodec::new(salt))
+    }
+
+    /// Assigns the route `path` a criticality tier, deciding how early load
+    /// shedding and error-budget throttling hit it.
+    pub fn criticality(mut self, path: impl Into<String>, criticality: Criticality) -> Self {
+        self.criticality.insert(path.into(), criticality);
+        self
+    }
+
+    /// Sets which path of a request rules are matched against.
+    ///
+    /// With [`MatchSource::OriginalUri`], rules are written against external
+    /// paths wherever the layer is placed in nested routers; with
+    /// [`MatchSource::MatchedPath`], against route templates.
+    pub fn match_on(mut self, source: MatchSource) -> Self {
+        self.match_on = source;
+        self
+    }
+
+    /// Isolates requests by the tenant `resolver` returns, namespacing keys,
+    /// budgets and stats per tenant.
+    pub fn tenants(mut self, resolver: impl TenantResolver) -> Self {
+        self.tenant_resolver = Some(Arc::new(resolver));
+        self
+    }
+
+    /// Caps the requests of each tenant in aggregate, on top of the per-user
+    /// limits. Requires a resolver set with [`tenants`](Self::tenants).
+    pub fn tenant_cap(mut self, cap: TenantCap) -> Self {
+        self.tenant_cap = Some(cap);
+        self
+    }
+
+    /// Consults `policy` for every limited request and merges its verdict
+    /// with the local rules.
+    pub fn external_policy(mut self, policy: ExternalPolicy) -> Self {
+        self.external_policy = Some(policy);
+        self
+    }
+
+    /// Limits each of `hosts` independently, by treating the `Host` as the
+    /// tenant. Shorthand for `tenants(HostTenant::new(hosts))`.
+    ///
+    /// Only listed hosts and subdomains of `*.`-prefixed entries become
+    /// tenants; requests for other hosts share the buckets of the layer, so
+    /// clients can't escape their limits by varying the `Host` header. With
+    /// the `engine` feature, [`tenant_engine`](Self::tenant_engine) sets rules
+    /// for single hosts, e.g. `tenant_engine("api.example.com", engine)`.
+    pub fn per_host<I>(self, hosts: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        self.tenants(HostTenant::new(hosts))
+    }
+
+    /// Checks requests without valid credentials against a stricter `config`.
+    ///
+    /// `is_authenticated` decides per request, e.g. by looking for a session
+    /// extension an auth layer inserted. Authenticated requests are checked
+    /// against this configuration, anonymous ones against `config`, so one
+    /// layer enforces two rule sets without duplicating routers:
+    ///
+    /// ```rust
+    /// # use axum::http::{header::AUTHORIZATION, request::Parts};
+    /// # use axum_governor::{GovernorConfig, Pacer};
+    /// # use std::time::Duration;
+    /// // Anonymous clients additionally get at most two requests per second.
+    /// let anonymous = GovernorConfig::new()
+    ///     .tier("anonymous")
+    ///     .pacer(Pacer::new(Duration::from_millis(500), 0));
+    /// let config = GovernorConfig::new().anonymous(
+    ///     |parts: &Parts| parts.headers.contains_key(AUTHORIZATION),
+    ///     anonymous,
+    /// );
+    /// ```
+    ///
+    /// With the `engine` feature, give each configuration an engine of its
+    /// own for fully separate rules. Load shedding, retry tracking and
+    /// response budgets of the layer still come from this configuration.
+    pub fn anonymous<F>(mut self, is_authenticated: F, config: GovernorConfig) -> Self
+    where
+        F: Fn(&Parts) -> bool + Send + Sync + 'static,
+    {
+        self.anonymous = Some((Arc::new(is_authenticated), Arc::new(config)));
+        self
+    }
+
+    /// Additionally limits requests under the key of `level`, after the
+    /// levels added before. Every level must pass.
+    pub fn key_level(mut self, level: KeyLevel) -> Self {
+        self.key_levels.push(level);
+        self
+    }
+
+    /// Keys requests under the path `prefix` with `extractor`, e.g. `/api`
+    /// by API key while everything else stays keyed by IP.
+    ///
+    /// Prefixes match whole segments of the rule path, and the longest
+    /// matching prefix wins. Requests outside every prefix use
+    /// `key_extractor` or `async_key_extractor` as usual.
+    pub fn key_extractor_for(
+        mut self,
+        prefix: impl Into<String>,
+        extractor: impl KeyExtractor,
+    ) -> Self {
+        self.route_extractors
+            .push((prefix.into(), Arc::new(extractor)));
+        self
+    }
+
+    /// Prefixes the limiter keys of this layer with `namespace`, e.g.
+    /// `premium:203.0.113.7`.
+    ///
+    /// Layers share the one `lazy-limit` store, so two layers with different
+    /// configurations count a client's requests in the same buckets. A
+    /// namespace gives the layer buckets of its own. It is applied before any
+    /// `KeyCodec`; bans and budgets still use the client key, so a ban holds
+    /// across namespaces.
+    pub fn key_namespace(mut self, namespace: impl Into<String>) -> Self {
+        self.key_namespace = Some(namespace.into());
+        self
+    }
+
+    /// Gives back the budget charges of a request whose handler panics.
+    ///
+    /// A panicking handler produces no useful response, so its request
+    /// shouldn't use up the client's allowance. When enabled, the charges of
+    /// key levels, the byte budget and named budgets are refunded, whether or
+    /// not a `CatchPanic` layer further out turns the panic into a response.
+    /// `lazy-limit` counters can't be refunded and keep the request. The
+    /// in-flight slot of [`load_shed`](Self::load_shed) is always released.
+    /// Only applies to `GovernorMiddleware`, not the `RateLimited` extractor.
+    pub fn refund_on_panic(mut self, refund: bool) -> Self {
+        self.refund_on_panic = refund;
+        self
+    }
+
+    /// Appends a normalized `User-Agent` family to the limiter key, e.g.
+    /// `203.0.113.7|browser` and `203.0.113.7|script`.
+    ///
+    /// Browsers, scripts and mobile apps behind one corporate NAT then get
+    /// separate buckets instead of throttling each other. The families are
+    /// coarse (`browser`, `mobile-browser`, `mobile-app`, `cli`, `script`,
+    /// `bot`, `other`, `none`), so a client faking its `User-Agent` gains at
+    /// most a few extra buckets. Bans, budgets and reports still use the
+    /// client key.
+    pub fn user_agent_in_key(mut self, enabled: bool) -> Self {
+        self.user_agent_in_key = enabled;
+        self
+    }
 }
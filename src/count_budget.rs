@@ -0,0 +1,146 @@
+/* src/count_budget.rs */
+
+use crate::expiring::{Expiring, ExpiringMap};
+use std::{
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::{Duration, Instant},
+};
+
+/// A per-key count of units, e.g. requests or bytes, per fixed window.
+///
+/// Each key may use at most `limit` units within a `window`. The counter
+/// behind [`ByteBudget`](crate::ByteBudget), named budgets, key levels, tenant
+/// caps and the retry quota. Clones share the same counts.
+#[derive(Debug, Clone)]
+pub(crate) struct CountBudget {
+    limit: u64,
+    window: Duration,
+    usage: Arc<Mutex<ExpiringMap<String, Usage>>>,
+}
+
+#[derive(Debug)]
+struct Usage {
+    resets_at: Instant,
+    used: u64,
+}
+
+impl Expiring for Usage {
+    fn expires_at(&self) -> Instant {
+        self.resets_at
+    }
+}
+
+impl CountBudget {
+    /// Creates a budget of `limit` units per `window`.
+    pub(crate) fn new(limit: u64, window: Duration) -> Self {
+        Self {
+            limit,
+            window,
+            usage: Arc::default(),
+        }
+    }
+
+    /// Charges `cost` to `key`, returning `false` if that would exceed the
+    /// budget.
+    pub(crate) fn charge(&self, key: &str, cost: u64) -> bool {
+        self.charge_window(key, cost).is_some()
+    }
+
+    /// Like [`charge`](Self::charge), but returns the end of the window the
+    /// cost was charged to, for a later [`refund`](Self::refund).
+    pub(crate) fn charge_window(&self, key: &str, cost: u64) -> Option<Instant> {
+        self.with_usage(key, |usage, limit| match usage.used.checked_add(cost) {
+            Some(total) if total <= limit => {
+                usage.used = total;
+                Some(usage.resets_at)
+            }
+            _ => None,
+        })
+    }
+
+    /// Gives back `cost` charged to `key` in the window ending at `window`.
+    /// Nothing is refunded once that window has reset.
+    pub(crate) fn refund(&self, key: &str, cost: u64, window: Instant) {
+        self.with_usage(key, |usage, _| {
+            if usage.resets_at == window {
+                usage.used = usage.used.saturating_sub(cost);
+            }
+        });
+    }
+
+    /// Charges `cost` to `key` even past the budget, returning `false` if the
+    /// budget is exceeded afterwards.
+    pub(crate) fn consume(&self, key: &str, cost: u64) -> bool {
+        self.with_usage(key, |usage, limit| {
+            usage.used = usage.used.saturating_add(cost);
+            usage.used <= limit
+        })
+    }
+
+    /// Returns whether `key` has used up its budget for the current window.
+    pub(crate) fn is_exhausted(&self, key: &str) -> bool {
+        self.with_usage(key, |usage, limit| usage.used >= limit)
+    }
+
+    /// Runs `f` on the usage of `key` in the current window.
+    fn with_usage<R>(&self, key: &str, f: impl FnOnce(&mut Usage, u64) -> R) -> R {
+        let now = Instant::now();
+        let fresh = || Usage {
+            resets_at: now + self.window,
+            used: 0,
+        };
+        self.usage().update(key, now, fresh, |usage| {
+            if usage.resets_at <= now {
+                *usage = fresh();
+            }
+            f(usage, self.limit)
+        })
+    }
+
+    fn usage(&self) -> MutexGuard<'_, ExpiringMap<String, Usage>> {
+        self.usage.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn charges_up_to_the_limit() {
+        let budget = CountBudget::new(3, Duration::from_secs(60));
+        assert!(budget.charge("a", 2));
+        assert!(!budget.charge("a", 2));
+        assert!(budget.charge("a", 1));
+        assert!(budget.is_exhausted("a"));
+        assert!(budget.charge("b", 3));
+    }
+
+    #[test]
+    fn refunds_within_the_window() {
+        let budget = CountBudget::new(2, Duration::from_secs(60));
+        let window = budget.charge_window("a", 2).unwrap();
+        budget.refund("a", 1, window);
+        assert!(budget.charge("a", 1));
+        assert!(!budget.charge("a", 1));
+    }
+
+    #[test]
+    fn consumes_past_the_limit() {
+        let budget = CountBudget::new(2, Duration::from_secs(60));
+        assert!(budget.consume("a", 2));
+        assert!(!budget.consume("a", 1));
+        assert!(budget.is_exhausted("a"));
+    }
+
+    #[test]
+    fn resets_after_the_window() {
+        let budget = CountBudget::new(1, Duration::from_millis(20));
+        let window = budget.charge_window("a", 1).unwrap();
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(budget.charge("a", 1));
+        // A refund for the old window doesn't touch the new one.
+        budget.refund("a", 1, window);
+        assert!(!budget.charge("a", 1));
+    }
+}
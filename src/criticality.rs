@@ -0,0 +1,35 @@
+/* src/criticality.rs */
+
+/// How important a route is when the server has to shed load.
+///
+/// Assign tiers with `GovernorConfig::criticality`. Every overload
+/// protection then sheds the lower tiers first, in the same order: a
+/// [`LoadShed`](crate::LoadShed) lets each tier fill only its share of
+/// `max_in_flight`, and an [`SloGuard`](crate::SloGuard) sees the error
+/// budget scaled by the same share, so low tiers are throttled while
+/// higher ones still pass untouched. Routes default to `Normal`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Criticality {
+    /// Shed first, once half of the capacity is in use, e.g. prefetching or
+    /// analytics beacons.
+    Sheddable,
+    /// Shed once three quarters of the capacity are in use, e.g. search.
+    Low,
+    /// Shed only at full capacity.
+    #[default]
+    Normal,
+    /// Shed only at full capacity and never throttled by an `SloGuard`, e.g.
+    /// checkout or login.
+    Critical,
+}
+
+impl Criticality {
+    /// Returns the share of capacity and error budget the tier may use.
+    pub(crate) fn share(self) -> f64 {
+        match self {
+            Criticality::Sheddable => 0.5,
+            Criticality::Low => 0.75,
+            Criticality::Normal | Criticality::Critical => 1.0,
+        }
+    }
+}
@@ -0,0 +1,146 @@
+/* src/decider.rs */
+
+use crate::{check::check, Decision, GovernorConfig, Rejection};
+use axum::http::{request::Parts, Request};
+use futures_util::future::BoxFuture;
+use std::{
+    convert::Infallible,
+    sync::Arc,
+    task::{Context, Poll},
+};
+use tower::Service;
+
+/// A request to a [`DecisionService`].
+#[derive(Debug, Clone)]
+pub struct DecisionRequest {
+    /// The method, URI, headers and extensions of the request to decide on.
+    pub parts: Parts,
+}
+
+impl DecisionRequest {
+    /// Creates a request deciding on `parts`.
+    pub fn new(parts: Parts) -> Self {
+        Self { parts }
+    }
+
+    /// Creates a request deciding on the head of `request`, cloning its
+    /// headers and extensions.
+    pub fn from_request<B>(request: &Request<B>) -> Self {
+        let (mut parts, ()) = Request::new(()).into_parts();
+        parts.method = request.method().clone();
+        parts.uri = request.uri().clone();
+        parts.version = request.version();
+        parts.headers = request.headers().clone();
+        parts.extensions = request.extensions().clone();
+        Self { parts }
+    }
+}
+
+/// The limiter's decision engine as a `tower::Service`.
+///
+/// Runs the same checks as `GovernorLayer` and answers with the resulting
+/// [`Decision`] instead of a response, so the decision can be composed with
+/// standard Tower middleware such as timeouts, retries or hedging:
+///
+/// ```rust
+/// # use axum::http::Request;
+/// # use axum_governor::{Decision, DecisionRequest, DecisionService, GovernorConfig};
+/// # use tower::ServiceExt;
+/// # async fn run() {
+/// let decider = DecisionService::new(GovernorConfig::new());
+/// let request = Request::get("/api/items").body(()).unwrap();
+/// let decision = decider
+///     .oneshot(DecisionRequest::from_request(&request))
+///     .await
+///     .unwrap();
+/// if decision != Decision::Allowed {
+///     // Answer the request yourself.
+/// }
+/// # }
+/// ```
+///
+/// Deciding counts the request against its limits, so a retry around the
+/// service charges the request again. Requests the governor would reject for
+/// other reasons than a ban, e.g. a missing `RealIp` extension or an invalid
+/// key, are `Denied`; shadow mode answers `Allowed`. The service is always
+/// ready and never fails. Clones share the same configuration.
+#[derive(Debug, Clone)]
+pub struct DecisionService {
+    config: Arc<GovernorConfig>,
+}
+
+impl DecisionService {
+    /// Creates a service deciding with `config`.
+    pub fn new(config: GovernorConfig) -> Self {
+        Self {
+            config: Arc::new(config),
+        }
+    }
+}
+
+impl Service<DecisionRequest> for DecisionService {
+    type Response = Decision;
+    type Error = Infallible;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: DecisionRequest) -> Self::Future {
+        let config = self.config.clone();
+
+        Box::pin(async move {
+            let decision = match check(&config, &request.parts).await {
+                Ok(_) => Decision::Allowed,
+                Err(response) => match response.extensions().get::<Rejection>() {
+                    Some(Rejection::Banned) => Decision::Banned,
+                    _ => Decision::Denied,
+                },
+            };
+            Ok(decision)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_util, Governor};
+    use std::time::Duration;
+    use tower::ServiceExt;
+
+    async fn decide(decider: &DecisionService, path: &str) -> Decision {
+        let request = DecisionRequest::new(test_util::parts(path));
+        decider.clone().oneshot(request).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn answers_with_the_decision() {
+        test_util::init_limiter().await;
+        let governor = Governor::new().budget("once", 1, Duration::from_secs(60));
+        let decider = DecisionService::new(
+            test_util::config()
+                .governor(governor.clone())
+                .budget("/export", "once"),
+        );
+
+        assert_eq!(decide(&decider, "/export").await, Decision::Allowed);
+        assert_eq!(decide(&decider, "/export").await, Decision::Denied);
+
+        governor.ban(test_util::CLIENT, Duration::from_secs(60), "abuse");
+        assert_eq!(decide(&decider, "/").await, Decision::Banned);
+    }
+
+    #[tokio::test]
+    async fn other_rejections_are_denied() {
+        // Without a `RealIp` extension the request can't be limited.
+        let decider = DecisionService::new(GovernorConfig::new());
+        let request = Request::get("/").body(()).unwrap();
+        let decision = decider
+            .oneshot(DecisionRequest::from_request(&request))
+            .await
+            .unwrap();
+        assert_eq!(decision, Decision::Denied);
+    }
+}
@@ -0,0 +1,155 @@
+/* src/decisions.rs */
+
+use crate::codec::fnv1a;
+use axum::http::Method;
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex, PoisonError},
+    time::SystemTime,
+};
+
+/// The outcome of a rate-limit check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    /// The request was passed to the inner service.
+    Allowed,
+    /// The request was rejected because the client exceeded its limit.
+    Denied,
+    /// The request was rejected because the client is banned.
+    Banned,
+}
+
+/// A single entry of a [`DecisionLog`].
+#[derive(Debug, Clone)]
+pub struct DecisionRecord {
+    /// FNV-1a hash of the client key, matched by
+    /// [`for_key`](DecisionLog::for_key). The hash is unsalted, so an IP key
+    /// is easily recovered by hashing the address space; treat it as
+    /// identifying as the key itself.
+    pub key_hash: u64,
+    /// The request method.
+    pub method: Method,
+    /// The request path.
+    pub path: String,
+    /// What the middleware decided.
+    pub decision: Decision,
+    /// When the decision was made.
+    pub timestamp: SystemTime,
+    /// When the client was first seen, if a `SeenTracker` is attached.
+    pub first_seen: Option<SystemTime>,
+}
+
+/// A bounded in-memory ring of the most recent decisions.
+///
+/// Attach it with `GovernorConfig::decision_log` and keep a clone around to
+/// answer questions like "why did this client get a 429 at 14:02?" without
+/// full audit logging. Once `capacity` records are held, the oldest is dropped.
+/// Clones share the same buffer, so one log can collect from several layers.
+#[derive(Debug, Clone)]
+pub struct DecisionLog {
+    records: Arc<Mutex<VecDeque<DecisionRecord>>>,
+    capacity: usize,
+}
+
+impl DecisionLog {
+    /// Creates a log holding at most `capacity` decisions.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            records: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+            capacity,
+        }
+    }
+
+    /// Returns all held records, oldest first.
+    pub fn snapshot(&self) -> Vec<DecisionRecord> {
+        self.lock().iter().cloned().collect()
+    }
+
+    /// Returns the held records for a single client key, oldest first.
+    pub fn for_key(&self, key: &str) -> Vec<DecisionRecord> {
+        let key_hash = fnv1a(key);
+        self.lock()
+            .iter()
+            .filter(|record| record.key_hash == key_hash)
+            .cloned()
+            .collect()
+    }
+
+    /// Drops all held records.
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    pub(crate) fn record(
+        &self,
+        key: &str,
+        method: Method,
+        path: &str,
+        decision: Decision,
+        first_seen: Option<SystemTime>,
+    ) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut records = self.lock();
+        if records.len() == self.capacity {
+            records.pop_front();
+        }
+        records.push_back(DecisionRecord {
+            key_hash: fnv1a(key),
+            method,
+            path: path.to_string(),
+            decision,
+            timestamp: SystemTime::now(),
+            first_seen,
+        });
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, VecDeque<DecisionRecord>> {
+        self.records.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths(records: &[DecisionRecord]) -> Vec<&str> {
+        records.iter().map(|record| record.path.as_str()).collect()
+    }
+
+    #[test]
+    fn keeps_the_latest_decisions() {
+        let log = DecisionLog::new(2);
+        for path in ["/a", "/b", "/c"] {
+            log.record("203.0.113.7", Method::GET, path, Decision::Allowed, None);
+        }
+        assert_eq!(paths(&log.snapshot()), ["/b", "/c"]);
+        // Clones share the buffer.
+        log.clone().clear();
+        assert!(log.snapshot().is_empty());
+    }
+
+    #[test]
+    fn filters_by_key() {
+        let log = DecisionLog::new(8);
+        log.record("203.0.113.7", Method::GET, "/a", Decision::Allowed, None);
+        log.record("198.51.100.1", Method::GET, "/b", Decision::Banned, None);
+        log.record("203.0.113.7", Method::POST, "/c", Decision::Denied, None);
+
+        let records = log.for_key("203.0.113.7");
+        assert_eq!(paths(&records), ["/a", "/c"]);
+        assert_eq!(records[1].method, Method::POST);
+        assert_eq!(records[1].decision, Decision::Denied);
+        assert_eq!(records[1].key_hash, fnv1a("203.0.113.7"));
+        assert!(log.for_key("192.0.2.1").is_empty());
+    }
+
+    #[test]
+    fn zero_capacity_keeps_nothing() {
+        let log = DecisionLog::new(0);
+        log.record("203.0.113.7", Method::GET, "/a", Decision::Allowed, None);
+        assert!(log.snapshot().is_empty());
+    }
+}
@@ -0,0 +1,131 @@
+/* src/deny_cache.rs */
+
+use crate::expiring::{Expiring, ExpiringMap};
+use std::{
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::{Duration, Instant},
+};
+
+/// A local cache of recent denials.
+///
+/// Once a request is denied, further requests for the same key, method and
+/// path are denied straight from this cache for the `hold` duration without
+/// consulting the rate limiter. During floods from a single hot key this
+/// reduces limiter work to a timestamp comparison.
+///
+/// `lazy-limit` does not report when a window resets, so `hold` should stay
+/// well below the shortest rule window. A longer hold denies requests the
+/// limiter would already allow again. With the built-in engine, denials are
+/// instead held exactly until the denying rule's window frees a slot, and
+/// `hold` is not used. Clones share the same cache.
+#[derive(Debug, Clone)]
+pub struct DenyCache {
+    hold: Duration,
+    entries: Arc<Mutex<ExpiringMap<String, Hold>>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Hold {
+    until: Instant,
+    /// Whether `until` is when the limiter frees a slot, rather than the
+    /// configured hold.
+    exact: bool,
+}
+
+impl Expiring for Hold {
+    fn expires_at(&self) -> Instant {
+        self.until
+    }
+}
+
+impl DenyCache {
+    /// Creates a cache holding each denial for `hold`.
+    pub fn new(hold: Duration) -> Self {
+        Self {
+            hold,
+            entries: Arc::default(),
+        }
+    }
+
+    /// Returns whether `id` is still held denied, with the time the limiter
+    /// frees a slot again if it reported one.
+    pub(crate) fn denied(&self, id: &str) -> Option<Option<Instant>> {
+        let mut entries = self.entries();
+        match entries.get(id) {
+            Some(hold) if hold.until > Instant::now() => Some(hold.exact.then_some(hold.until)),
+            Some(_) => {
+                entries.remove(id);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Records a denial of `id`, held until `until` if the limiter knows when
+    /// it ends, or for `hold` otherwise.
+    pub(crate) fn deny(&self, id: String, until: Option<Instant>) {
+        let now = Instant::now();
+        let hold = Hold {
+            until: until.unwrap_or(now + self.hold),
+            exact: until.is_some(),
+        };
+        self.entries().insert(id, hold, now);
+    }
+
+    fn entries(&self) -> MutexGuard<'_, ExpiringMap<String, Hold>> {
+        self.entries.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn holds_denials_for_hold() {
+        let cache = DenyCache::new(Duration::from_secs(60));
+        assert_eq!(cache.denied("GET /login 203.0.113.7"), None);
+
+        cache.deny("GET /login 203.0.113.7".to_string(), None);
+        assert_eq!(cache.denied("GET /login 203.0.113.7"), Some(None));
+        assert_eq!(cache.denied("POST /login 203.0.113.7"), None);
+        // Clones share the cache.
+        assert_eq!(cache.clone().denied("GET /login 203.0.113.7"), Some(None));
+    }
+
+    #[test]
+    fn forgets_expired_denials() {
+        let cache = DenyCache::new(Duration::ZERO);
+        cache.deny("GET /login 203.0.113.7".to_string(), None);
+        assert_eq!(cache.denied("GET /login 203.0.113.7"), None);
+        assert!(cache.entries().get("GET /login 203.0.113.7").is_none());
+    }
+
+    #[test]
+    fn evicts_the_soonest_denial_when_full() {
+        let cache = DenyCache {
+            hold: Duration::from_secs(60),
+            entries: Arc::new(Mutex::new(ExpiringMap::with_capacity(2))),
+        };
+        let now = Instant::now();
+        cache.deny("GET /a".to_string(), Some(now + Duration::from_secs(30)));
+        cache.deny("GET /b".to_string(), Some(now + Duration::from_secs(10)));
+        cache.deny("GET /c".to_string(), None);
+        assert_eq!(cache.entries().len(), 2);
+        assert_eq!(cache.denied("GET /b"), None);
+        assert!(cache.denied("GET /a").is_some());
+    }
+
+    #[test]
+    fn holds_until_the_limiter_frees_a_slot() {
+        let cache = DenyCache::new(Duration::ZERO);
+        let until = Instant::now() + Duration::from_secs(30);
+        cache.deny("GET /login 203.0.113.7".to_string(), Some(until));
+        assert_eq!(cache.denied("GET /login 203.0.113.7"), Some(Some(until)));
+
+        // An exact hold in the past is over, whatever the configured hold.
+        let cache = DenyCache::new(Duration::from_secs(60));
+        cache.deny("GET /login 203.0.113.7".to_string(), Some(Instant::now()));
+        assert_eq!(cache.denied("GET /login 203.0.113.7"), None);
+    }
+}
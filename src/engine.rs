@@ -0,0 +1,286 @@
+/* src/engine.rs */
+
+use crate::expiring::{Expiring, ExpiringMap};
+use axum::http::Method;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::{Duration, Instant},
+};
+
+/// A rate-limit rule: at most `max_requests` per sliding `window`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Rule {
+    window: Duration,
+    max_requests: usize,
+}
+
+impl Rule {
+    /// Creates a rule allowing `max_requests` per `window`.
+    pub fn new(window: Duration, max_requests: usize) -> Self {
+        Self {
+            window,
+            max_requests,
+        }
+    }
+}
+
+/// A self-contained decision engine replacing `lazy-limit`.
+///
+/// Implements the same rule semantics: a default rule applies to every
+/// request, route rules to requests for their exact path (optionally only
+/// for one method). In normal mode a request must pass both the default and
+/// the matching route rule; in override mode only the route rule applies,
+/// falling back to the default rule for paths without one.
+///
+/// Unlike `lazy-limit`, an engine is a plain value without global state, so
+/// several independent engines can coexist, e.g. one per router:
+///
+/// ```rust
+/// # use axum_governor::{Engine, GovernorConfig, Rule};
+/// # use axum::http::Method;
+/// # use std::time::Duration;
+/// let engine = Engine::new(Rule::new(Duration::from_secs(1), 5))
+///     .route("/api/special", Rule::new(Duration::from_secs(1), 10))
+///     .route_method("/api/upload", Method::POST, Rule::new(Duration::from_secs(60), 3));
+/// let config = GovernorConfig::new().engine(engine);
+/// ```
+///
+/// Clones share the same counters, while each clone owns its rules: a route
+/// added to a clone applies to that clone only. Once the config holding the
+/// engine is turned into a layer, the rules are frozen. Idle windows are
+/// dropped once a second, and at most 2^20 windows are tracked: past that,
+/// the windows closest to expiry are dropped first. Requires the `engine`
+/// feature.
+#[derive(Debug, Clone)]
+pub struct Engine {
+    default: Rule,
+    routes: HashMap<String, Vec<(Option<Method>, Rule)>>,
+    windows: Arc<Mutex<Windows>>,
+}
+
+/// The recent hits per rule and key.
+type Windows = ExpiringMap<(Rule, String), Hits>;
+
+/// The recent hits of a key under one rule.
+#[derive(Debug)]
+struct Hits {
+    at: VecDeque<Instant>,
+    /// When the latest hit leaves the window.
+    expires_at: Instant,
+}
+
+impl Expiring for Hits {
+    fn expires_at(&self) -> Instant {
+        self.expires_at
+    }
+}
+
+impl Engine {
+    /// Creates an engine with the `default` rule and no route rules.
+    pub fn new(default: Rule) -> Self {
+        Self {
+            default,
+            routes: HashMap::new(),
+            windows: Arc::default(),
+        }
+    }
+
+    /// Adds a rule for every request to `path`.
+    pub fn route(self, path: impl Into<String>, rule: Rule) -> Self {
+        self.add_route(path.into(), None, rule)
+    }
+
+    /// Adds a rule for `method` requests to `path`, taking precedence over a
+    /// rule for every method.
+    pub fn route_method(self, path: impl Into<String>, method: Method, rule: Rule) -> Self {
+        self.add_route(path.into(), Some(method), rule)
+    }
+
+    fn add_route(mut self, path: String, method: Option<Method>, rule: Rule) -> Self {
+        self.routes.entry(path).or_default().push((method, rule));
+        self
+    }
+
+    /// Checks a request of `key` and records it if allowed.
+    ///
+    /// A denial carries the instant the denying rule frees up again. Denied
+    /// requests are not recorded, so until then every request of `key` to
+    /// `path` is denied as well, which makes the instant safe to cache.
+    pub(crate) fn check(
+        &self,
+        key: &str,
+        path: &str,
+        method: &Method,
+        override_mode: bool,
+    ) -> Result<(), Instant> {
+        let route = self.routes.get(path).and_then(|rules| {
+            rules
+                .iter()
+                .find(|(rule_method, _)| rule_method.as_ref() == Some(method))
+                .or_else(|| rules.iter().find(|(rule_method, _)| rule_method.is_none()))
+                .map(|(_, rule)| *rule)
+        });
+
+        let rules = match (route, override_mode) {
+            (Some(route), true) => vec![route],
+            (Some(route), false) => vec![self.default, route],
+            (None, _) => vec![self.default],
+        };
+
+        let now = Instant::now();
+        let mut windows = self.windows();
+
+        // Check every rule first, so a request denied by one rule is not
+        // counted against the others.
+        for rule in &rules {
+            let (live, oldest) = match windows.get(&(*rule, key.to_string())) {
+                Some(hits) => {
+                    let expired = hits
+                        .at
+                        .partition_point(|hit| now.duration_since(*hit) >= rule.window);
+                    (hits.at.len() - expired, hits.at.get(expired).copied())
+                }
+                None => (0, None),
+            };
+            if live >= rule.max_requests {
+                return Err(oldest.unwrap_or(now) + rule.window);
+            }
+        }
+
+        for rule in rules {
+            let fresh = || Hits {
+                at: VecDeque::new(),
+                expires_at: now,
+            };
+            windows.update(&(rule, key.to_string()), now, fresh, |hits| {
+                while hits
+                    .at
+                    .front()
+                    .is_some_and(|hit| now.duration_since(*hit) >= rule.window)
+                {
+                    hits.at.pop_front();
+                }
+                hits.at.push_back(now);
+                hits.expires_at = now + rule.window;
+            });
+        }
+        Ok(())
+    }
+
+    fn windows(&self) -> MutexGuard<'_, Windows> {
+        self.windows.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "203.0.113.7";
+
+    fn per_minute(max_requests: usize) -> Rule {
+        Rule::new(Duration::from_secs(60), max_requests)
+    }
+
+    #[test]
+    fn default_rule_allows_up_to_max() {
+        let engine = Engine::new(per_minute(2));
+        assert!(engine.check(KEY, "/", &Method::GET, false).is_ok());
+        assert!(engine.check(KEY, "/other", &Method::GET, false).is_ok());
+        assert!(engine.check(KEY, "/", &Method::GET, false).is_err());
+        assert!(engine
+            .check("198.51.100.1", "/", &Method::GET, false)
+            .is_ok());
+    }
+
+    #[test]
+    fn denial_carries_when_oldest_hit_expires() {
+        let engine = Engine::new(per_minute(1));
+        let before = Instant::now();
+        engine.check(KEY, "/", &Method::GET, false).unwrap();
+        let until = engine.check(KEY, "/", &Method::GET, false).unwrap_err();
+        assert!(until >= before + Duration::from_secs(60));
+        assert!(until <= Instant::now() + Duration::from_secs(60));
+    }
+
+    #[test]
+    fn window_slides() {
+        let engine = Engine::new(Rule::new(Duration::from_millis(50), 1));
+        engine.check(KEY, "/", &Method::GET, false).unwrap();
+        assert!(engine.check(KEY, "/", &Method::GET, false).is_err());
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(engine.check(KEY, "/", &Method::GET, false).is_ok());
+    }
+
+    #[test]
+    fn route_rules_and_override_mode() {
+        let engine = Engine::new(per_minute(1)).route("/bulk", per_minute(3));
+        // Normal mode: the default rule of 1 still applies on top.
+        engine.check(KEY, "/bulk", &Method::GET, false).unwrap();
+        assert!(engine.check(KEY, "/bulk", &Method::GET, false).is_err());
+        // Override mode: only the route rule applies.
+        let engine = Engine::new(per_minute(1)).route("/bulk", per_minute(3));
+        for _ in 0..3 {
+            engine.check(KEY, "/bulk", &Method::GET, true).unwrap();
+        }
+        assert!(engine.check(KEY, "/bulk", &Method::GET, true).is_err());
+    }
+
+    #[test]
+    fn denied_requests_are_not_counted() {
+        let engine = Engine::new(per_minute(10)).route("/login", per_minute(1));
+        engine.check(KEY, "/login", &Method::POST, false).unwrap();
+        for _ in 0..5 {
+            assert!(engine.check(KEY, "/login", &Method::POST, false).is_err());
+        }
+        // Only the allowed request counted against the default rule.
+        for _ in 0..9 {
+            engine.check(KEY, "/", &Method::GET, false).unwrap();
+        }
+        assert!(engine.check(KEY, "/", &Method::GET, false).is_err());
+    }
+
+    #[test]
+    fn method_rule_takes_precedence() {
+        let engine = Engine::new(per_minute(100))
+            .route("/upload", per_minute(5))
+            .route_method("/upload", Method::POST, per_minute(1));
+        engine.check(KEY, "/upload", &Method::POST, true).unwrap();
+        assert!(engine.check(KEY, "/upload", &Method::POST, true).is_err());
+        for _ in 0..5 {
+            engine.check(KEY, "/upload", &Method::GET, true).unwrap();
+        }
+        assert!(engine.check(KEY, "/upload", &Method::GET, true).is_err());
+    }
+
+    #[test]
+    fn zero_rule_denies_everything() {
+        let engine = Engine::new(per_minute(0));
+        assert!(engine.check(KEY, "/", &Method::GET, false).is_err());
+    }
+
+    #[test]
+    fn routes_can_be_added_after_cloning() {
+        let engine = Engine::new(per_minute(1));
+        let shared = engine.clone();
+        let engine = engine.route("/bulk", per_minute(5));
+        engine.check(KEY, "/", &Method::GET, false).unwrap();
+        // Clones share counters, but rules stay per clone.
+        assert!(shared.check(KEY, "/", &Method::GET, false).is_err());
+        assert!(shared.routes.is_empty());
+    }
+
+    #[test]
+    fn caps_tracked_windows() {
+        let mut engine = Engine::new(per_minute(1));
+        engine.windows = Arc::new(Mutex::new(ExpiringMap::with_capacity(2)));
+        for key in ["a", "b", "c"] {
+            engine.check(key, "/", &Method::GET, false).unwrap();
+        }
+        assert_eq!(engine.windows().len(), 2);
+        // The window closest to expiry made room for the newest one.
+        assert!(engine.check("a", "/", &Method::GET, false).is_ok());
+        assert!(engine.check("c", "/", &Method::GET, false).is_err());
+    }
+}
@@ -0,0 +1,68 @@
+/* src/error.rs */
+
+use crate::{Ban, DeniedInfo, Rejection};
+use axum::{
+    body::Body,
+    http::{Method, Response},
+};
+
+/// A request the governor refused, passed to a [`GovernorErrorHandler`].
+#[derive(Debug, Clone)]
+pub enum GovernorError {
+    /// The client exceeded its limit.
+    Denied(DeniedInfo),
+    /// The client is banned, or denylisted if there is no ban.
+    Banned(Option<Ban>),
+    /// The client IP is unknown because `RealIpLayer` is missing.
+    MissingIp,
+    /// The forwarded-IP headers are malformed or conflicting.
+    MalformedHeaders(&'static str),
+    /// The method is refused by a method policy or the allowed methods.
+    ForbiddenMethod(Method),
+    /// The server is shedding load.
+    Overloaded,
+    /// The key extractor refused the request's key, for the given reason.
+    InvalidKey(String),
+    /// The given tenant used up its aggregate cap.
+    TenantQuota(String),
+}
+
+impl GovernorError {
+    /// Returns the rejection reason reported in the response.
+    pub fn rejection(&self) -> Rejection {
+        match self {
+            GovernorError::Denied(_) => Rejection::RateLimited,
+            GovernorError::Banned(_) => Rejection::Banned,
+            GovernorError::MissingIp => Rejection::Misconfigured,
+            GovernorError::MalformedHeaders(_) => Rejection::MalformedHeaders,
+            GovernorError::ForbiddenMethod(_) => Rejection::ForbiddenMethod,
+            GovernorError::Overloaded => Rejection::Overloaded,
+            GovernorError::InvalidKey(_) => Rejection::InvalidKey,
+            GovernorError::TenantQuota(_) => Rejection::TenantQuota,
+        }
+    }
+}
+
+/// Customizes every response the governor produces instead of the inner
+/// service.
+///
+/// `handle` receives the error and the response the governor would send, and
+/// returns the response to send instead, so a handler can rewrite only the
+/// cases it cares about and pass the rest through. The result is still
+/// marked with the [`REJECTION_HEADER`](crate::REJECTION_HEADER).
+///
+/// Any `Fn(&GovernorError, Response<Body>) -> Response<Body>` closure is also
+/// a `GovernorErrorHandler`.
+pub trait GovernorErrorHandler: Send + Sync + 'static {
+    /// Returns the response for `error`, given the `default` one.
+    fn handle(&self, error: &GovernorError, default: Response<Body>) -> Response<Body>;
+}
+
+impl<F> GovernorErrorHandler for F
+where
+    F: Fn(&GovernorError, Response<Body>) -> Response<Body> + Send + Sync + 'static,
+{
+    fn handle(&self, error: &GovernorError, default: Response<Body>) -> Response<Body> {
+        self(error, default)
+    }
+}
@@ -0,0 +1,230 @@
+/* src/expiring.rs */
+
+use std::{
+    borrow::Borrow,
+    collections::{BTreeMap, HashMap},
+    hash::Hash,
+    time::{Duration, Instant},
+};
+
+/// Number of entries above which the soonest expiring ones are evicted.
+const MAX_ENTRIES: usize = 1 << 20;
+
+/// How often expired entries are pruned.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A value that is of no use after some instant.
+pub(crate) trait Expiring {
+    /// Returns the instant after which the value can be dropped.
+    fn expires_at(&self) -> Instant;
+}
+
+impl Expiring for Instant {
+    fn expires_at(&self) -> Instant {
+        *self
+    }
+}
+
+impl<T> Expiring for (T, Instant) {
+    fn expires_at(&self) -> Instant {
+        self.1
+    }
+}
+
+/// A map dropping its entries once they expire.
+///
+/// Entries are indexed by expiry, so pruning touches only expired entries.
+/// It runs at most once per `PRUNE_INTERVAL`, on the next write after it.
+/// Until then an expired entry may still be returned, and callers check
+/// expiry themselves as before. The map never holds more than `capacity`
+/// entries: inserting into a full map evicts the entry closest to expiry.
+#[derive(Debug)]
+pub(crate) struct ExpiringMap<K, V> {
+    entries: HashMap<K, Slot<V>>,
+    by_expiry: BTreeMap<(Instant, u64), K>,
+    next_id: u64,
+    next_prune: Instant,
+    capacity: usize,
+}
+
+#[derive(Debug)]
+struct Slot<V> {
+    value: V,
+    expires_at: Instant,
+    id: u64,
+}
+
+impl<K, V> Default for ExpiringMap<K, V> {
+    fn default() -> Self {
+        Self::with_capacity(MAX_ENTRIES)
+    }
+}
+
+impl<K, V> ExpiringMap<K, V> {
+    /// Creates a map holding at most `capacity` entries.
+    pub(crate) fn with_capacity(capacity: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            by_expiry: BTreeMap::new(),
+            next_id: 0,
+            next_prune: Instant::now(),
+            capacity: capacity.max(1),
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+impl<K: Clone + Eq + Hash, V: Expiring> ExpiringMap<K, V> {
+    /// Returns the value of `key`, which may have expired already.
+    pub(crate) fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.entries.get(key).map(|slot| &slot.value)
+    }
+
+    /// Removes `key`, returning its value.
+    pub(crate) fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        let slot = self.entries.remove(key)?;
+        self.by_expiry.remove(&(slot.expires_at, slot.id));
+        Some(slot.value)
+    }
+
+    /// Sets the value of `key`.
+    pub(crate) fn insert(&mut self, key: K, value: V, now: Instant) {
+        self.prune(now);
+        self.remove(&key);
+        self.make_room();
+        self.insert_new(key, value);
+    }
+
+    /// Runs `f` on the value of `key`, inserting `init()` first if there is
+    /// none, and returns its result.
+    pub(crate) fn update<Q, R>(
+        &mut self,
+        key: &Q,
+        now: Instant,
+        init: impl FnOnce() -> V,
+        f: impl FnOnce(&mut V) -> R,
+    ) -> R
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ToOwned<Owned = K> + ?Sized,
+    {
+        self.prune(now);
+        if !self.entries.contains_key(key) {
+            self.make_room();
+            self.insert_new(key.to_owned(), init());
+        }
+
+        let Some(slot) = self.entries.get_mut(key) else {
+            unreachable!("the entry was just inserted");
+        };
+        let result = f(&mut slot.value);
+        let expires_at = slot.value.expires_at();
+        if expires_at != slot.expires_at {
+            if let Some(key) = self.by_expiry.remove(&(slot.expires_at, slot.id)) {
+                self.by_expiry.insert((expires_at, slot.id), key);
+            }
+            slot.expires_at = expires_at;
+        }
+        result
+    }
+
+    fn insert_new(&mut self, key: K, value: V) {
+        let id = self.next_id;
+        self.next_id += 1;
+        let expires_at = value.expires_at();
+        self.by_expiry.insert((expires_at, id), key.clone());
+        self.entries.insert(
+            key,
+            Slot {
+                value,
+                expires_at,
+                id,
+            },
+        );
+    }
+
+    /// Drops expired entries if the last prune is `PRUNE_INTERVAL` ago.
+    fn prune(&mut self, now: Instant) {
+        if now < self.next_prune {
+            return;
+        }
+        self.next_prune = now + PRUNE_INTERVAL;
+
+        while let Some(entry) = self.by_expiry.first_entry()
+            && entry.key().0 <= now
+        {
+            let key = entry.remove();
+            self.entries.remove(&key);
+        }
+    }
+
+    /// Evicts the entries closest to expiry until one more fits.
+    fn make_room(&mut self) {
+        while self.entries.len() >= self.capacity {
+            let Some((_, key)) = self.by_expiry.pop_first() else {
+                break;
+            };
+            self.entries.remove(&key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prunes_expired_entries_on_schedule() {
+        let mut map = ExpiringMap::default();
+        let now = Instant::now();
+        map.insert("a".to_string(), now + Duration::from_millis(10), now);
+        map.insert("b".to_string(), now + Duration::from_secs(60), now);
+
+        // Not pruned before the next scheduled prune.
+        let soon = now + Duration::from_millis(20);
+        map.insert("c".to_string(), soon + Duration::from_secs(60), soon);
+        assert!(map.get("a").is_some());
+
+        let later = now + PRUNE_INTERVAL + Duration::from_millis(1);
+        map.update("c", later, || later, |_| ());
+        assert!(map.get("a").is_none());
+        assert!(map.get("b").is_some());
+        assert_eq!(map.len(), 2);
+    }
+
+    #[test]
+    fn evicts_the_soonest_expiring_entry_when_full() {
+        let now = Instant::now();
+        let mut map = ExpiringMap::with_capacity(2);
+        map.insert("a".to_string(), now + Duration::from_secs(60), now);
+        map.insert("b".to_string(), now + Duration::from_secs(10), now);
+        map.insert("c".to_string(), now + Duration::from_secs(30), now);
+        assert_eq!(map.len(), 2);
+        assert!(map.get("b").is_none());
+        assert!(map.get("a").is_some() && map.get("c").is_some());
+    }
+
+    #[test]
+    fn updates_move_the_expiry() {
+        let now = Instant::now();
+        let mut map = ExpiringMap::with_capacity(2);
+        map.insert("a".to_string(), now + Duration::from_secs(10), now);
+        map.insert("b".to_string(), now + Duration::from_secs(30), now);
+        map.update("a", now, || now, |at| *at = now + Duration::from_secs(60));
+        map.insert("c".to_string(), now + Duration::from_secs(40), now);
+        assert!(map.get("b").is_none());
+        assert!(map.get("a").is_some());
+    }
+}
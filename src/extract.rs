@@ -0,0 +1,102 @@
+/* src/extract.rs */
+
+use crate::{check::check, ErrorFormat, ErrorTemplates, GovernorConfig, Rejection};
+use axum::{
+    body::Body,
+    extract::FromRequestParts,
+    http::{request::Parts, Response, StatusCode},
+    response::IntoResponse,
+};
+use std::sync::Arc;
+use tracing::error;
+
+/// An extractor that rate-limits the handler it is used in.
+///
+/// Performs the same checks as `GovernorLayer`, but during the handler's
+/// extraction phase, for apps that prefer per-handler guards over router
+/// layers. Once the request is admitted, `T` is extracted and handed to the
+/// handler, so the limit runs before any other work of that extractor:
+///
+/// ```rust
+/// # use axum::extract::Path;
+/// # use axum_governor::RateLimited;
+/// async fn handler(_: RateLimited) -> &'static str {
+///     "Hello!"
+/// }
+///
+/// async fn user(RateLimited(Path(id)): RateLimited<Path<u32>>) -> String {
+///     format!("User {id}")
+/// }
+/// ```
+///
+/// The configuration is taken from an `Arc<GovernorConfig>` request
+/// extension, e.g. added with `axum::Extension(Arc::new(config))`, so it is
+/// shared rather than cloned per request. Without it the request is
+/// answered with `500 Internal Server Error` and an error is logged.
+/// `RealIpLayer` is still required.
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimited<T = ()>(pub T);
+
+impl<S, T> FromRequestParts<S> for RateLimited<T>
+where
+    S: Send + Sync,
+    T: FromRequestParts<S>,
+    T::Rejection: IntoResponse,
+{
+    type Rejection = Response<Body>;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Some(config) = parts.extensions.get::<Arc<GovernorConfig>>().cloned() else {
+            error!("RateLimited used without an Arc<GovernorConfig> request extension");
+            let response = ErrorFormat::Text.response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Rejection::Misconfigured,
+                "Internal Server Error: Rate limiter misconfigured",
+                None,
+                &ErrorTemplates::new(),
+            );
+            return Err(Rejection::Misconfigured.mark(response));
+        };
+
+        let admitted = check(&config, parts).await?;
+        parts.extensions.insert(admitted.tags);
+        T::from_request_parts(parts, state)
+            .await
+            .map(RateLimited)
+            .map_err(IntoResponse::into_response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_util, GovernorTags, REJECTION_HEADER};
+    use axum::extract::Query;
+    use std::collections::HashMap;
+
+    #[tokio::test]
+    async fn admits_and_extracts() {
+        test_util::init_limiter().await;
+        let mut parts = test_util::parts("/search?q=rust");
+        parts.extensions.insert(Arc::new(test_util::config()));
+
+        let RateLimited(Query(query)) =
+            RateLimited::<Query<HashMap<String, String>>>::from_request_parts(&mut parts, &())
+                .await
+                .unwrap();
+        assert_eq!(query["q"], "rust");
+        assert!(parts.extensions.get::<GovernorTags>().is_some());
+    }
+
+    #[tokio::test]
+    async fn rejects_without_config() {
+        let mut parts = test_util::parts("/");
+        parts.extensions.insert(test_util::config());
+
+        let response = RateLimited::<()>::from_request_parts(&mut parts, &())
+            .await
+            .unwrap_err();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(response.headers()[REJECTION_HEADER], "misconfigured");
+    }
+}
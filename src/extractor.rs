@@ -0,0 +1,655 @@
+/* src/extractor.rs */
+
+use axum::http::{
+    header::{AUTHORIZATION, COOKIE},
+    request::Parts,
+    HeaderName,
+};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use futures_util::future::BoxFuture;
+use real::RealIp;
+use std::{fmt, marker::PhantomData};
+
+/// Why a [`KeyExtractor`] produced no key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyError {
+    /// The request carries nothing to key by, e.g. an anonymous request
+    /// without an API key. The request is keyed by its client IP instead.
+    Missing,
+    /// The request carries a key that is malformed or not acceptable. The
+    /// request is rejected with `400 Bad Request`.
+    Invalid(String),
+}
+
+impl fmt::Display for KeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyError::Missing => f.write_str("no key in request"),
+            KeyError::Invalid(reason) => write!(f, "invalid key: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for KeyError {}
+
+/// Derives the rate-limit key of a request.
+///
+/// By default requests are keyed by their client IP. Set an extractor with
+/// `GovernorConfig::key_extractor` to key by anything in the request
+/// instead, e.g. a header or a value put into the extensions by an
+/// authentication layer. The key is used for limits, bans, budgets and
+/// reports; allow- and denylists still match the client IP.
+///
+/// Any `Fn(&Parts) -> Result<String, KeyError>` closure is also a
+/// `KeyExtractor`.
+pub trait KeyExtractor: Send + Sync + 'static {
+    /// Returns the key of the request described by `parts`.
+    fn extract(&self, parts: &Parts) -> Result<String, KeyError>;
+
+    /// Like [`extract`](Self::extract), but also names the strategy that
+    /// produced the key, for extractors combining several like [`KeyChain`].
+    fn extract_named(&self, parts: &Parts) -> Result<(String, Option<&str>), KeyError> {
+        self.extract(parts).map(|key| (key, None))
+    }
+}
+
+impl<F> KeyExtractor for F
+where
+    F: Fn(&Parts) -> Result<String, KeyError> + Send + Sync + 'static,
+{
+    fn extract(&self, parts: &Parts) -> Result<String, KeyError> {
+        self(parts)
+    }
+}
+
+/// Derives the rate-limit key of a request asynchronously.
+///
+/// For keys that need a lookup, e.g. resolving an API key to its tenant in a
+/// cache. Set with `GovernorConfig::async_key_extractor`; bound the lookup
+/// with `GovernorConfig::key_timeout`, after which the future is dropped and
+/// the request falls back to `GovernorConfig::key_fallback` or its client IP.
+///
+/// Any `Fn(&Parts) -> impl Future<Output = Result<String, KeyError>>`
+/// closure is also an `AsyncKeyExtractor`. The future must be `'static`, so
+/// copy what it needs out of `parts` first:
+///
+/// ```rust
+/// # use axum::http::request::Parts;
+/// # use axum_governor::{GovernorConfig, KeyError};
+/// # use std::time::Duration;
+/// # async fn tenant_of(api_key: &str) -> Option<String> { None }
+/// let config = GovernorConfig::new()
+///     .async_key_extractor(|parts: &Parts| {
+///         let api_key = parts.headers.get("x-api-key").cloned();
+///         async move {
+///             let api_key = api_key.ok_or(KeyError::Missing)?;
+///             let api_key = api_key
+///                 .to_str()
+///                 .map_err(|_| KeyError::Invalid("API key is not ASCII".into()))?;
+///             tenant_of(api_key).await.ok_or(KeyError::Missing)
+///         }
+///     })
+///     .key_timeout(Duration::from_millis(20));
+/// ```
+pub trait AsyncKeyExtractor: Send + Sync + 'static {
+    /// Returns the key of the request described by `parts`.
+    fn extract<'a>(&'a self, parts: &'a Parts) -> BoxFuture<'a, Result<String, KeyError>>;
+}
+
+impl<F, Fut> AsyncKeyExtractor for F
+where
+    F: Fn(&Parts) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<String, KeyError>> + Send + 'static,
+{
+    fn extract<'a>(&'a self, parts: &'a Parts) -> BoxFuture<'a, Result<String, KeyError>> {
+        Box::pin(self(parts))
+    }
+}
+
+/// Tries several key extractors in order, the first key found wins.
+///
+/// Each strategy is named, e.g. API key, then JWT subject, then session
+/// cookie, then client IP:
+///
+/// ```rust
+/// # use axum_governor::{
+/// #     ApiKeyExtractor, CookieExtractor, GovernorConfig, KeyChain, RealIpExtractor,
+/// # };
+/// let config = GovernorConfig::new().key_extractor(
+///     KeyChain::new()
+///         .then("api-key", ApiKeyExtractor::default())
+///         .then("session", CookieExtractor::new("session"))
+///         .then("ip", RealIpExtractor),
+/// );
+/// ```
+///
+/// A strategy finding nothing ([`KeyError::Missing`]) passes on to the next
+/// one; an invalid key ([`KeyError::Invalid`]) rejects the request, so a
+/// broken credential doesn't quietly downgrade to a weaker key. The name of
+/// the strategy that matched is logged at debug level and recorded in
+/// [`GovernorTags::key_source`](crate::GovernorTags::key_source). If no
+/// strategy matches, the request is keyed by its client IP.
+#[derive(Default)]
+pub struct KeyChain {
+    strategies: Vec<(String, Box<dyn KeyExtractor>)>,
+}
+
+impl KeyChain {
+    /// Creates an empty chain.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends `extractor` as the strategy `name`.
+    pub fn then(mut self, name: impl Into<String>, extractor: impl KeyExtractor) -> Self {
+        self.strategies.push((name.into(), Box::new(extractor)));
+        self
+    }
+}
+
+impl fmt::Debug for KeyChain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.strategies.iter().map(|(name, _)| name))
+            .finish()
+    }
+}
+
+impl KeyExtractor for KeyChain {
+    fn extract(&self, parts: &Parts) -> Result<String, KeyError> {
+        self.extract_named(parts).map(|(key, _)| key)
+    }
+
+    fn extract_named(&self, parts: &Parts) -> Result<(String, Option<&str>), KeyError> {
+        for (name, extractor) in &self.strategies {
+            match extractor.extract(parts) {
+                Ok(key) => return Ok((key, Some(name))),
+                Err(KeyError::Missing) => continue,
+                Err(invalid) => return Err(invalid),
+            }
+        }
+        Err(KeyError::Missing)
+    }
+}
+
+/// Keys requests by the client IP from the `RealIp` extension.
+///
+/// The default behavior, minus the IPv6 prefix and IPv4-mapped handling
+/// configured on `GovernorConfig`, which only apply without an extractor.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RealIpExtractor;
+
+impl KeyExtractor for RealIpExtractor {
+    fn extract(&self, parts: &Parts) -> Result<String, KeyError> {
+        parts
+            .extensions
+            .get::<RealIp>()
+            .map(|real_ip| real_ip.ip().to_canonical().to_string())
+            .ok_or(KeyError::Missing)
+    }
+}
+
+/// Keys requests by an API key header, e.g. `X-Api-Key`.
+///
+/// Requests without the header fall back to their client IP, so anonymous
+/// traffic is still limited per client. Keys are prefixed with `api-key:` to
+/// keep them apart from IP keys. A header that is not visible ASCII is
+/// rejected with `400 Bad Request`.
+///
+/// The key is not verified: any client can send a made-up key and get a
+/// fresh bucket with each one. Verify keys in the handler or an earlier
+/// layer, or pair this with a limit on unknown keys. Bans and the denylist
+/// still apply to the client IP.
+#[derive(Debug, Clone)]
+pub struct ApiKeyExtractor {
+    header: HeaderName,
+}
+
+impl ApiKeyExtractor {
+    /// Creates an extractor reading `header`.
+    pub fn new(header: HeaderName) -> Self {
+        Self { header }
+    }
+}
+
+impl Default for ApiKeyExtractor {
+    /// Reads `X-Api-Key`.
+    fn default() -> Self {
+        Self::new(HeaderName::from_static("x-api-key"))
+    }
+}
+
+impl KeyExtractor for ApiKeyExtractor {
+    fn extract(&self, parts: &Parts) -> Result<String, KeyError> {
+        let value = parts.headers.get(&self.header).ok_or(KeyError::Missing)?;
+        let key = value
+            .to_str()
+            .map_err(|_| KeyError::Invalid(format!("{} is not visible ASCII", self.header)))?
+            .trim();
+        if key.is_empty() {
+            return Err(KeyError::Missing);
+        }
+        Ok(format!("api-key:{key}"))
+    }
+}
+
+/// Keys requests by a session cookie, e.g. `session_id`.
+///
+/// Logged-in users behind a shared NAT get a bucket each instead of sharing
+/// one per IP. Requests without the cookie fall back to their client IP.
+/// Keys have the form `<cookie name>:<value>`.
+///
+/// Any client can mint cookies, so pair this with a limit on anonymous
+/// traffic or only set the cookie after login.
+#[derive(Debug, Clone)]
+pub struct CookieExtractor {
+    name: String,
+}
+
+impl CookieExtractor {
+    /// Creates an extractor reading the cookie `name`.
+    pub fn new(name: impl Into<String>) -> Self {
+        Self { name: name.into() }
+    }
+}
+
+impl KeyExtractor for CookieExtractor {
+    fn extract(&self, parts: &Parts) -> Result<String, KeyError> {
+        parts
+            .headers
+            .get_all(COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(';'))
+            .filter_map(|cookie| cookie.trim().split_once('='))
+            .find(|(name, value)| *name == self.name && !value.is_empty())
+            .map(|(name, value)| format!("{name}:{}", value.trim_matches('"')))
+            .ok_or(KeyError::Missing)
+    }
+}
+
+/// Keys requests by a typed value an earlier layer put into the extensions.
+///
+/// Lets the governor sit below an authentication layer such as `axum-login`
+/// and limit per user. Requests without the extension, e.g. anonymous ones,
+/// fall back to their client IP:
+///
+/// ```rust
+/// # use axum_governor::{ExtensionExtractor, GovernorConfig};
+/// #[derive(Clone)]
+/// struct UserId(u64);
+///
+/// let config = GovernorConfig::new()
+///     .key_extractor(ExtensionExtractor::new(|user: &UserId| format!("user:{}", user.0)));
+/// ```
+pub struct ExtensionExtractor<T, F> {
+    key: F,
+    _extension: PhantomData<fn() -> T>,
+}
+
+impl<T, F> ExtensionExtractor<T, F>
+where
+    F: Fn(&T) -> String,
+{
+    /// Creates an extractor turning the `T` extension into a key with `key`.
+    pub fn new(key: F) -> Self {
+        Self {
+            key,
+            _extension: PhantomData,
+        }
+    }
+}
+
+impl<T, F: Clone> Clone for ExtensionExtractor<T, F> {
+    fn clone(&self) -> Self {
+        Self {
+            key: self.key.clone(),
+            _extension: PhantomData,
+        }
+    }
+}
+
+impl<T, F> fmt::Debug for ExtensionExtractor<T, F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ExtensionExtractor")
+            .field("extension", &std::any::type_name::<T>())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T, F> KeyExtractor for ExtensionExtractor<T, F>
+where
+    T: Send + Sync + 'static,
+    F: Fn(&T) -> String + Send + Sync + 'static,
+{
+    fn extract(&self, parts: &Parts) -> Result<String, KeyError> {
+        parts
+            .extensions
+            .get::<T>()
+            .map(&self.key)
+            .ok_or(KeyError::Missing)
+    }
+}
+
+/// Keys requests by the username of their `Authorization: Basic` header.
+///
+/// For legacy APIs where brute-force protection per account matters more
+/// than per IP: guesses against one account share a bucket however many
+/// addresses they come from. Keys have the form `basic:<username>`; the
+/// password is never looked at. Requests without basic credentials fall back
+/// to their client IP, undecodable ones are rejected with
+/// `400 Bad Request`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BasicAuthExtractor;
+
+impl KeyExtractor for BasicAuthExtractor {
+    fn extract(&self, parts: &Parts) -> Result<String, KeyError> {
+        let credentials = parts
+            .headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split_once(' '))
+            .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("basic"))
+            .map(|(_, credentials)| credentials.trim())
+            .ok_or(KeyError::Missing)?;
+
+        let invalid = || KeyError::Invalid("malformed basic credentials".into());
+        let decoded = STANDARD.decode(credentials).map_err(|_| invalid())?;
+        let decoded = String::from_utf8(decoded).map_err(|_| invalid())?;
+        match decoded.split_once(':') {
+            Some(("", _)) => Err(KeyError::Missing),
+            Some((username, _)) => Ok(format!("basic:{username}")),
+            None => Err(invalid()),
+        }
+    }
+}
+
+/// Keys requests by a query parameter, e.g. `?api_key=...`.
+///
+/// For older APIs passing credentials in the query string. Requests without
+/// the parameter fall back to their client IP. Keys have the form
+/// `<parameter>:<value>`, with the value percent-decoded.
+///
+/// As with [`ApiKeyExtractor`], the value is not verified and any client can
+/// pick a new one per request, so pair this with a limit on unknown values
+/// or verify them before the governor.
+#[derive(Debug, Clone)]
+pub struct QueryParamExtractor {
+    name: String,
+}
+
+impl QueryParamExtractor {
+    /// Creates an extractor reading the query parameter `name`.
+    pub fn new(name: impl Into<String>) -> Self {
+        Self { name: name.into() }
+    }
+}
+
+impl KeyExtractor for QueryParamExtractor {
+    fn extract(&self, parts: &Parts) -> Result<String, KeyError> {
+        let query = parts.uri.query().ok_or(KeyError::Missing)?;
+        form_urlencoded::parse(query.as_bytes())
+            .find(|(name, value)| *name == self.name && !value.is_empty())
+            .map(|(name, value)| format!("{name}:{value}"))
+            .ok_or(KeyError::Missing)
+    }
+}
+
+/// Keys requests by the client certificate identity a TLS terminator
+/// forwards, e.g. in `X-SSL-Client-CN`.
+///
+/// Machine-to-machine clients authenticated by mTLS get a quota per identity,
+/// however many source IPs they use. Keys have the form `cert:<identity>`.
+/// Requests without the header fall back to their client IP.
+///
+/// The terminator must strip the header from incoming requests and set it
+/// only for verified certificates, or clients can claim any identity.
+#[derive(Debug, Clone)]
+pub struct ClientCertExtractor {
+    header: HeaderName,
+}
+
+impl ClientCertExtractor {
+    /// Creates an extractor reading the identity from `header`.
+    pub fn new(header: HeaderName) -> Self {
+        Self { header }
+    }
+}
+
+impl Default for ClientCertExtractor {
+    /// Reads `X-SSL-Client-CN`.
+    fn default() -> Self {
+        Self::new(HeaderName::from_static("x-ssl-client-cn"))
+    }
+}
+
+impl KeyExtractor for ClientCertExtractor {
+    fn extract(&self, parts: &Parts) -> Result<String, KeyError> {
+        let value = parts.headers.get(&self.header).ok_or(KeyError::Missing)?;
+        // Terminators may forward UTF-8 subjects, so don't insist on ASCII.
+        let identity = String::from_utf8_lossy(value.as_bytes());
+        let identity = identity.trim();
+        if identity.is_empty() || identity == "(null)" {
+            return Err(KeyError::Missing);
+        }
+        Ok(format!("cert:{identity}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::{HeaderValue, Request};
+
+    fn parts(uri: &str, headers: &[(&'static str, &'static [u8])]) -> Parts {
+        let mut request = Request::get(uri).body(()).unwrap();
+        for (name, value) in headers {
+            request.headers_mut().append(
+                HeaderName::from_static(name),
+                HeaderValue::from_bytes(value).unwrap(),
+            );
+        }
+        request.into_parts().0
+    }
+
+    #[test]
+    fn closures_are_extractors() {
+        let extractor = |parts: &Parts| {
+            parts
+                .headers
+                .get("x-user")
+                .and_then(|value| value.to_str().ok())
+                .map(|user| format!("user:{user}"))
+                .ok_or(KeyError::Missing)
+        };
+        assert_eq!(
+            extractor.extract(&parts("/", &[("x-user", b"42")])),
+            Ok("user:42".to_string())
+        );
+        assert_eq!(extractor.extract(&parts("/", &[])), Err(KeyError::Missing));
+    }
+
+    #[test]
+    fn api_key_from_header() {
+        let extractor = ApiKeyExtractor::default();
+        assert_eq!(
+            extractor.extract(&parts("/", &[("x-api-key", b" abc ")])),
+            Ok("api-key:abc".to_string())
+        );
+        assert_eq!(extractor.extract(&parts("/", &[])), Err(KeyError::Missing));
+        assert_eq!(
+            extractor.extract(&parts("/", &[("x-api-key", b"  ")])),
+            Err(KeyError::Missing)
+        );
+        assert!(matches!(
+            extractor.extract(&parts("/", &[("x-api-key", b"caf\xc3\xa9")])),
+            Err(KeyError::Invalid(_))
+        ));
+
+        let extractor = ApiKeyExtractor::new(HeaderName::from_static("x-customer"));
+        assert_eq!(
+            extractor.extract(&parts("/", &[("x-customer", b"acme")])),
+            Ok("api-key:acme".to_string())
+        );
+    }
+
+    #[test]
+    fn cookie_by_name() {
+        let extractor = CookieExtractor::new("session_id");
+        assert_eq!(
+            extractor.extract(&parts(
+                "/",
+                &[("cookie", b"theme=dark; session_id=\"abc123\"; lang=en")]
+            )),
+            Ok("session_id:abc123".to_string())
+        );
+        // Cookies may be split over several headers.
+        assert_eq!(
+            extractor.extract(&parts(
+                "/",
+                &[("cookie", b"theme=dark"), ("cookie", b"session_id=xyz")]
+            )),
+            Ok("session_id:xyz".to_string())
+        );
+        for cookies in [&b"theme=dark"[..], b"session_id=", b"my_session_id=abc"] {
+            assert_eq!(
+                extractor.extract(&parts("/", &[("cookie", cookies)])),
+                Err(KeyError::Missing)
+            );
+        }
+        assert_eq!(extractor.extract(&parts("/", &[])), Err(KeyError::Missing));
+    }
+
+    #[test]
+    fn extension_set_by_earlier_layer() {
+        #[derive(Clone)]
+        struct UserId(u64);
+
+        let extractor = ExtensionExtractor::new(|user: &UserId| format!("user:{}", user.0));
+        let mut request = parts("/", &[]);
+        assert_eq!(extractor.extract(&request), Err(KeyError::Missing));
+
+        request.extensions.insert(UserId(7));
+        assert_eq!(extractor.extract(&request), Ok("user:7".to_string()));
+    }
+
+    #[test]
+    fn basic_auth_username() {
+        let extractor = BasicAuthExtractor;
+        for authorization in [&b"Basic YWxpY2U6c2VjcmV0"[..], b"basic  YWxpY2U6c2VjcmV0 "] {
+            assert_eq!(
+                extractor.extract(&parts("/", &[("authorization", authorization)])),
+                Ok("basic:alice".to_string())
+            );
+        }
+        // No credentials, another scheme or an empty username.
+        for headers in [
+            &[][..],
+            &[("authorization", &b"Bearer YWxpY2U6c2VjcmV0"[..])],
+            &[("authorization", b"Basic OnB3")],
+        ] {
+            assert_eq!(
+                extractor.extract(&parts("/", headers)),
+                Err(KeyError::Missing)
+            );
+        }
+        // Not base64, not UTF-8, or without a password separator.
+        for credentials in [&b"Basic !!!"[..], b"Basic /zpwdw==", b"Basic YWxpY2U="] {
+            assert!(matches!(
+                extractor.extract(&parts("/", &[("authorization", credentials)])),
+                Err(KeyError::Invalid(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn query_param_by_name() {
+        let extractor = QueryParamExtractor::new("api_key");
+        assert_eq!(
+            extractor.extract(&parts("/items?page=2&api_key=abc%2F1+2", &[])),
+            Ok("api_key:abc/1 2".to_string())
+        );
+        for uri in [
+            "/items",
+            "/items?page=2",
+            "/items?api_key=",
+            "/items?x_api_key=abc",
+        ] {
+            assert_eq!(extractor.extract(&parts(uri, &[])), Err(KeyError::Missing));
+        }
+    }
+
+    #[test]
+    fn client_cert_identity() {
+        let extractor = ClientCertExtractor::default();
+        assert_eq!(
+            extractor.extract(&parts("/", &[("x-ssl-client-cn", b" billing.internal ")])),
+            Ok("cert:billing.internal".to_string())
+        );
+        assert_eq!(
+            extractor.extract(&parts("/", &[("x-ssl-client-cn", b"M\xc3\xbcller GmbH")])),
+            Ok("cert:Müller GmbH".to_string())
+        );
+        for headers in [
+            &[][..],
+            &[("x-ssl-client-cn", &b""[..])],
+            &[("x-ssl-client-cn", b"(null)")],
+        ] {
+            assert_eq!(
+                extractor.extract(&parts("/", headers)),
+                Err(KeyError::Missing)
+            );
+        }
+
+        let extractor = ClientCertExtractor::new(HeaderName::from_static("x-client-dn"));
+        assert_eq!(
+            extractor.extract(&parts("/", &[("x-client-dn", b"CN=billing")])),
+            Ok("cert:CN=billing".to_string())
+        );
+    }
+
+    #[test]
+    fn chain_takes_first_match() {
+        let chain = KeyChain::new()
+            .then("api-key", ApiKeyExtractor::default())
+            .then("session", CookieExtractor::new("session"));
+
+        let request = parts("/", &[("x-api-key", b"abc"), ("cookie", b"session=s1")]);
+        assert_eq!(
+            chain.extract_named(&request),
+            Ok(("api-key:abc".to_string(), Some("api-key")))
+        );
+        assert_eq!(chain.extract(&request), Ok("api-key:abc".to_string()));
+
+        let request = parts("/", &[("cookie", b"session=s1")]);
+        assert_eq!(
+            chain.extract_named(&request),
+            Ok(("session:s1".to_string(), Some("session")))
+        );
+
+        assert_eq!(
+            chain.extract_named(&parts("/", &[])),
+            Err(KeyError::Missing)
+        );
+        assert_eq!(
+            KeyChain::new().extract(&parts("/", &[])),
+            Err(KeyError::Missing)
+        );
+        assert_eq!(format!("{chain:?}"), r#"["api-key", "session"]"#);
+    }
+
+    #[test]
+    fn chain_stops_at_invalid_key() {
+        let chain = KeyChain::new()
+            .then("api-key", ApiKeyExtractor::default())
+            .then("session", CookieExtractor::new("session"));
+        let request = parts(
+            "/",
+            &[("x-api-key", b"caf\xc3\xa9"), ("cookie", b"session=s1")],
+        );
+        assert!(matches!(
+            chain.extract_named(&request),
+            Err(KeyError::Invalid(_))
+        ));
+    }
+}
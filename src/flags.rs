@@ -0,0 +1,38 @@
+/* src/flags.rs */
+
+use axum::http::request::Parts;
+use std::sync::Arc;
+
+/// A function deriving [`GovernorFlags`] from a request, see
+/// [`GovernorConfig::flags_from`](crate::GovernorConfig::flags_from).
+pub type FlagsResolver = Arc<dyn Fn(&Parts) -> GovernorFlags + Send + Sync>;
+
+/// Per-request switches for the governor.
+///
+/// Insert as a request extension from an earlier middleware, or derive from
+/// the request with `GovernorConfig::flags_from`, to change how single
+/// requests are handled while integrating the governor step by step:
+///
+/// ```rust
+/// # use axum::{extract::Request, middleware::Next, response::Response};
+/// # use axum_governor::GovernorFlags;
+/// async fn mark_internal(mut request: Request, next: Next) -> Response {
+///     request.extensions_mut().insert(GovernorFlags {
+///         trusted: true,
+///         ..GovernorFlags::default()
+///     });
+///     next.run(request).await
+/// }
+/// ```
+///
+/// An extension takes precedence over `flags_from`. Never derive `trusted`
+/// from headers a client can set.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GovernorFlags {
+    /// Leaves the `x-governor-rejected` header off rejections.
+    pub skip_headers: bool,
+    /// Lets the request through if it exceeds its limit, as in shadow mode.
+    pub force_shadow: bool,
+    /// Exempts the request from limits. Bans and the denylist still apply.
+    pub trusted: bool,
+}
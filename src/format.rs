@@ -0,0 +1,318 @@
+/* src/format.rs */
+
+use crate::{usage::json_escape, Rejection};
+use axum::{
+    body::Body,
+    http::{
+        header::{ACCEPT, CONTENT_TYPE, RETRY_AFTER},
+        HeaderMap, HeaderValue, Response, StatusCode,
+    },
+};
+use std::time::Duration;
+
+/// The body format of governor-produced error responses.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ErrorFormat {
+    /// A plain text message, e.g. `Too Many Requests`.
+    #[default]
+    Text,
+    /// A JSON object for SPA and API consumers, e.g.
+    /// `{"error":"rate_limited","message":"Too Many Requests","retry_after":3}`,
+    /// with `Content-Type: application/json`. The `error` member is the
+    /// [`Rejection::as_str`] of the reason with underscores. `retry_after`
+    /// is in seconds and only present when known, as is the `Retry-After`
+    /// header.
+    Json,
+    /// An RFC 7807 problem details object with
+    /// `Content-Type: application/problem+json`, e.g.
+    /// `{"type":"about:blank","title":"Too Many Requests","status":429,
+    /// "detail":"Too Many Requests","rejection":"rate-limited","retry_after":3}`,
+    /// where `retry_after` is present as for `Json`.
+    ProblemJson,
+    /// A small HTML page for browsers.
+    Html,
+    /// `Html`, `Json`, `ProblemJson` or `Text`, whichever the request's
+    /// `Accept` header prefers. Browsers get a page, API clients get JSON,
+    /// and everything else gets plain text.
+    Negotiate,
+}
+
+/// Templates replacing the built-in bodies of [`ErrorFormat`]s.
+///
+/// Templates may contain the placeholders `{status}` (e.g. `429`), `{title}`
+/// (e.g. `Too Many Requests`), `{message}` and `{error}` (the
+/// [`Rejection::as_str`] of the reason). Values are HTML-escaped in the HTML
+/// template and JSON-escaped in the JSON template.
+#[derive(Debug, Clone, Default)]
+pub struct ErrorTemplates {
+    html: Option<String>,
+    json: Option<String>,
+    text: Option<String>,
+}
+
+impl ErrorTemplates {
+    /// Creates an empty set, keeping every built-in body.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the template of `ErrorFormat::Html` bodies.
+    pub fn html(mut self, template: impl Into<String>) -> Self {
+        self.html = Some(template.into());
+        self
+    }
+
+    /// Sets the template of `ErrorFormat::Json` bodies.
+    pub fn json(mut self, template: impl Into<String>) -> Self {
+        self.json = Some(template.into());
+        self
+    }
+
+    /// Sets the template of `ErrorFormat::Text` bodies.
+    pub fn text(mut self, template: impl Into<String>) -> Self {
+        self.text = Some(template.into());
+        self
+    }
+}
+
+impl ErrorFormat {
+    /// Resolves `Negotiate` against the `Accept` header in `headers`.
+    pub(crate) fn negotiate(self, headers: &HeaderMap) -> ErrorFormat {
+        if self != ErrorFormat::Negotiate {
+            return self;
+        }
+
+        let mut best = (ErrorFormat::Text, 0.0);
+        let accept = headers.get_all(ACCEPT).iter();
+        for range in accept.filter_map(|value| value.to_str().ok()) {
+            for entry in range.split(',') {
+                let mut params = entry.split(';').map(str::trim);
+                let format = match params.next().unwrap_or_default() {
+                    "text/html" => ErrorFormat::Html,
+                    "application/json" => ErrorFormat::Json,
+                    "application/problem+json" => ErrorFormat::ProblemJson,
+                    "text/plain" => ErrorFormat::Text,
+                    _ => continue,
+                };
+                let quality = params
+                    .find_map(|param| param.strip_prefix("q="))
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .unwrap_or(1.0);
+                if quality > best.1 {
+                    best = (format, quality);
+                }
+            }
+        }
+        best.0
+    }
+
+    /// Builds an error response with `message` in this format, advising a
+    /// retry after `retry_after` seconds if known.
+    pub(crate) fn response(
+        self,
+        status: StatusCode,
+        rejection: Rejection,
+        message: &str,
+        retry_after: Option<u64>,
+        templates: &ErrorTemplates,
+    ) -> Response<Body> {
+        let title = status.canonical_reason().unwrap_or_default();
+        let fill = |template: &str, escape: fn(&str) -> String| {
+            template
+                .replace("{status}", status.as_str())
+                .replace("{title}", &escape(title))
+                .replace("{error}", rejection.as_str())
+                .replace("{message}", &escape(message))
+        };
+        let retry_member = retry_after
+            .map(|secs| format!(",\"retry_after\":{secs}"))
+            .unwrap_or_default();
+
+        let (content_type, body) = match self {
+            ErrorFormat::Text | ErrorFormat::Negotiate => match &templates.text {
+                Some(template) => ("text/plain; charset=utf-8", fill(template, str::to_string)),
+                None => ("text/plain; charset=utf-8", message.to_string()),
+            },
+            ErrorFormat::Json => match &templates.json {
+                Some(template) => ("application/json", fill(template, json_escape)),
+                None => (
+                    "application/json",
+                    format!(
+                        "{{\"error\":\"{}\",\"message\":\"{}\"{}}}",
+                        rejection.as_str().replace('-', "_"),
+                        json_escape(message),
+                        retry_member
+                    ),
+                ),
+            },
+            ErrorFormat::ProblemJson => (
+                "application/problem+json",
+                format!(
+                    "{{\"type\":\"about:blank\",\"title\":\"{}\",\"status\":{},\"detail\":\"{}\",\"rejection\":\"{}\"{}}}",
+                    title,
+                    status.as_u16(),
+                    json_escape(message),
+                    rejection.as_str(),
+                    retry_member
+                ),
+            ),
+            ErrorFormat::Html => {
+                let template = templates.html.as_deref().unwrap_or(
+                    "<!DOCTYPE html><html><head><title>{title}</title></head>\
+                     <body><h1>{title}</h1><p>{message}</p></body></html>",
+                );
+                ("text/html; charset=utf-8", fill(template, html_escape))
+            }
+        };
+
+        let mut response = Response::builder()
+            .status(status)
+            .header(CONTENT_TYPE, content_type)
+            .body(Body::from(body))
+            .unwrap();
+        if let Some(secs) = retry_after {
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from(secs));
+        }
+        response
+    }
+}
+
+/// Rounds `duration` up to whole seconds, as sent in `Retry-After`.
+pub(crate) fn whole_seconds(duration: Duration) -> u64 {
+    duration.as_secs() + u64::from(duration.subsec_nanos() > 0)
+}
+
+fn html_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn body(response: Response<Body>) -> String {
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    fn respond(format: ErrorFormat, retry_after: Option<u64>) -> Response<Body> {
+        format.response(
+            StatusCode::TOO_MANY_REQUESTS,
+            Rejection::RateLimited,
+            "Too Many Requests",
+            retry_after,
+            &ErrorTemplates::new(),
+        )
+    }
+
+    #[tokio::test]
+    async fn retry_after_when_known() {
+        let response = respond(ErrorFormat::ProblemJson, Some(7));
+        assert_eq!(response.headers()[RETRY_AFTER], "7");
+        assert_eq!(
+            body(response).await,
+            "{\"type\":\"about:blank\",\"title\":\"Too Many Requests\",\"status\":429,\
+             \"detail\":\"Too Many Requests\",\"rejection\":\"rate-limited\",\"retry_after\":7}"
+        );
+
+        let response = respond(ErrorFormat::Text, Some(7));
+        assert_eq!(response.headers()[RETRY_AFTER], "7");
+        assert_eq!(body(response).await, "Too Many Requests");
+    }
+
+    #[tokio::test]
+    async fn no_retry_after_when_unknown() {
+        let response = respond(ErrorFormat::ProblemJson, None);
+        assert!(!response.headers().contains_key(RETRY_AFTER));
+        assert!(!body(response).await.contains("retry_after"));
+    }
+
+    #[test]
+    fn rounds_up_to_whole_seconds() {
+        assert_eq!(whole_seconds(Duration::from_secs(3)), 3);
+        assert_eq!(whole_seconds(Duration::from_millis(2001)), 3);
+        assert_eq!(whole_seconds(Duration::ZERO), 0);
+    }
+
+    fn accept(values: &[&'static str]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for value in values {
+            headers.append(ACCEPT, HeaderValue::from_static(value));
+        }
+        headers
+    }
+
+    #[test]
+    fn negotiates_from_accept() {
+        let negotiate = |values| ErrorFormat::Negotiate.negotiate(&accept(values));
+        assert_eq!(negotiate(&[]), ErrorFormat::Text);
+        assert_eq!(negotiate(&["*/*"]), ErrorFormat::Text);
+        assert_eq!(
+            negotiate(&["text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8"]),
+            ErrorFormat::Html
+        );
+        assert_eq!(negotiate(&["application/json"]), ErrorFormat::Json);
+        assert_eq!(
+            negotiate(&["application/problem+json, application/json;q=0.5"]),
+            ErrorFormat::ProblemJson
+        );
+        assert_eq!(
+            negotiate(&["text/html;q=0.2", "application/json;q=0.8"]),
+            ErrorFormat::Json
+        );
+        assert_eq!(negotiate(&["text/plain, text/html"]), ErrorFormat::Text);
+    }
+
+    #[test]
+    fn fixed_formats_ignore_accept() {
+        let headers = accept(&["text/html"]);
+        assert_eq!(ErrorFormat::Json.negotiate(&headers), ErrorFormat::Json);
+    }
+
+    #[tokio::test]
+    async fn fills_templates() {
+        let templates = ErrorTemplates::new()
+            .html("<p>{status} {title}: {message} ({error})</p>")
+            .json("{\"code\":{status},\"message\":\"{message}\"}")
+            .text("{title}");
+        let respond = |format: ErrorFormat, message: &str| {
+            format.response(
+                StatusCode::TOO_MANY_REQUESTS,
+                Rejection::RateLimited,
+                message,
+                None,
+                &templates,
+            )
+        };
+
+        let response = respond(ErrorFormat::Html, "<b>\"slow\"</b>");
+        assert_eq!(response.headers()[CONTENT_TYPE], "text/html; charset=utf-8");
+        assert_eq!(
+            body(response).await,
+            "<p>429 Too Many Requests: &lt;b&gt;&quot;slow&quot;&lt;/b&gt; (rate-limited)</p>"
+        );
+        let response = respond(ErrorFormat::Json, "\"slow\"");
+        assert_eq!(response.headers()[CONTENT_TYPE], "application/json");
+        assert_eq!(
+            body(response).await,
+            "{\"code\":429,\"message\":\"\\\"slow\\\"\"}"
+        );
+        let response = respond(ErrorFormat::Text, "slow");
+        assert_eq!(body(response).await, "Too Many Requests");
+    }
+}
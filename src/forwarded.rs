@@ -0,0 +1,242 @@
+/* src/forwarded.rs */
+
+use axum::http::{HeaderMap, HeaderName};
+use std::net::{IpAddr, SocketAddr};
+
+const X_REAL_IP: HeaderName = HeaderName::from_static("x-real-ip");
+const X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+const FORWARDED: HeaderName = HeaderName::from_static("forwarded");
+
+/// Checks the forwarded-IP headers for values a spoofing client could use to
+/// confuse IP extraction.
+///
+/// Rejects headers that are not valid UTF-8, addresses that don't parse,
+/// multiple `X-Real-IP` headers that disagree, and `X-Forwarded-For` and
+/// `Forwarded` headers naming different addresses for the same hop.
+///
+/// With `trusted_hops` set, only the last `trusted_hops` entries of
+/// `X-Forwarded-For` and `Forwarded` are checked: those were appended by the
+/// trusted proxies, while anything before them comes from the client and is
+/// never used for the client IP. RFC 7239 `unknown` and obfuscated (`_name`)
+/// identifiers are accepted. Returns a short description of the first problem
+/// found.
+pub(crate) fn validate(
+    headers: &HeaderMap,
+    trusted_hops: Option<usize>,
+) -> Result<(), &'static str> {
+    let mut real_ip = None;
+    for value in headers.get_all(X_REAL_IP) {
+        let ip = value
+            .to_str()
+            .ok()
+            .and_then(|value| parse_addr(value.trim()))
+            .ok_or("malformed X-Real-IP header")?;
+        if real_ip.is_some_and(|seen| seen != ip) {
+            return Err("conflicting X-Real-IP headers");
+        }
+        real_ip = Some(ip);
+    }
+
+    let mut forwarded_for = Vec::new();
+    for value in headers.get_all(X_FORWARDED_FOR) {
+        let value = value
+            .to_str()
+            .map_err(|_| "malformed X-Forwarded-For header")?;
+        forwarded_for.extend(value.split(',').map(str::trim));
+    }
+    let forwarded_for = trusted(&forwarded_for, trusted_hops)
+        .iter()
+        .map(|node| parse_node(node).ok_or("malformed X-Forwarded-For header"))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut forwarded = Vec::new();
+    for value in headers.get_all(FORWARDED) {
+        let value = value.to_str().map_err(|_| "malformed Forwarded header")?;
+        for element in value.split(',') {
+            forwarded.push(for_node(element).ok_or("malformed Forwarded header")?);
+        }
+    }
+    let forwarded = trusted(&forwarded, trusted_hops)
+        .iter()
+        .map(|node| match node {
+            Some(node) => parse_node(node).ok_or("malformed Forwarded header"),
+            None => Ok(None),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // Compare hop by hop, starting from the closest proxy.
+    let conflict = forwarded_for
+        .iter()
+        .rev()
+        .zip(forwarded.iter().rev())
+        .any(|pair| matches!(pair, (Some(a), Some(b)) if a != b));
+    if conflict {
+        return Err("conflicting X-Forwarded-For and Forwarded headers");
+    }
+
+    Ok(())
+}
+
+/// Returns the entries appended by the last `trusted_hops` proxies.
+fn trusted<T>(entries: &[T], trusted_hops: Option<usize>) -> &[T] {
+    match trusted_hops {
+        Some(hops) => &entries[entries.len().saturating_sub(hops)..],
+        None => entries,
+    }
+}
+
+/// Returns the `for` node of a `Forwarded` element, `Some(None)` if it has
+/// none, or `None` if the element is malformed.
+fn for_node(element: &str) -> Option<Option<&str>> {
+    let mut node = None;
+    for pair in element.split(';') {
+        let (name, value) = pair.trim().split_once('=')?;
+        if name.trim().eq_ignore_ascii_case("for") {
+            node = Some(value.trim().trim_matches('"'));
+        }
+    }
+    Some(node)
+}
+
+/// Parses a node identifier: an address, optionally with port, or an
+/// `unknown` or obfuscated (`_name`) identifier, for which there is no
+/// address. Returns `None` if the node is malformed.
+fn parse_node(node: &str) -> Option<Option<IpAddr>> {
+    if node.eq_ignore_ascii_case("unknown") || is_obfuscated(node) {
+        return Some(None);
+    }
+    // `[2001:db8::1]`, `[2001:db8::1]:443` or `[2001:db8::1]:_port`
+    let host = match node.strip_prefix('[') {
+        Some(rest) => {
+            let (ip, port) = rest.split_once(']')?;
+            if let Some(port) = port.strip_prefix(':')
+                && !is_obfuscated(port)
+                && port.parse::<u16>().is_err()
+            {
+                return None;
+            }
+            return ip.parse().ok().map(Some);
+        }
+        None => node,
+    };
+    if let Some(addr) = parse_addr(host) {
+        return Some(Some(addr));
+    }
+    // `192.0.2.1:_port`
+    let (ip, port) = host.rsplit_once(':')?;
+    let ip = ip.parse::<IpAddr>().ok()?;
+    is_obfuscated(port).then_some(Some(ip))
+}
+
+/// Returns whether `value` is an RFC 7239 obfuscated identifier, e.g.
+/// `_hidden`.
+fn is_obfuscated(value: &str) -> bool {
+    value.len() > 1
+        && value.starts_with('_')
+        && value
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || matches!(byte, b'.' | b'_' | b'-'))
+}
+
+/// Parses a bare IP address or an `ip:port` pair.
+fn parse_addr(value: &str) -> Option<IpAddr> {
+    value
+        .parse::<IpAddr>()
+        .ok()
+        .or_else(|| value.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn header_map(pairs: &[(HeaderName, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(name, HeaderValue::from_static(value));
+        }
+        headers
+    }
+
+    #[test]
+    fn accepts_well_formed_headers() {
+        let headers = header_map(&[
+            (X_REAL_IP, "192.0.2.60"),
+            (X_FORWARDED_FOR, "192.0.2.60, 198.51.100.17"),
+            (
+                FORWARDED,
+                "for=192.0.2.60;proto=http, for=\"198.51.100.17:4711\"",
+            ),
+        ]);
+        assert_eq!(validate(&headers, None), Ok(()));
+
+        let headers = header_map(&[(FORWARDED, "for=\"[2001:db8:cafe::17]:4711\"")]);
+        assert_eq!(validate(&headers, None), Ok(()));
+    }
+
+    #[test]
+    fn accepts_unknown_and_obfuscated_nodes() {
+        let headers = header_map(&[
+            (X_FORWARDED_FOR, "unknown, _hidden, 198.51.100.17"),
+            (
+                FORWARDED,
+                "for=unknown, for=\"_SEVKISEK\", for=\"198.51.100.17:_port\"",
+            ),
+        ]);
+        assert_eq!(validate(&headers, None), Ok(()));
+    }
+
+    #[test]
+    fn rejects_malformed_nodes() {
+        for (name, value) in [
+            (X_REAL_IP, "not-an-ip"),
+            (X_FORWARDED_FOR, "192.0.2.60, garbage"),
+            (FORWARDED, "for=garbage"),
+            (FORWARDED, "for=_"),
+            (FORWARDED, "for=\"[2001:db8::1]:http\""),
+            (FORWARDED, "for"),
+        ] {
+            let headers = header_map(&[(name.clone(), value)]);
+            assert!(validate(&headers, None).is_err(), "{name}: {value}");
+        }
+    }
+
+    #[test]
+    fn ignores_entries_beyond_trusted_hops() {
+        let headers = header_map(&[
+            (X_FORWARDED_FOR, "spoofed, 192.0.2.60, 198.51.100.17"),
+            (FORWARDED, "for=spoofed, for=192.0.2.60, for=198.51.100.17"),
+        ]);
+        assert_eq!(validate(&headers, Some(2)), Ok(()));
+        assert!(validate(&headers, Some(3)).is_err());
+        assert!(validate(&headers, None).is_err());
+    }
+
+    #[test]
+    fn rejects_conflicting_values() {
+        let headers = header_map(&[(X_REAL_IP, "192.0.2.60"), (X_REAL_IP, "192.0.2.61")]);
+        assert_eq!(
+            validate(&headers, None),
+            Err("conflicting X-Real-IP headers")
+        );
+
+        let headers = header_map(&[
+            (X_FORWARDED_FOR, "203.0.113.9, 192.0.2.60"),
+            (FORWARDED, "for=198.51.100.1, for=192.0.2.61"),
+        ]);
+        let conflict = Err("conflicting X-Forwarded-For and Forwarded headers");
+        assert_eq!(validate(&headers, Some(1)), conflict);
+        assert_eq!(validate(&headers, None), conflict);
+    }
+
+    #[test]
+    fn allows_differences_beyond_trusted_hops() {
+        let headers = header_map(&[
+            (X_FORWARDED_FOR, "203.0.113.9, 192.0.2.60"),
+            (FORWARDED, "for=198.51.100.1, for=192.0.2.60"),
+        ]);
+        assert_eq!(validate(&headers, Some(1)), Ok(()));
+        assert!(validate(&headers, Some(2)).is_err());
+    }
+}
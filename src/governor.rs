@@ -0,0 +1,670 @@
+/* src/governor.rs */
+
+use crate::{
+    codec::fnv1a,
+    count_budget::CountBudget,
+    reservation::{Charge, Ledger},
+    ErrorFormat, ErrorTemplates, GovernorConfig, GovernorLayer, Rejection, Reservation,
+};
+use axum::{
+    body::Body,
+    http::{Response, StatusCode},
+};
+use ipnet::IpNet;
+use std::{
+    collections::{HashMap, HashSet},
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, MutexGuard, PoisonError, RwLock,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tracing::warn;
+
+/// A runtime control handle shared by every layer it is attached to.
+///
+/// `Governor` is cheap to clone; all clones refer to the same state. Attach it
+/// to layers with `GovernorConfig::governor` and keep a clone around to act on
+/// them at runtime, e.g. to ban an abusive client.
+///
+/// A `Governor` can also hold named groups of configuration, defined once and
+/// selected by each sub-router with [`layer_for_group`](Self::layer_for_group),
+/// and named budgets shared by several paths, see [`budget`](Self::budget).
+#[derive(Debug, Clone, Default)]
+pub struct Governor {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    bans: Mutex<HashMap<String, Ban>>,
+    groups: Mutex<HashMap<String, GovernorConfig>>,
+    shadow_mode: AtomicBool,
+    allowlist: RwLock<Vec<IpNet>>,
+    denylist: RwLock<Vec<IpNet>>,
+    budgets: Mutex<HashMap<String, CountBudget>>,
+    /// Undefined budget names already warned about.
+    undefined_budgets: Mutex<HashSet<String>>,
+    reservations: Ledger,
+}
+
+/// An active ban placed with [`Governor::ban`].
+#[derive(Debug, Clone)]
+pub struct Ban {
+    /// Why the key was banned, for operators and logs.
+    pub reason: String,
+    /// Short reference shown to the banned client, so support can look up the
+    /// ban without the client knowing its key.
+    pub reference: String,
+    /// When the ban lifts.
+    pub until: Instant,
+}
+
+/// State carried over from another rate limiter, see [`Governor::import`].
+#[derive(Debug, Clone)]
+pub enum ImportRecord {
+    /// An active ban of `key`, lifting after `remaining`.
+    Ban {
+        /// The client key, e.g. the client IP.
+        key: String,
+        /// How long the ban has left to run.
+        remaining: Duration,
+        /// Why the key was banned.
+        reason: String,
+    },
+    /// Usage `key` already accrued against the budget `budget`.
+    Usage {
+        /// The name of a budget defined with [`Governor::budget`].
+        budget: String,
+        /// The client key, e.g. the client IP.
+        key: String,
+        /// The requests already spent in the current window.
+        used: u64,
+    },
+}
+
+impl Governor {
+    /// Creates a new handle with no bans.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Defines the configuration of the group `name`, replacing any previous
+    /// definition.
+    ///
+    /// The group name becomes the configuration's tier unless one is set.
+    pub fn group(self, name: impl Into<String>, mut config: GovernorConfig) -> Self {
+        let name = name.into();
+        config.tier.get_or_insert_with(|| name.clone());
+        self.inner
+            .groups
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(name, config);
+        self
+    }
+
+    /// Defines the budget `name` of `limit` requests per `window` and key,
+    /// replacing any previous definition.
+    ///
+    /// Paths assigned to the budget with `GovernorConfig::budget` draw from
+    /// it together, so e.g. `/export/csv` and `/export/pdf` can share 5
+    /// exports per minute and alternating between them gains nothing. The
+    /// budget applies on top of the `lazy-limit` rules.
+    pub fn budget(self, name: impl Into<String>, limit: u64, window: Duration) -> Self {
+        self.inner
+            .budgets
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(name.into(), CountBudget::new(limit, window));
+        self
+    }
+
+    /// Charges `cost` to `key` in the budget `name`, returning `false` if
+    /// that would exceed it. A refused charge is not recorded.
+    ///
+    /// Lets code paths that are not routes, e.g. GraphQL resolvers or work
+    /// triggered in the background on behalf of a user, draw from the same
+    /// budgets the middleware enforces:
+    ///
+    /// ```rust
+    /// # use axum_governor::Governor;
+    /// # use std::time::Duration;
+    /// let governor = Governor::new().budget("exports", 5, Duration::from_secs(60));
+    /// if !governor.charge_budget("exports", "203.0.113.7", 2) {
+    ///     // Refuse the export.
+    /// }
+    /// ```
+    ///
+    /// The middleware charges 1 per request under the client key, i.e. the
+    /// client IP, so use the same key to share an allowance with it.
+    /// Undefined budgets are never exceeded.
+    pub fn charge_budget(&self, name: &str, key: &str, cost: u64) -> bool {
+        self.inner.reservations.expire();
+        self.budget_named(name)
+            .is_none_or(|budget| budget.charge(key, cost))
+    }
+
+    /// Like [`charge_budget`](Self::charge_budget), but appends the charge to
+    /// `charges` so it can be refunded.
+    pub(crate) fn record_budget(
+        &self,
+        name: &str,
+        key: &str,
+        cost: u64,
+        charges: &mut Vec<Charge>,
+    ) -> bool {
+        self.inner.reservations.expire();
+        self.budget_named(name)
+            .is_none_or(|budget| Charge::record(&budget, key, cost, charges))
+    }
+
+    /// Reserves `cost` for `key` in the budget `name`, returning `None` if
+    /// that would exceed it.
+    ///
+    /// Unlike [`charge_budget`](Self::charge_budget), the cost can be given
+    /// back if a long-running operation aborts early:
+    ///
+    /// ```rust
+    /// # use axum_governor::Governor;
+    /// # use std::time::Duration;
+    /// # fn render_report() -> Result<(), ()> { Ok(()) }
+    /// let governor = Governor::new().budget("reports", 10, Duration::from_secs(3600));
+    /// if let Some(reservation) = governor.reserve("reports", "user-42", 3) {
+    ///     match render_report() {
+    ///         Ok(()) => reservation.commit(),
+    ///         Err(()) => reservation.cancel(),
+    ///     }
+    /// }
+    /// ```
+    ///
+    /// Requests charged by the middleware itself are not reservations and
+    /// are never given back. Undefined budgets are never exceeded.
+    pub fn reserve(&self, name: &str, key: &str, cost: u64) -> Option<Reservation> {
+        let Some(budget) = self.budget_named(name) else {
+            return Some(Reservation::unlimited());
+        };
+        self.inner.reservations.reserve(budget, key, cost)
+    }
+
+    /// Sets how long a reservation may stay open before it expires and its
+    /// cost is given back. Defaults to 10 minutes.
+    pub fn reservation_ttl(self, ttl: Duration) -> Self {
+        self.inner.reservations.set_ttl(ttl);
+        self
+    }
+
+    /// Returns how many reservations expired without being committed or
+    /// cancelled, hinting at leaks in handler code.
+    pub fn expired_reservations(&self) -> u64 {
+        self.inner.reservations.expired()
+    }
+
+    /// Creates a `GovernorLayer` for `config` with this handle attached.
+    pub fn layer(&self, config: GovernorConfig) -> GovernorLayer {
+        GovernorLayer::new(config.governor(self.clone()))
+    }
+
+    /// Creates a `GovernorLayer` from the configuration of the group `name`,
+    /// with this handle attached.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no group `name` was defined with [`group`](Self::group).
+    pub fn layer_for_group(&self, name: &str) -> GovernorLayer {
+        let config = self
+            .inner
+            .groups
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(name)
+            .cloned()
+            .unwrap_or_else(|| panic!("governor group `{name}` is not defined"));
+        self.layer(config)
+    }
+
+    /// Turns shadow mode on or off for every attached layer.
+    ///
+    /// In shadow mode, requests that exceed their limit are still let through.
+    /// They are logged and recorded as denied in any `DecisionLog`, so new
+    /// limits can be evaluated against production traffic. Bans stay in force.
+    pub fn set_shadow_mode(&self, enabled: bool) {
+        self.inner.shadow_mode.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Returns whether shadow mode is on.
+    pub fn shadow_mode(&self) -> bool {
+        self.inner.shadow_mode.load(Ordering::Relaxed)
+    }
+
+    /// Adds a network to the allowlist, e.g. `"203.0.113.0/24".parse()?`.
+    ///
+    /// Allowlisted clients are never limited, banned or denylisted, which lets
+    /// an operator unblock a misclassified partner without a deploy. Takes
+    /// effect immediately in every attached layer.
+    pub fn allowlist_add(&self, network: impl Into<IpNet>) {
+        add_network(&self.inner.allowlist, network.into());
+    }
+
+    /// Removes a network from the allowlist, returning whether it was listed.
+    pub fn allowlist_remove(&self, network: impl Into<IpNet>) -> bool {
+        remove_network(&self.inner.allowlist, network.into())
+    }
+
+    /// Adds a network to the denylist.
+    ///
+    /// Denylisted clients are rejected like banned ones, unless they are also
+    /// allowlisted. Takes effect immediately in every attached layer.
+    pub fn denylist_add(&self, network: impl Into<IpNet>) {
+        add_network(&self.inner.denylist, network.into());
+    }
+
+    /// Removes a network from the denylist, returning whether it was listed.
+    pub fn denylist_remove(&self, network: impl Into<IpNet>) -> bool {
+        remove_network(&self.inner.denylist, network.into())
+    }
+
+    /// Returns whether `ip` is on the allowlist.
+    pub fn is_allowlisted(&self, ip: IpAddr) -> bool {
+        contains(&self.inner.allowlist, ip)
+    }
+
+    /// Returns whether `ip` is on the denylist and not on the allowlist.
+    pub fn is_denylisted(&self, ip: IpAddr) -> bool {
+        contains(&self.inner.denylist, ip) && !self.is_allowlisted(ip)
+    }
+
+    /// Bans `key` for `duration`. Requests from a banned key are rejected
+    /// with `403 Forbidden` by every layer this handle is attached to.
+    ///
+    /// The key is the client key as extracted by the middleware (the client IP
+    /// by default), before any `KeyCodec` is applied. Banning an already
+    /// banned key replaces the previous ban.
+    pub fn ban(&self, key: impl Into<String>, duration: Duration, reason: impl Into<String>) {
+        let key = key.into();
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let ban = Ban {
+            reason: reason.into(),
+            reference: format!("{:08X}", fnv1a(&format!("{key}@{nanos}")) as u32),
+            until: Instant::now() + duration,
+        };
+        self.bans().insert(key, ban);
+    }
+
+    /// Lifts the ban on `key`, returning it if there was one.
+    pub fn unban(&self, key: &str) -> Option<Ban> {
+        self.bans()
+            .remove(key)
+            .filter(|ban| ban.until > Instant::now())
+    }
+
+    /// Returns the active ban on `key`, if any.
+    pub fn ban_for(&self, key: &str) -> Option<Ban> {
+        let mut bans = self.bans();
+        match bans.get(key) {
+            Some(ban) if ban.until > Instant::now() => Some(ban.clone()),
+            Some(_) => {
+                bans.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Seeds bans and budget usage from an external source, returning how
+    /// many records were imported.
+    ///
+    /// Meant for startup, when migrating from another rate limiter such as
+    /// nginx, so active bans and long-window usage carry over instead of
+    /// starting from zero:
+    ///
+    /// ```rust
+    /// # use axum_governor::{Governor, ImportRecord};
+    /// # use std::time::Duration;
+    /// let governor = Governor::new().budget("exports", 100, Duration::from_secs(86_400));
+    /// let imported = governor.import([
+    ///     ImportRecord::Ban {
+    ///         key: "203.0.113.7".into(),
+    ///         remaining: Duration::from_secs(3600),
+    ///         reason: "carried over from nginx".into(),
+    ///     },
+    ///     ImportRecord::Usage {
+    ///         budget: "exports".into(),
+    ///         key: "198.51.100.4".into(),
+    ///         used: 40,
+    ///     },
+    /// ]);
+    /// ```
+    ///
+    /// Usage is charged to a window starting at import, even past the
+    /// budget. Records for undefined budgets and bans that already lifted are
+    /// skipped. `lazy-limit` keeps its counters private, so its windows can't
+    /// be seeded.
+    pub fn import(&self, records: impl IntoIterator<Item = ImportRecord>) -> usize {
+        let mut imported = 0;
+        for record in records {
+            match record {
+                ImportRecord::Ban {
+                    key,
+                    remaining,
+                    reason,
+                } if !remaining.is_zero() => self.ban(key, remaining, reason),
+                ImportRecord::Ban { .. } => continue,
+                ImportRecord::Usage { budget, key, used } => match self.budget_named(&budget) {
+                    Some(budget) => {
+                        budget.consume(&key, used);
+                    }
+                    None => continue,
+                },
+            }
+            imported += 1;
+        }
+        imported
+    }
+
+    /// Returns all active bans.
+    pub fn active_bans(&self) -> Vec<(String, Ban)> {
+        let now = Instant::now();
+        let mut bans = self.bans();
+        bans.retain(|_, ban| ban.until > now);
+        bans.iter()
+            .map(|(key, ban)| (key.clone(), ban.clone()))
+            .collect()
+    }
+
+    fn budget_named(&self, name: &str) -> Option<CountBudget> {
+        let budget = self
+            .inner
+            .budgets
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(name)
+            .cloned();
+        if budget.is_none() {
+            let mut undefined = self
+                .inner
+                .undefined_budgets
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            // Once per name, rather than on every request naming it.
+            if undefined.insert(name.to_string()) {
+                warn!("Governor budget `{name}` is not defined and is never exceeded");
+            }
+        }
+        budget
+    }
+
+    fn bans(&self) -> MutexGuard<'_, HashMap<String, Ban>> {
+        self.inner
+            .bans
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+fn add_network(list: &RwLock<Vec<IpNet>>, network: IpNet) {
+    let network = network.trunc();
+    let mut list = list.write().unwrap_or_else(PoisonError::into_inner);
+    if !list.contains(&network) {
+        list.push(network);
+    }
+}
+
+fn remove_network(list: &RwLock<Vec<IpNet>>, network: IpNet) -> bool {
+    let network = network.trunc();
+    let mut list = list.write().unwrap_or_else(PoisonError::into_inner);
+    let len = list.len();
+    list.retain(|listed| *listed != network);
+    list.len() != len
+}
+
+fn contains(list: &RwLock<Vec<IpNet>>, ip: IpAddr) -> bool {
+    let ip = ip.to_canonical();
+    list.read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .iter()
+        .any(|network| network.contains(&ip))
+}
+
+/// The response sent to banned clients.
+///
+/// Kept distinct from ordinary throttling so that blocked clients are told to
+/// get in touch rather than to retry later.
+#[derive(Debug, Clone)]
+pub struct BanResponse {
+    /// Status code of the response. Defaults to `403 Forbidden`.
+    pub status: StatusCode,
+    /// Message shown to the client.
+    pub message: String,
+    /// Optional contact link or address appended to the message.
+    pub contact: Option<String>,
+}
+
+impl Default for BanResponse {
+    fn default() -> Self {
+        Self {
+            status: StatusCode::FORBIDDEN,
+            message: "Access has been blocked.".to_string(),
+            contact: None,
+        }
+    }
+}
+
+impl BanResponse {
+    /// Creates the default ban response.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the status code.
+    pub fn status(mut self, status: StatusCode) -> Self {
+        self.status = status;
+        self
+    }
+
+    /// Sets the message shown to the client.
+    pub fn message(mut self, message: impl Into<String>) -> Self {
+        self.message = message.into();
+        self
+    }
+
+    /// Sets a contact link or address, e.g. `"https://example.com/support"`.
+    pub fn contact(mut self, contact: impl Into<String>) -> Self {
+        self.contact = Some(contact.into());
+        self
+    }
+
+    /// Renders the response. Denylisted clients have no `ban` and get no
+    /// reference.
+    pub(crate) fn render(
+        &self,
+        ban: Option<&Ban>,
+        format: ErrorFormat,
+        templates: &ErrorTemplates,
+    ) -> Response<Body> {
+        let mut body = self.message.clone();
+        if let Some(ban) = ban {
+            body.push_str(&format!(" Reference: #{}.", ban.reference));
+        }
+        if let Some(contact) = &self.contact {
+            body.push_str(&format!(" Contact: {contact}"));
+        }
+
+        format.response(self.status, Rejection::Banned, &body, None, templates)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bans_until_lifted() {
+        let governor = Governor::new();
+        governor.ban("203.0.113.7", Duration::from_secs(60), "scraping");
+        let ban = governor.ban_for("203.0.113.7").unwrap();
+        assert_eq!(ban.reason, "scraping");
+        assert_eq!(ban.reference.len(), 8);
+        assert!(governor.ban_for("198.51.100.1").is_none());
+        let active = governor.active_bans();
+        assert_eq!(active.len(), 1);
+        assert_eq!(active[0].0, "203.0.113.7");
+
+        assert_eq!(governor.unban("203.0.113.7").unwrap().reason, "scraping");
+        assert!(governor.ban_for("203.0.113.7").is_none());
+        assert!(governor.unban("203.0.113.7").is_none());
+    }
+
+    #[test]
+    fn expired_bans_are_forgotten() {
+        let governor = Governor::new();
+        governor.ban("203.0.113.7", Duration::ZERO, "scraping");
+        assert!(governor.ban_for("203.0.113.7").is_none());
+        governor.ban("203.0.113.7", Duration::ZERO, "scraping");
+        assert!(governor.active_bans().is_empty());
+        governor.ban("203.0.113.7", Duration::ZERO, "scraping");
+        assert!(governor.unban("203.0.113.7").is_none());
+    }
+
+    async fn text(response: Response<Body>) -> String {
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn renders_ban_responses() {
+        let governor = Governor::new();
+        governor.ban("203.0.113.7", Duration::from_secs(60), "scraping");
+        let ban = governor.ban_for("203.0.113.7").unwrap();
+        let templates = ErrorTemplates::new();
+
+        let response = BanResponse::new().render(Some(&ban), ErrorFormat::Text, &templates);
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert_eq!(
+            text(response).await,
+            format!("Access has been blocked. Reference: #{}.", ban.reference)
+        );
+
+        let custom = BanResponse::new()
+            .status(StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS)
+            .message("Blocked.")
+            .contact("abuse@example.com");
+        let response = custom.render(Some(&ban), ErrorFormat::Text, &templates);
+        assert_eq!(response.status(), StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS);
+        assert_eq!(
+            text(response).await,
+            format!(
+                "Blocked. Reference: #{}. Contact: abuse@example.com",
+                ban.reference
+            )
+        );
+
+        // Denylisted clients have no ban to refer to.
+        let response = custom.render(None, ErrorFormat::Text, &templates);
+        assert_eq!(text(response).await, "Blocked. Contact: abuse@example.com");
+    }
+
+    #[test]
+    fn groups_default_their_tier_to_the_name() {
+        let governor = Governor::new()
+            .group("admin", GovernorConfig::new())
+            .group("public", GovernorConfig::new().tier("free"));
+        let groups = governor.inner.groups.lock().unwrap();
+        assert_eq!(groups["admin"].tier.as_deref(), Some("admin"));
+        assert_eq!(groups["public"].tier.as_deref(), Some("free"));
+    }
+
+    #[test]
+    #[should_panic(expected = "governor group `missing` is not defined")]
+    fn layers_for_undefined_groups_panic() {
+        Governor::new()
+            .group("admin", GovernorConfig::new())
+            .layer_for_group("missing");
+    }
+
+    #[test]
+    fn allowlist_overrides_denylist() {
+        let governor = Governor::new();
+        let client: IpAddr = "203.0.113.7".parse().unwrap();
+        let network: IpNet = "203.0.113.0/24".parse().unwrap();
+
+        governor.denylist_add(network);
+        assert!(governor.is_denylisted(client));
+        assert!(!governor.is_denylisted("198.51.100.1".parse().unwrap()));
+        // IPv4-mapped addresses match IPv4 networks.
+        assert!(governor.is_denylisted("::ffff:203.0.113.7".parse().unwrap()));
+
+        governor.allowlist_add(IpNet::from(client));
+        assert!(governor.is_allowlisted(client));
+        assert!(!governor.is_denylisted(client));
+
+        assert!(governor.allowlist_remove(IpNet::from(client)));
+        assert!(!governor.allowlist_remove(IpNet::from(client)));
+        assert!(governor.is_denylisted(client));
+        assert!(governor.denylist_remove(network));
+        assert!(!governor.is_denylisted(client));
+    }
+
+    #[test]
+    fn named_budgets_are_shared_per_key() {
+        let governor = Governor::new().budget("exports", 2, Duration::from_secs(60));
+        assert!(governor.charge_budget("exports", "a", 1));
+        // Clones, and so every path assigned to the budget, share the counts.
+        assert!(governor.clone().charge_budget("exports", "a", 1));
+        assert!(!governor.charge_budget("exports", "a", 1));
+        assert!(governor.charge_budget("exports", "b", 2));
+    }
+
+    #[test]
+    fn undefined_budgets_are_never_exceeded_and_reported_once() {
+        let governor = Governor::new();
+        for _ in 0..3 {
+            assert!(governor.charge_budget("missing", "a", 1_000));
+        }
+        let undefined = governor.inner.undefined_budgets.lock().unwrap();
+        assert_eq!(undefined.len(), 1);
+    }
+
+    #[test]
+    fn imports_bans_and_usage() {
+        let governor = Governor::new().budget("exports", 100, Duration::from_secs(60));
+        let imported = governor.import([
+            ImportRecord::Ban {
+                key: "203.0.113.7".into(),
+                remaining: Duration::from_secs(60),
+                reason: "carried over".into(),
+            },
+            ImportRecord::Ban {
+                key: "198.51.100.1".into(),
+                remaining: Duration::ZERO,
+                reason: "lifted".into(),
+            },
+            ImportRecord::Usage {
+                budget: "exports".into(),
+                key: "192.0.2.1".into(),
+                used: 150,
+            },
+            ImportRecord::Usage {
+                budget: "missing".into(),
+                key: "192.0.2.1".into(),
+                used: 1,
+            },
+        ]);
+
+        assert_eq!(imported, 2);
+        assert_eq!(
+            governor.ban_for("203.0.113.7").unwrap().reason,
+            "carried over"
+        );
+        assert!(governor.ban_for("198.51.100.1").is_none());
+        // Usage past the budget is kept.
+        assert!(!governor.charge_budget("exports", "192.0.2.1", 1));
+        assert!(governor.charge_budget("exports", "192.0.2.2", 100));
+    }
+}
@@ -0,0 +1,91 @@
+/* src/handler.rs */
+
+use crate::{GovernorConfig, GovernorLayer};
+use axum::handler::{Handler, Layered};
+
+/// Wraps a single handler with its own rate-limiting configuration.
+///
+/// A middle ground between the `RateLimited` extractor and a full router
+/// layer, for apps with a handful of special endpoints:
+///
+/// ```rust
+/// # use axum::{routing::post, Router};
+/// # use axum_governor::{rate_limited, GovernorConfig};
+/// async fn login() -> &'static str {
+///     "Welcome back!"
+/// }
+///
+/// let app: Router = Router::new().route(
+///     "/api/login",
+///     post(rate_limited(GovernorConfig::new().override_mode(true), login)),
+/// );
+/// ```
+///
+/// Limits still come from the `lazy-limit` rules; combine a route rule for
+/// the handler's path with override mode to apply only that rule.
+/// `RealIpLayer` must be installed on the router.
+pub fn rate_limited<H, T, S>(config: GovernorConfig, handler: H) -> Layered<GovernorLayer, H, T, S>
+where
+    H: Handler<T, S>,
+    T: 'static,
+    S: Clone + Send + Sync + 'static,
+{
+    handler.layer(GovernorLayer::new(config))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_util, Governor};
+    use axum::{
+        body::Body,
+        extract::ConnectInfo,
+        http::{Request, StatusCode},
+        routing::get,
+        Router,
+    };
+    use std::{net::SocketAddr, time::Duration};
+    use tower::ServiceExt;
+
+    async fn ok() -> &'static str {
+        "ok"
+    }
+
+    fn get_from_client(path: &str) -> Request<Body> {
+        let mut request = Request::get(path).body(Body::empty()).unwrap();
+        let addr = SocketAddr::new(test_util::CLIENT.parse().unwrap(), 40000);
+        request.extensions_mut().insert(ConnectInfo(addr));
+        request
+    }
+
+    #[tokio::test]
+    async fn limits_only_the_wrapped_handler() {
+        test_util::init_limiter().await;
+        let governor = Governor::new().budget("login", 1, Duration::from_secs(60));
+        let config = test_util::config()
+            .governor(governor)
+            .budget("/login", "login");
+        let app = Router::new()
+            .route("/login", get(rate_limited(config, ok)))
+            .route("/home", get(ok));
+
+        let response = app
+            .clone()
+            .oneshot(get_from_client("/login"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = app
+            .clone()
+            .oneshot(get_from_client("/login"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        // Other handlers of the router are not limited.
+        for _ in 0..3 {
+            let response = app.clone().oneshot(get_from_client("/home")).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+    }
+}
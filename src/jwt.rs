@@ -0,0 +1,232 @@
+/* src/jwt.rs */
+
+use crate::{KeyError, KeyExtractor};
+use axum::http::{header, request::Parts};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use hmac::{Hmac, Mac};
+use serde_json::Value;
+use sha2::Sha256;
+use std::{
+    fmt,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Keys requests by a claim of their `Authorization: Bearer` JWT.
+///
+/// Limits follow the identity in the token, e.g. `sub` for per-user or
+/// `org_id` for per-tenant limits, instead of the client IP. Keys have the
+/// form `<claim>:<value>`. Requests without a bearer token, with an expired
+/// token or without the claim fall back to their client IP; malformed tokens
+/// and bad signatures are rejected with `400 Bad Request`.
+///
+/// Tokens are verified as HS256 under a shared secret, since an unverified
+/// claim lets clients pick a fresh key for every request. If an earlier
+/// layer already verified the token, use [`unverified`](Self::unverified).
+/// Requires the `jwt` feature.
+#[derive(Clone)]
+pub struct JwtClaimExtractor {
+    claim: String,
+    secret: Option<Arc<[u8]>>,
+}
+
+impl fmt::Debug for JwtClaimExtractor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JwtClaimExtractor")
+            .field("claim", &self.claim)
+            .field("verified", &self.secret.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+impl JwtClaimExtractor {
+    /// Creates an extractor keying by `claim` of HS256 tokens signed with
+    /// `secret`.
+    pub fn hs256(secret: impl AsRef<[u8]>, claim: impl Into<String>) -> Self {
+        Self {
+            claim: claim.into(),
+            secret: Some(secret.as_ref().into()),
+        }
+    }
+
+    /// Creates an extractor keying by `claim` without checking the
+    /// signature.
+    ///
+    /// Only use this behind a layer that rejects requests with invalid
+    /// tokens before they reach the governor.
+    pub fn unverified(claim: impl Into<String>) -> Self {
+        Self {
+            claim: claim.into(),
+            secret: None,
+        }
+    }
+
+    /// Checks the signature of `token` and returns its decoded claims.
+    fn claims(&self, token: &str) -> Result<Value, KeyError> {
+        let invalid = |reason: &str| KeyError::Invalid(format!("bearer token {reason}"));
+
+        let mut segments = token.split('.');
+        let (Some(header), Some(payload), Some(signature), None) = (
+            segments.next(),
+            segments.next(),
+            segments.next(),
+            segments.next(),
+        ) else {
+            return Err(invalid("is not a JWT"));
+        };
+
+        if let Some(secret) = &self.secret {
+            let signed = &token[..header.len() + 1 + payload.len()];
+            let header = decode(header).ok_or_else(|| invalid("has a malformed header"))?;
+            if header.get("alg").and_then(Value::as_str) != Some("HS256") {
+                return Err(invalid("is not signed with HS256"));
+            }
+            let signature = URL_SAFE_NO_PAD
+                .decode(signature)
+                .map_err(|_| invalid("has a malformed signature"))?;
+            let mut mac =
+                Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+            mac.update(signed.as_bytes());
+            mac.verify_slice(&signature)
+                .map_err(|_| invalid("has a bad signature"))?;
+        }
+
+        decode(payload).ok_or_else(|| invalid("has a malformed payload"))
+    }
+}
+
+impl KeyExtractor for JwtClaimExtractor {
+    fn extract(&self, parts: &Parts) -> Result<String, KeyError> {
+        let token = parts
+            .headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(str::trim)
+            .ok_or(KeyError::Missing)?;
+        let claims = self.claims(token)?;
+
+        // `exp` is a NumericDate, which may have a fractional part.
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64();
+        if claims
+            .get("exp")
+            .and_then(Value::as_f64)
+            .is_some_and(|exp| exp <= now)
+        {
+            return Err(KeyError::Missing);
+        }
+
+        match claims.get(&self.claim) {
+            Some(Value::String(value)) if !value.is_empty() => {
+                Ok(format!("{}:{value}", self.claim))
+            }
+            Some(Value::Number(value)) => Ok(format!("{}:{value}", self.claim)),
+            _ => Err(KeyError::Missing),
+        }
+    }
+}
+
+/// Decodes a base64url JSON segment.
+fn decode(segment: &str) -> Option<Value> {
+    let bytes = URL_SAFE_NO_PAD.decode(segment).ok()?;
+    serde_json::from_slice(&bytes).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::{HeaderValue, Request};
+    use serde_json::json;
+
+    const SECRET: &str = "secret";
+
+    fn token(secret: &str, header: Value, claims: Value) -> String {
+        let encode = |value: Value| URL_SAFE_NO_PAD.encode(value.to_string());
+        let signed = format!("{}.{}", encode(header), encode(claims));
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(signed.as_bytes());
+        let signature = URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes());
+        format!("{signed}.{signature}")
+    }
+
+    fn hs256(claims: Value) -> String {
+        token(SECRET, json!({"alg": "HS256", "typ": "JWT"}), claims)
+    }
+
+    fn extract(extractor: &JwtClaimExtractor, token: &str) -> Result<String, KeyError> {
+        let mut request = Request::get("/").body(()).unwrap();
+        request.headers_mut().insert(
+            header::AUTHORIZATION,
+            HeaderValue::from_str(&format!("Bearer {token}")).unwrap(),
+        );
+        extractor.extract(&request.into_parts().0)
+    }
+
+    fn in_secs(offset: i64) -> i64 {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        now.as_secs() as i64 + offset
+    }
+
+    #[test]
+    fn keys_by_claim() {
+        let extractor = JwtClaimExtractor::hs256(SECRET, "sub");
+        let token = hs256(json!({"sub": "alice", "exp": in_secs(60)}));
+        assert_eq!(extract(&extractor, &token), Ok("sub:alice".to_string()));
+
+        let extractor = JwtClaimExtractor::hs256(SECRET, "org_id");
+        let token = hs256(json!({"org_id": 42}));
+        assert_eq!(extract(&extractor, &token), Ok("org_id:42".to_string()));
+    }
+
+    #[test]
+    fn falls_back_without_token_or_claim() {
+        let extractor = JwtClaimExtractor::hs256(SECRET, "sub");
+        let request = Request::get("/").body(()).unwrap();
+        assert_eq!(
+            extractor.extract(&request.into_parts().0),
+            Err(KeyError::Missing)
+        );
+        for claims in [json!({}), json!({"sub": ""}), json!({"sub": null})] {
+            assert_eq!(extract(&extractor, &hs256(claims)), Err(KeyError::Missing));
+        }
+    }
+
+    #[test]
+    fn falls_back_for_expired_tokens() {
+        let extractor = JwtClaimExtractor::hs256(SECRET, "sub");
+        let expired = hs256(json!({"sub": "alice", "exp": in_secs(-60)}));
+        assert_eq!(extract(&extractor, &expired), Err(KeyError::Missing));
+        // NumericDate may be fractional.
+        let expired = hs256(json!({"sub": "alice", "exp": in_secs(-60) as f64 + 0.5}));
+        assert_eq!(extract(&extractor, &expired), Err(KeyError::Missing));
+        let valid = hs256(json!({"sub": "alice", "exp": in_secs(60) as f64 + 0.5}));
+        assert_eq!(extract(&extractor, &valid), Ok("sub:alice".to_string()));
+    }
+
+    #[test]
+    fn rejects_bad_tokens() {
+        let extractor = JwtClaimExtractor::hs256(SECRET, "sub");
+        let claims = json!({"sub": "alice"});
+        for token in [
+            token("other", json!({"alg": "HS256"}), claims.clone()),
+            token(SECRET, json!({"alg": "none"}), claims.clone()),
+            "not-a-jwt".to_string(),
+            format!("{}.extra", hs256(claims.clone())),
+        ] {
+            assert!(
+                matches!(extract(&extractor, &token), Err(KeyError::Invalid(_))),
+                "{token}"
+            );
+        }
+    }
+
+    #[test]
+    fn unverified_skips_the_signature() {
+        let extractor = JwtClaimExtractor::unverified("sub");
+        let token = token("other", json!({"alg": "HS256"}), json!({"sub": "alice"}));
+        assert_eq!(extract(&extractor, &token), Ok("sub:alice".to_string()));
+    }
+}
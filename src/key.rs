@@ -0,0 +1,392 @@
+/* src/key.rs */
+
+use crate::GovernorConfig;
+use axum::{
+    body::Body,
+    http::{header::USER_AGENT, HeaderMap, Method, Response},
+};
+use std::{
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    sync::Arc,
+};
+
+/// Turns a client IP into its rate-limit key.
+///
+/// IPv4-mapped IPv6 addresses (`::ffff:1.2.3.4`) are unwrapped to plain IPv4
+/// unless `preserve_ipv4_mapped` is set, so a client gets the same bucket on
+/// either stack. Addresses are grouped by `ipv4_prefix` and `ipv6_prefix` if
+/// configured, yielding keys like `203.0.113.0/24` or `2001:db8:1:2::/64`.
+/// Addresses are rendered in their canonical RFC 5952 text form.
+pub(crate) fn ip_key(ip: IpAddr, config: &GovernorConfig) -> String {
+    IpKeying::of(config).key(ip)
+}
+
+/// The settings of a `GovernorConfig` that turn a client IP into its key,
+/// for places that derive IP keys without a config at hand.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct IpKeying {
+    preserve_ipv4_mapped: bool,
+    ipv4_prefix: Option<u8>,
+    ipv6_prefix: Option<u8>,
+}
+
+impl IpKeying {
+    pub(crate) fn of(config: &GovernorConfig) -> Self {
+        Self {
+            preserve_ipv4_mapped: config.preserve_ipv4_mapped,
+            ipv4_prefix: config.ipv4_prefix,
+            ipv6_prefix: config.ipv6_prefix,
+        }
+    }
+
+    /// Returns the key of `ip`, see [`ip_key`].
+    pub(crate) fn key(self, ip: IpAddr) -> String {
+        let ip = if self.preserve_ipv4_mapped {
+            ip
+        } else {
+            ip.to_canonical()
+        };
+
+        match (ip, self.ipv4_prefix, self.ipv6_prefix) {
+            (IpAddr::V4(v4), Some(prefix), _) if prefix < 32 => {
+                let mask = u32::MAX.checked_shl(u32::from(32 - prefix)).unwrap_or(0);
+                let network = Ipv4Addr::from(u32::from(v4) & mask);
+                format!("{network}/{prefix}")
+            }
+            (IpAddr::V6(v6), _, Some(prefix)) if prefix < 128 => {
+                let mask = u128::MAX.checked_shl(u32::from(128 - prefix)).unwrap_or(0);
+                let network = Ipv6Addr::from(u128::from(v6) & mask);
+                format!("{network}/{prefix}")
+            }
+            _ => ip.to_string(),
+        }
+    }
+}
+
+/// Renders a `GovernorConfig::key_template` for a request.
+///
+/// `{ip}` is rendered like an IP key, honoring `ipv6_prefix`.
+///
+/// Placeholders are replaced in a single pass, so values containing
+/// placeholder syntax (e.g. a path with `{ip}` in it) are not expanded again.
+/// Unknown placeholders are kept as is.
+pub(crate) fn render_template(
+    template: &str,
+    config: &GovernorConfig,
+    key: &str,
+    ip: IpAddr,
+    method: &Method,
+    path: &str,
+) -> String {
+    let mut rendered = String::with_capacity(template.len() + key.len() + path.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        rendered.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let Some(end) = rest.find('}') else {
+            break;
+        };
+        let placeholder = &rest[..=end];
+        match placeholder {
+            "{key}" => rendered.push_str(key),
+            "{ip}" => rendered.push_str(&ip_key(ip, config)),
+            "{method}" => rendered.push_str(method.as_str()),
+            "{path}" => rendered.push_str(path),
+            _ => rendered.push_str(placeholder),
+        }
+        rest = &rest[end + 1..];
+    }
+    rendered.push_str(rest);
+    rendered
+}
+
+/// Reduces the `User-Agent` of a request to a coarse client family.
+///
+/// Families are deliberately few, so a client rotating its `User-Agent` gains
+/// at most a handful of extra buckets: `browser`, `mobile-browser`,
+/// `mobile-app` (native HTTP stacks like OkHttp or CFNetwork), `cli` (curl,
+/// wget, HTTPie), `script` (language HTTP libraries), `bot`, `other`, and
+/// `none` without a `User-Agent`.
+pub(crate) fn user_agent_family(headers: &HeaderMap) -> &'static str {
+    let Some(user_agent) = headers.get(USER_AGENT) else {
+        return "none";
+    };
+    let user_agent = String::from_utf8_lossy(user_agent.as_bytes()).to_ascii_lowercase();
+    let has = |needles: &[&str]| needles.iter().any(|needle| user_agent.contains(needle));
+
+    if user_agent.trim().is_empty() {
+        "none"
+    } else if has(&["bot", "spider", "crawl", "slurp"]) {
+        "bot"
+    } else if has(&["curl/", "wget/", "httpie/"]) {
+        "cli"
+    } else if has(&["okhttp/", "cfnetwork/", "dalvik/", "alamofire/"]) {
+        "mobile-app"
+    } else if user_agent.starts_with("mozilla/") {
+        if has(&["mobile", "android", "iphone", "ipad"]) {
+            "mobile-browser"
+        } else {
+            "browser"
+        }
+    } else if has(&[
+        "python",
+        "go-http-client/",
+        "java/",
+        "node-fetch",
+        "axios/",
+        "undici",
+        "ruby",
+        "libwww-perl",
+        "guzzlehttp",
+        "reqwest",
+        "hyper/",
+    ]) {
+        "script"
+    } else {
+        "other"
+    }
+}
+
+/// How requests from private address space are limited.
+///
+/// A request only counts as private if both the client address and the
+/// connection peer from `ConnectInfo<SocketAddr>` are private. The client
+/// address alone comes from `RealIp`, which trusts forwarding headers that
+/// any client can forge, so without `ConnectInfo` (i.e. without serving via
+/// `into_make_service_with_connect_info::<SocketAddr>()`) no request counts
+/// as private. Behind a proxy on a private network the peer is always
+/// private; the proxy must then overwrite forwarding headers rather than
+/// append to client-supplied ones.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PrivateTraffic {
+    /// Private sources are limited like any other client.
+    #[default]
+    Limit,
+    /// Private sources are never limited.
+    Exempt,
+    /// Private sources are only subject to route-specific rules, as in
+    /// override mode, so internet-facing global defaults don't throttle
+    /// intra-cluster traffic and health checks.
+    RouteRulesOnly,
+}
+
+/// What happens to requests without a `RealIp` extension.
+///
+/// The extension is missing when `RealIpLayer` is not installed in front of
+/// the governor, which usually is a deployment mistake.
+#[derive(Clone, Default)]
+pub enum MissingIpPolicy {
+    /// Reject with `500 Internal Server Error`.
+    #[default]
+    FailClosed,
+    /// Let the request through without limiting it.
+    FailOpen,
+    /// Key by the peer address from `ConnectInfo<SocketAddr>`, failing
+    /// closed if that is missing too. Requires serving with
+    /// `into_make_service_with_connect_info::<SocketAddr>()`.
+    UseConnectInfo,
+    /// Reject with the response returned by the function.
+    CustomResponse(Arc<dyn Fn() -> Response<Body> + Send + Sync>),
+}
+
+impl fmt::Debug for MissingIpPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MissingIpPolicy::FailClosed => f.write_str("FailClosed"),
+            MissingIpPolicy::FailOpen => f.write_str("FailOpen"),
+            MissingIpPolicy::UseConnectInfo => f.write_str("UseConnectInfo"),
+            MissingIpPolicy::CustomResponse(_) => f.write_str("CustomResponse(..)"),
+        }
+    }
+}
+
+/// Returns `true` for loopback, link-local and private (RFC 1918 / RFC 4193)
+/// addresses.
+pub(crate) fn is_private(ip: IpAddr) -> bool {
+    match ip.to_canonical() {
+        IpAddr::V4(v4) => v4.is_private() || v4.is_loopback() || v4.is_link_local(),
+        IpAddr::V6(v6) => v6.is_loopback() || v6.is_unique_local() || v6.is_unicast_link_local(),
+    }
+}
+
+/// Returns whether a request from `client_ip` over a connection from `peer`
+/// comes from private address space. See [`PrivateTraffic`].
+pub(crate) fn is_private_source(client_ip: IpAddr, peer: Option<IpAddr>) -> bool {
+    is_private(client_ip) && peer.is_some_and(is_private)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(value: &str) -> IpAddr {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn unwraps_ipv4_mapped_addresses() {
+        let config = GovernorConfig::new();
+        assert_eq!(ip_key(ip("::ffff:192.0.2.1"), &config), "192.0.2.1");
+        assert_eq!(ip_key(ip("192.0.2.1"), &config), "192.0.2.1");
+
+        let config = GovernorConfig::new().preserve_ipv4_mapped(true);
+        assert_eq!(ip_key(ip("::ffff:192.0.2.1"), &config), "::ffff:192.0.2.1");
+    }
+
+    #[test]
+    fn masks_ipv6_prefixes() {
+        let config = GovernorConfig::new().ipv6_prefix(64);
+        assert_eq!(
+            ip_key(ip("2001:db8:1:2:3:4:5:6"), &config),
+            "2001:db8:1:2::/64"
+        );
+        assert_eq!(
+            ip_key(ip("2001:DB8:1:2::ffff"), &config),
+            "2001:db8:1:2::/64"
+        );
+        // IPv4 clients are unaffected.
+        assert_eq!(ip_key(ip("192.0.2.1"), &config), "192.0.2.1");
+
+        assert_eq!(
+            ip_key(ip("2001:db8::1"), &GovernorConfig::new().ipv6_prefix(0)),
+            "::/0"
+        );
+        assert_eq!(
+            ip_key(ip("2001:db8::1"), &GovernorConfig::new().ipv6_prefix(128)),
+            "2001:db8::1"
+        );
+    }
+
+    #[test]
+    fn masks_ipv4_prefixes() {
+        let config = GovernorConfig::new().ipv4_prefix(24);
+        assert_eq!(ip_key(ip("203.0.113.77"), &config), "203.0.113.0/24");
+        assert_eq!(ip_key(ip("::ffff:203.0.113.77"), &config), "203.0.113.0/24");
+        assert_eq!(ip_key(ip("2001:db8::1"), &config), "2001:db8::1");
+
+        let config = GovernorConfig::new().ipv4_prefix(20);
+        assert_eq!(ip_key(ip("203.0.127.255"), &config), "203.0.112.0/20");
+        let config = GovernorConfig::new().ipv4_prefix(0);
+        assert_eq!(ip_key(ip("203.0.113.77"), &config), "0.0.0.0/0");
+        let config = GovernorConfig::new().ipv4_prefix(32);
+        assert_eq!(ip_key(ip("203.0.113.77"), &config), "203.0.113.77");
+
+        // Mapped addresses kept as IPv6 are not grouped by the IPv4 prefix.
+        let config = GovernorConfig::new()
+            .ipv4_prefix(24)
+            .preserve_ipv4_mapped(true);
+        assert_eq!(
+            ip_key(ip("::ffff:203.0.113.77"), &config),
+            "::ffff:203.0.113.77"
+        );
+    }
+
+    fn render(template: &str, config: &GovernorConfig, path: &str) -> String {
+        render_template(
+            template,
+            config,
+            "api-key:abc",
+            ip("2001:db8:1:2::9"),
+            &Method::POST,
+            path,
+        )
+    }
+
+    #[test]
+    fn renders_placeholders() {
+        let config = GovernorConfig::new().ipv6_prefix(64);
+        assert_eq!(
+            render("{key}|{ip}|{method}|{path}", &config, "/login"),
+            "api-key:abc|2001:db8:1:2::/64|POST|/login"
+        );
+        assert_eq!(render("static", &config, "/login"), "static");
+    }
+
+    #[test]
+    fn expands_values_only_once() {
+        let config = GovernorConfig::new();
+        assert_eq!(
+            render("{path}:{key}", &config, "/files/{key}"),
+            "/files/{key}:api-key:abc"
+        );
+    }
+
+    #[test]
+    fn keeps_unknown_and_unclosed_placeholders() {
+        let config = GovernorConfig::new();
+        assert_eq!(render("{user}:{key}", &config, "/"), "{user}:api-key:abc");
+        assert_eq!(render("{key}:{ip", &config, "/"), "api-key:abc:{ip");
+    }
+
+    #[test]
+    fn groups_user_agents_into_families() {
+        let family = |user_agent: Option<&'static str>| {
+            let mut headers = HeaderMap::new();
+            if let Some(user_agent) = user_agent {
+                headers.insert(USER_AGENT, user_agent.parse().unwrap());
+            }
+            user_agent_family(&headers)
+        };
+
+        for (user_agent, expected) in [
+            (None, "none"),
+            (Some(" "), "none"),
+            (
+                Some("Mozilla/5.0 (X11; Linux x86_64) Gecko/20100101 Firefox/130.0"),
+                "browser",
+            ),
+            (
+                Some("Mozilla/5.0 (iPhone; CPU iPhone OS 17_0 like Mac OS X) Mobile/15E148"),
+                "mobile-browser",
+            ),
+            (
+                Some("Mozilla/5.0 (compatible; Googlebot/2.1; +http://www.google.com/bot.html)"),
+                "bot",
+            ),
+            (Some("curl/8.5.0"), "cli"),
+            (Some("okhttp/4.12.0"), "mobile-app"),
+            (Some("python-requests/2.32.3"), "script"),
+            (Some("Go-http-client/2.0"), "script"),
+            (Some("MyClient/1.0"), "other"),
+        ] {
+            assert_eq!(family(user_agent), expected, "{user_agent:?}");
+        }
+    }
+
+    #[test]
+    fn detects_private_sources() {
+        for private in [
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "127.0.0.1",
+            "169.254.0.1",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:10.1.2.3",
+        ] {
+            assert!(is_private(ip(private)), "{private}");
+        }
+        for public in [
+            "203.0.113.7",
+            "172.32.0.1",
+            "2001:db8::1",
+            "::ffff:203.0.113.7",
+        ] {
+            assert!(!is_private(ip(public)), "{public}");
+        }
+    }
+
+    #[test]
+    fn private_sources_need_a_private_peer() {
+        let private = ip("10.1.2.3");
+        assert!(is_private_source(private, Some(ip("10.0.0.1"))));
+        // A forged forwarding header from a public peer doesn't qualify.
+        assert!(!is_private_source(private, Some(ip("203.0.113.7"))));
+        assert!(!is_private_source(private, None));
+        // Nor does a public client behind a private proxy.
+        assert!(!is_private_source(ip("203.0.113.7"), Some(ip("10.0.0.1"))));
+    }
+}
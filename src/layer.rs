@@ -1,22 +1,27 @@
 /* src/layer.rs */
 
 use crate::{GovernorConfig, GovernorMiddleware};
-use std::clone::Clone;
+use std::{clone::Clone, sync::Arc};
 
 /// A `tower::Layer` that applies rate-limiting to requests.
 ///
 /// This layer wraps an inner service with the `GovernorMiddleware`. It requires
 /// that the `real::RealIpLayer` has been applied beforehand to make the
 /// client's IP address available in the request extensions.
+///
+/// The configuration is shared behind an `Arc` by every service the layer
+/// creates, so cloning the layer or handling a request never copies it.
 #[derive(Debug, Clone)]
 pub struct GovernorLayer {
-    config: GovernorConfig,
+    config: Arc<GovernorConfig>,
 }
 
 impl GovernorLayer {
     /// Creates a new `GovernorLayer` with the given configuration.
     pub fn new(config: GovernorConfig) -> Self {
-        Self { config }
+        Self {
+            config: Arc::new(config),
+        }
     }
 }
 
@@ -26,9 +31,7 @@ impl Default for GovernorLayer {
     /// Default mode uses `lazy_limit::limit!`, enforcing both global and
     /// route-specific rules.
     fn default() -> Self {
-        Self {
-            config: GovernorConfig::default(),
-        }
+        Self::new(GovernorConfig::default())
     }
 }
 
@@ -36,6 +39,6 @@ impl<S> tower::Layer<S> for GovernorLayer {
     type Service = GovernorMiddleware<S>;
 
     fn layer(&self, inner: S) -> Self::Service {
-        GovernorMiddleware::new(inner, self.config.clone())
+        GovernorMiddleware::from_shared(inner, self.config.clone())
     }
 }
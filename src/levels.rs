@@ -0,0 +1,188 @@
+/* src/levels.rs */
+
+use crate::{count_budget::CountBudget, reservation::Charge, KeyExtractor};
+use axum::http::request::Parts;
+use std::{fmt, sync::Arc, time::Duration};
+use tracing::debug;
+
+/// One level of a hierarchical limit, e.g. per user or per organization.
+///
+/// Add levels with `GovernorConfig::key_level` to limit a request under
+/// several keys at once, where every level must pass:
+///
+/// ```rust
+/// # use axum::http::request::Parts;
+/// # use axum_governor::{ApiKeyExtractor, GovernorConfig, KeyError, KeyLevel};
+/// # use std::time::Duration;
+/// let config = GovernorConfig::new()
+///     // Per IP: the `lazy-limit` rules, e.g. 100/s.
+///     .key_level(KeyLevel::new(
+///         "user",
+///         ApiKeyExtractor::default(),
+///         20,
+///         Duration::from_secs(1),
+///     ))
+///     .key_level(KeyLevel::new(
+///         "org",
+///         |parts: &Parts| {
+///             let org = parts.headers.get("x-org-id").and_then(|org| org.to_str().ok());
+///             org.map(|org| format!("org:{org}")).ok_or(KeyError::Missing)
+///         },
+///         1000,
+///         Duration::from_secs(1),
+///     ));
+/// ```
+///
+/// Levels are checked in order after the regular limits passed, each
+/// allowing `max_requests` per fixed `window` and key. A request denied by
+/// one level is not counted against the others. A level whose extractor
+/// finds no key, e.g. the user level for anonymous requests, is skipped.
+#[derive(Clone)]
+pub struct KeyLevel {
+    name: String,
+    extractor: Arc<dyn KeyExtractor>,
+    requests: CountBudget,
+}
+
+impl fmt::Debug for KeyLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyLevel")
+            .field("name", &self.name)
+            .field("requests", &self.requests)
+            .finish_non_exhaustive()
+    }
+}
+
+impl KeyLevel {
+    /// Creates the level `name`, keyed by `extractor` and allowing
+    /// `max_requests` per `window` and key.
+    pub fn new(
+        name: impl Into<String>,
+        extractor: impl KeyExtractor,
+        max_requests: u64,
+        window: Duration,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            extractor: Arc::new(extractor),
+            requests: CountBudget::new(max_requests, window),
+        }
+    }
+}
+
+/// Counts a request against every level and appends the charges to
+/// `charges`, returning `false` if one is exhausted. Levels charged before the
+/// exhausted one are refunded.
+pub(crate) fn charge_all(
+    levels: &[KeyLevel],
+    parts: &Parts,
+    tenant: Option<&str>,
+    charges: &mut Vec<Charge>,
+) -> bool {
+    let charged = charges.len();
+    for level in levels {
+        let Ok(key) = level.extractor.extract(parts) else {
+            continue;
+        };
+        let key = match tenant {
+            Some(tenant) => format!("{tenant}/{key}"),
+            None => key,
+        };
+        if !Charge::record(&level.requests, &key, 1, charges) {
+            debug!("Key level `{}` exhausted for {key}", level.name);
+            for charge in charges.drain(charged..) {
+                charge.refund();
+            }
+            return false;
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ApiKeyExtractor, KeyError};
+    use axum::http::Request;
+
+    fn levels() -> [KeyLevel; 2] {
+        let org = |parts: &Parts| {
+            let org = parts
+                .headers
+                .get("x-org-id")
+                .and_then(|org| org.to_str().ok());
+            org.map(|org| format!("org:{org}")).ok_or(KeyError::Missing)
+        };
+        [
+            KeyLevel::new(
+                "user",
+                ApiKeyExtractor::default(),
+                1,
+                Duration::from_secs(60),
+            ),
+            KeyLevel::new("org", org, 2, Duration::from_secs(60)),
+        ]
+    }
+
+    fn request(api_key: Option<&str>) -> Parts {
+        let mut request = Request::get("/").header("x-org-id", "acme");
+        if let Some(api_key) = api_key {
+            request = request.header("x-api-key", api_key);
+        }
+        request.body(()).unwrap().into_parts().0
+    }
+
+    #[test]
+    fn every_level_must_pass() {
+        let levels = levels();
+        let mut charges = Vec::new();
+        assert!(charge_all(
+            &levels,
+            &request(Some("alice")),
+            None,
+            &mut charges
+        ));
+        assert_eq!(charges.len(), 2);
+
+        // The user level is exhausted, so the org level is refunded.
+        assert!(!charge_all(
+            &levels,
+            &request(Some("alice")),
+            None,
+            &mut charges
+        ));
+        assert_eq!(charges.len(), 2);
+        assert!(charge_all(
+            &levels,
+            &request(Some("bob")),
+            None,
+            &mut charges
+        ));
+        assert!(!charge_all(
+            &levels,
+            &request(Some("carol")),
+            None,
+            &mut charges
+        ));
+    }
+
+    #[test]
+    fn skips_levels_without_a_key() {
+        let levels = levels();
+        let mut charges = Vec::new();
+        for _ in 0..2 {
+            assert!(charge_all(&levels, &request(None), None, &mut charges));
+        }
+        assert!(!charge_all(&levels, &request(None), None, &mut charges));
+    }
+
+    #[test]
+    fn keys_are_per_tenant() {
+        let levels = levels();
+        let mut charges = Vec::new();
+        let alice = request(Some("alice"));
+        assert!(charge_all(&levels, &alice, Some("t1"), &mut charges));
+        assert!(charge_all(&levels, &alice, Some("t2"), &mut charges));
+        assert!(!charge_all(&levels, &alice, Some("t1"), &mut charges));
+    }
+}
@@ -77,15 +77,113 @@ use axum::http::Method;
 use lazy_limit::HttpMethod;
 
 // Public exports
-pub use config::GovernorConfig;
+pub use byte_budget::ByteBudget;
+pub use codec::{HashedCodec, KeyCodec, PrefixCodec, SaltedHashCodec};
+pub use config::{AuthPredicate, GovernorConfig, MethodMapper};
+pub use criticality::Criticality;
+pub use decider::{DecisionRequest, DecisionService};
+pub use decisions::{Decision, DecisionLog, DecisionRecord};
+pub use deny_cache::DenyCache;
+#[cfg(feature = "engine")]
+pub use engine::{Engine, Rule};
+pub use error::{GovernorError, GovernorErrorHandler};
+pub use extract::RateLimited;
+pub use extractor::{
+    ApiKeyExtractor, AsyncKeyExtractor, BasicAuthExtractor, ClientCertExtractor, CookieExtractor,
+    ExtensionExtractor, KeyChain, KeyError, KeyExtractor, QueryParamExtractor, RealIpExtractor,
+};
+pub use flags::{FlagsResolver, GovernorFlags};
+pub use format::{ErrorFormat, ErrorTemplates};
+pub use governor::{Ban, BanResponse, Governor, ImportRecord};
+pub use handler::rate_limited;
+#[cfg(feature = "jwt")]
+pub use jwt::JwtClaimExtractor;
+pub use key::{MissingIpPolicy, PrivateTraffic};
 pub use layer::GovernorLayer;
+pub use levels::KeyLevel;
+pub use listener::{GovernorIo, GovernorListener};
+#[cfg(feature = "regex")]
+pub use matcher::RegexMatcher;
+pub use matcher::{ExactMatcher, PrefixMatcher, RuleMatcher};
+pub use metered::StreamCutoff;
+pub use methods::MethodPolicy;
 pub use middleware::GovernorMiddleware;
+#[cfg(feature = "opa")]
+pub use opa::OpaAdapter;
+pub use pacer::Pacer;
+pub use path::MatchSource;
+pub use policy::{ExternalPolicy, PolicyQuery, PolicyService, PolicyVerdict};
+pub use prefilter::FirstSeenFilter;
+pub use rejection::{DeniedHandler, DeniedInfo, Rejection, REJECTION_HEADER};
+pub use reports::{AbuseReport, AbuseReports, RouteStats};
+pub use reservation::Reservation;
+pub use retry::RetryPolicy;
+pub use seen::{KeySeen, SeenTracker};
+pub use service::ServiceAuth;
+pub use shed::LoadShed;
+pub use slo::{ErrorBudgetSignal, SloGuard};
+pub use tags::{AppliedRules, ClientClass, GovernorTags};
+pub use tarpit::{Tarpit, TarpitDelay};
+pub use tenant::{HostTenant, TenantCap, TenantResolver};
+pub use usage::{UsageFormat, UsageLog};
 
 // Module declarations
+mod byte_budget;
+mod check;
+mod codec;
 mod config;
+mod count_budget;
+mod criticality;
+mod decider;
+mod decisions;
+mod deny_cache;
+#[cfg(feature = "engine")]
+mod engine;
+mod error;
+mod expiring;
+mod extract;
+mod extractor;
+mod flags;
+mod format;
+mod forwarded;
+mod governor;
+mod handler;
+#[cfg(feature = "jwt")]
+mod jwt;
+mod key;
 mod layer;
+mod levels;
+mod listener;
+mod matcher;
+mod metered;
+mod methods;
 mod middleware;
+#[cfg(feature = "opa")]
+mod opa;
+mod pacer;
+mod path;
+mod policy;
+mod prefilter;
+mod rejection;
+mod reports;
+mod reservation;
+mod retry;
+mod seen;
+mod service;
+mod shed;
+mod slo;
+mod tags;
+mod tarpit;
+mod tenant;
+#[cfg(test)]
+mod test_util;
+mod usage;
 
+/// Maps an HTTP method to the `lazy-limit` method used for rule matching.
+///
+/// This is the default mapping. Every method without a `lazy-limit`
+/// counterpart (WebDAV verbs, custom methods) becomes `HttpMethod::OTHER`.
+/// Override it with `GovernorConfig::method_mapper`.
 pub fn map_method(m: Method) -> HttpMethod {
     match m {
         Method::GET => HttpMethod::GET,
@@ -100,3 +198,36 @@ pub fn map_method(m: Method) -> HttpMethod {
         _ => HttpMethod::OTHER,
     }
 }
+
+/// Returns `true` for extension methods, i.e. every method `map_method`
+/// collapses into `HttpMethod::OTHER`.
+pub(crate) fn is_extension_method(m: &Method) -> bool {
+    !matches!(
+        *m,
+        Method::GET
+            | Method::POST
+            | Method::PUT
+            | Method::DELETE
+            | Method::PATCH
+            | Method::HEAD
+            | Method::OPTIONS
+            | Method::CONNECT
+            | Method::TRACE
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_extension_methods_to_other() {
+        assert!(matches!(map_method(Method::GET), HttpMethod::GET));
+        assert!(matches!(map_method(Method::TRACE), HttpMethod::TRACE));
+        assert!(!is_extension_method(&Method::OPTIONS));
+
+        let propfind = Method::from_bytes(b"PROPFIND").unwrap();
+        assert!(matches!(map_method(propfind.clone()), HttpMethod::OTHER));
+        assert!(is_extension_method(&propfind));
+    }
+}
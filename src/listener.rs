@@ -0,0 +1,294 @@
+/* src/listener.rs */
+
+use crate::{key::IpKeying, Governor, GovernorConfig};
+use axum::serve::Listener;
+use std::{
+    collections::HashMap,
+    io,
+    net::{IpAddr, SocketAddr},
+    pin::Pin,
+    sync::{Arc, Mutex, PoisonError},
+    task::{Context, Poll},
+};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tracing::debug;
+
+/// A listener wrapper enforcing bans and connection caps at accept time.
+///
+/// Connections from banned or denylisted IPs are dropped immediately. With
+/// [`max_connections_per_ip`](Self::max_connections_per_ip), connections above
+/// the cap are closed as well, complementing request-rate limits with
+/// connection-level protection.
+///
+/// Wrap the listener passed to `axum::serve` to refuse banned clients before
+/// any HTTP parsing happens, which cuts the cost of volumetric attacks.
+/// axum only provides `ConnectInfo<SocketAddr>` for a few listener types, so
+/// chain `ListenerExt::tap_io` to keep `RealIpLayer` working:
+///
+/// ```rust,no_run
+/// # use axum::{serve::ListenerExt, Router};
+/// # use axum_governor::{Governor, GovernorListener};
+/// # use std::net::SocketAddr;
+/// # async {
+/// let governor = Governor::new();
+/// let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
+/// let listener = GovernorListener::new(listener, governor.clone()).tap_io(|_| ());
+/// axum::serve(listener, Router::new().into_make_service_with_connect_info::<SocketAddr>())
+///     .await
+///     .unwrap();
+/// # };
+/// ```
+///
+/// Bans and caps apply to the peer address of the connection. Behind a
+/// reverse proxy the peer is the proxy itself, so this only helps when clients
+/// connect directly. Bans are looked up by the plain address and, with
+/// [`ip_keys_from`](Self::ip_keys_from), by the key the layer bans, e.g. a
+/// `/64` network.
+#[derive(Debug)]
+pub struct GovernorListener<L> {
+    inner: L,
+    governor: Governor,
+    ip_keying: IpKeying,
+    max_connections_per_ip: Option<usize>,
+    open: Arc<Mutex<HashMap<IpAddr, usize>>>,
+}
+
+impl<L> GovernorListener<L> {
+    /// Wraps `inner`, consulting the bans of `governor` for every connection.
+    pub fn new(inner: L, governor: Governor) -> Self {
+        Self {
+            inner,
+            governor,
+            ip_keying: IpKeying::default(),
+            max_connections_per_ip: None,
+            open: Arc::default(),
+        }
+    }
+
+    /// Derives ban keys from the IP settings of `config`, i.e.
+    /// `ipv4_prefix`, `ipv6_prefix` and `preserve_ipv4_mapped`, so bans the
+    /// layer issues for a whole network are enforced here as well.
+    pub fn ip_keys_from(mut self, config: &GovernorConfig) -> Self {
+        self.ip_keying = IpKeying::of(config);
+        self
+    }
+
+    /// Closes new connections from an IP that already holds `max` open ones.
+    pub fn max_connections_per_ip(mut self, max: usize) -> Self {
+        self.max_connections_per_ip = Some(max);
+        self
+    }
+
+    /// Returns whether `ip` is banned under its key or its plain address.
+    fn is_banned(&self, ip: IpAddr) -> bool {
+        let key = self.ip_keying.key(ip);
+        let plain = ip.to_canonical().to_string();
+        self.governor.ban_for(&key).is_some()
+            || (key != plain && self.governor.ban_for(&plain).is_some())
+    }
+}
+
+impl<L> Listener for GovernorListener<L>
+where
+    L: Listener<Addr = SocketAddr>,
+{
+    type Io = GovernorIo<L::Io>;
+    type Addr = SocketAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        loop {
+            let (io, addr) = self.inner.accept().await;
+            let ip = addr.ip().to_canonical();
+
+            let banned = !self.governor.is_allowlisted(ip)
+                && (self.governor.is_denylisted(ip) || self.is_banned(addr.ip()));
+            if banned {
+                debug!("Refusing connection from banned address {ip}");
+                continue;
+            }
+
+            {
+                let mut open = self.open.lock().unwrap_or_else(PoisonError::into_inner);
+                let count = open.get(&ip).copied().unwrap_or(0);
+                if self.max_connections_per_ip.is_some_and(|max| count >= max) {
+                    debug!("Refusing connection from {ip}: too many open connections");
+                    continue;
+                }
+                open.insert(ip, count + 1);
+            }
+
+            let io = GovernorIo {
+                inner: io,
+                ip,
+                open: self.open.clone(),
+            };
+            return (io, addr);
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        self.inner.local_addr()
+    }
+}
+
+/// A connection accepted by [`GovernorListener`].
+///
+/// Counts towards the open connections of its IP until dropped.
+#[derive(Debug)]
+pub struct GovernorIo<I> {
+    inner: I,
+    ip: IpAddr,
+    open: Arc<Mutex<HashMap<IpAddr, usize>>>,
+}
+
+impl<I> GovernorIo<I> {
+    /// Returns a reference to the underlying connection.
+    pub fn get_ref(&self) -> &I {
+        &self.inner
+    }
+}
+
+impl<I> Drop for GovernorIo<I> {
+    fn drop(&mut self) {
+        let mut open = self.open.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(count) = open.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                open.remove(&self.ip);
+            }
+        }
+    }
+}
+
+impl<I: AsyncRead + Unpin> AsyncRead for GovernorIo<I> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<I: AsyncWrite + Unpin> AsyncWrite for GovernorIo<I> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{collections::VecDeque, time::Duration};
+    use tokio::io::DuplexStream;
+
+    /// A listener handing out connections from the queued peer addresses.
+    struct Incoming(VecDeque<SocketAddr>);
+
+    impl Listener for Incoming {
+        type Io = DuplexStream;
+        type Addr = SocketAddr;
+
+        async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+            let addr = self.0.pop_front().expect("no more queued connections");
+            (tokio::io::duplex(64).0, addr)
+        }
+
+        fn local_addr(&self) -> io::Result<Self::Addr> {
+            Ok(SocketAddr::from(([127, 0, 0, 1], 3000)))
+        }
+    }
+
+    fn incoming(peers: &[&str]) -> Incoming {
+        Incoming(peers.iter().map(|peer| peer.parse().unwrap()).collect())
+    }
+
+    fn listener(governor: &Governor, config: &GovernorConfig) -> GovernorListener<()> {
+        GovernorListener::new((), governor.clone()).ip_keys_from(config)
+    }
+
+    #[test]
+    fn network_bans_cover_every_address() {
+        let governor = Governor::new();
+        governor.ban("2001:db8:1:2::/64", Duration::from_secs(60), "abuse");
+        let config = GovernorConfig::new().ipv6_prefix(64);
+
+        let listener = listener(&governor, &config);
+        assert!(listener.is_banned("2001:db8:1:2::1".parse().unwrap()));
+        assert!(listener.is_banned("2001:db8:1:2:ffff::9".parse().unwrap()));
+        assert!(!listener.is_banned("2001:db8:1:3::1".parse().unwrap()));
+        // Without the config's prefix the network ban is not found.
+        let plain = GovernorListener::new((), governor.clone());
+        assert!(!plain.is_banned("2001:db8:1:2::1".parse().unwrap()));
+    }
+
+    #[test]
+    fn address_bans_still_apply() {
+        let governor = Governor::new();
+        governor.ban("203.0.113.7", Duration::from_secs(60), "abuse");
+        let config = GovernorConfig::new().ipv4_prefix(24);
+
+        let listener = listener(&governor, &config);
+        assert!(listener.is_banned("203.0.113.7".parse().unwrap()));
+        assert!(listener.is_banned("::ffff:203.0.113.7".parse().unwrap()));
+        assert!(!listener.is_banned("203.0.113.8".parse().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn caps_open_connections_per_ip() {
+        let peers = incoming(&[
+            "203.0.113.7:1001",
+            "203.0.113.7:1002",
+            "203.0.113.7:1003",
+            "198.51.100.1:1001",
+            "203.0.113.7:1004",
+        ]);
+        let mut listener = GovernorListener::new(peers, Governor::new()).max_connections_per_ip(2);
+
+        let (first, _) = listener.accept().await;
+        let (_second, _) = listener.accept().await;
+        // The third connection of the same IP is closed right away.
+        let (_, addr) = listener.accept().await;
+        assert_eq!(addr, "198.51.100.1:1001".parse().unwrap());
+
+        drop(first);
+        let (_, addr) = listener.accept().await;
+        assert_eq!(addr, "203.0.113.7:1004".parse().unwrap());
+    }
+
+    #[tokio::test]
+    async fn refuses_banned_peers() {
+        let governor = Governor::new();
+        governor.ban("203.0.113.7", Duration::from_secs(60), "abuse");
+        governor.denylist_add("192.0.2.0/24".parse::<ipnet::IpNet>().unwrap());
+        let peers = incoming(&["203.0.113.7:1001", "192.0.2.1:1001", "198.51.100.1:1001"]);
+        let mut listener = GovernorListener::new(peers, governor);
+
+        let (_, addr) = listener.accept().await;
+        assert_eq!(addr, "198.51.100.1:1001".parse().unwrap());
+    }
+}
@@ -0,0 +1,118 @@
+/* src/metered.rs */
+
+use crate::ByteBudget;
+use axum::body::{Body, Bytes};
+use http_body::{Frame, SizeHint};
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// What happens to a streaming response once its client's response byte
+/// budget runs out mid-stream.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StreamCutoff {
+    /// The current response is sent in full; the client's next requests are
+    /// denied until the budget window resets.
+    #[default]
+    Finish,
+    /// The current response is aborted with a body error, which closes the
+    /// HTTP/1 connection or resets the HTTP/2 stream.
+    Terminate,
+}
+
+/// A response body charging every data frame to a [`ByteBudget`].
+pub(crate) struct MeteredBody {
+    inner: Body,
+    budget: ByteBudget,
+    key: String,
+    cutoff: StreamCutoff,
+}
+
+impl MeteredBody {
+    pub(crate) fn new(inner: Body, budget: ByteBudget, key: String, cutoff: StreamCutoff) -> Self {
+        Self {
+            inner,
+            budget,
+            key,
+            cutoff,
+        }
+    }
+}
+
+impl http_body::Body for MeteredBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let frame = match Pin::new(&mut self.inner).poll_frame(cx) {
+            Poll::Ready(Some(Ok(frame))) => frame,
+            other => return other,
+        };
+
+        if let Some(data) = frame.data_ref() {
+            let within = self.budget.bytes().consume(&self.key, data.len() as u64);
+            if !within && self.cutoff == StreamCutoff::Terminate {
+                return Poll::Ready(Some(Err(axum::Error::new(
+                    "response byte budget exhausted",
+                ))));
+            }
+        }
+        Poll::Ready(Some(Ok(frame)))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body::Body as _;
+    use std::{convert::Infallible, time::Duration};
+
+    /// Polls `body` to the end, returning the data frames sent and whether
+    /// it failed.
+    async fn drain(body: MeteredBody) -> (Vec<Bytes>, bool) {
+        let mut body = std::pin::pin!(body);
+        let mut frames = Vec::new();
+        while let Some(frame) = std::future::poll_fn(|cx| body.as_mut().poll_frame(cx)).await {
+            match frame {
+                Ok(frame) => frames.extend(frame.into_data().ok()),
+                Err(_) => return (frames, true),
+            }
+        }
+        (frames, false)
+    }
+
+    fn metered(budget: &ByteBudget, cutoff: StreamCutoff) -> MeteredBody {
+        let chunks = ["aaaa", "bbbb", "cccc"].map(|chunk| Ok::<_, Infallible>(Bytes::from(chunk)));
+        let body = Body::from_stream(futures_util::stream::iter(chunks));
+        MeteredBody::new(body, budget.clone(), "203.0.113.7".to_string(), cutoff)
+    }
+
+    #[tokio::test]
+    async fn finishes_streams_past_the_budget() {
+        let budget = ByteBudget::new(6, Duration::from_secs(60));
+        let (frames, failed) = drain(metered(&budget, StreamCutoff::Finish)).await;
+        assert_eq!(frames.len(), 3);
+        assert!(!failed);
+        assert!(budget.bytes().is_exhausted("203.0.113.7"));
+    }
+
+    #[tokio::test]
+    async fn terminates_streams_past_the_budget() {
+        let budget = ByteBudget::new(6, Duration::from_secs(60));
+        let (frames, failed) = drain(metered(&budget, StreamCutoff::Terminate)).await;
+        assert_eq!(frames, [Bytes::from("aaaa")]);
+        assert!(failed);
+    }
+}
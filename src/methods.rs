@@ -0,0 +1,68 @@
+/* src/methods.rs */
+
+use axum::http::{HeaderValue, Method, StatusCode};
+
+/// How `TRACE` and `CONNECT` requests are treated.
+///
+/// Most apps never intend to serve these methods, yet by default they are
+/// limited like any other request and then passed on to the router.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MethodPolicy {
+    /// Limited like any other request.
+    #[default]
+    Limit,
+    /// Rejected with `405 Method Not Allowed` before any limit is charged.
+    Reject,
+    /// Rejected with `403 Forbidden` before any limit is charged.
+    Forbid,
+    /// Never limited.
+    Exempt,
+}
+
+impl MethodPolicy {
+    /// Returns the status to reject with, if the policy rejects.
+    pub(crate) fn rejection_status(self) -> Option<StatusCode> {
+        match self {
+            MethodPolicy::Reject => Some(StatusCode::METHOD_NOT_ALLOWED),
+            MethodPolicy::Forbid => Some(StatusCode::FORBIDDEN),
+            MethodPolicy::Limit | MethodPolicy::Exempt => None,
+        }
+    }
+}
+
+/// Returns `true` for the methods covered by [`MethodPolicy`].
+pub(crate) fn is_trace_or_connect(method: &Method) -> bool {
+    method == Method::TRACE || method == Method::CONNECT
+}
+
+/// Returns whether `allowed` permits `method`. `GET` implies `HEAD`.
+pub(crate) fn allows(allowed: &[Method], method: &Method) -> bool {
+    allowed.contains(method) || (method == Method::HEAD && allowed.contains(&Method::GET))
+}
+
+/// Returns the `Allow` header value listing `allowed`.
+pub(crate) fn allow_header(allowed: &[Method]) -> HeaderValue {
+    let mut methods: Vec<&str> = allowed.iter().map(Method::as_str).collect();
+    if allowed.contains(&Method::GET) && !allowed.contains(&Method::HEAD) {
+        methods.push(Method::HEAD.as_str());
+    }
+    HeaderValue::from_str(&methods.join(", ")).expect("method names are valid header values")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn get_implies_head() {
+        let allowed = [Method::GET, Method::POST];
+        assert!(allows(&allowed, &Method::GET));
+        assert!(allows(&allowed, &Method::HEAD));
+        assert!(!allows(&allowed, &Method::DELETE));
+        assert!(!allows(&[Method::POST], &Method::HEAD));
+
+        assert_eq!(allow_header(&allowed), "GET, POST, HEAD");
+        assert_eq!(allow_header(&[Method::GET, Method::HEAD]), "GET, HEAD");
+        assert_eq!(allow_header(&[Method::POST]), "POST");
+    }
+}
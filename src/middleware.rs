@@ -1,28 +1,37 @@
 /* src/middleware.rs */
 
-use crate::{map_method, GovernorConfig};
+use crate::{
+    check::{self, check},
+    metered::MeteredBody,
+    reservation::RefundOnPanic,
+    shed::InFlightBody,
+    GovernorConfig,
+};
 use axum::{
     body::Body,
-    http::{Request, Response, StatusCode},
+    http::{Request, Response},
 };
 use futures_util::future::BoxFuture;
-use real::RealIp;
 use std::{
     fmt,
+    sync::Arc,
     task::{Context, Poll},
 };
 use tower::Service;
-use tracing::warn;
 
 /// The middleware service that performs rate-limiting.
 #[derive(Clone)]
 pub struct GovernorMiddleware<S> {
     inner: S,
-    config: GovernorConfig,
+    config: Arc<GovernorConfig>,
 }
 
 impl<S> GovernorMiddleware<S> {
     pub fn new(inner: S, config: GovernorConfig) -> Self {
+        Self::from_shared(inner, Arc::new(config))
+    }
+
+    pub(crate) fn from_shared(inner: S, config: Arc<GovernorConfig>) -> Self {
         Self { inner, config }
     }
 }
@@ -56,46 +65,171 @@ where
     fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
         let mut inner = self.inner.clone();
         let config = self.config.clone();
-        let method = req.method().clone();
 
         Box::pin(async move {
-            // Extract the RealIp extension. This must be present.
-            // Ensure `RealIpLayer` is added *before* `GovernorLayer`.
-            let ip_ext = req.extensions().get::<RealIp>();
-
-            if ip_ext.is_none() {
-                warn!(
-                    "RealIp extension not found. Make sure RealIpLayer is installed before GovernorLayer."
-                );
-                let response = Response::builder()
-                    .status(StatusCode::INTERNAL_SERVER_ERROR)
-                    .body(Body::from(
-                        "Internal Server Error: Rate limiter misconfigured",
-                    ))
-                    .unwrap();
-                return Ok(response);
-            }
-
-            let ip_str = ip_ext.unwrap().ip().to_string();
-            let path = req.uri().path().to_string();
+            let in_flight = match &config.load_shed {
+                Some(shed) => {
+                    let path = check::rule_path(&config, req.uri(), req.extensions());
+                    match shed.acquire(check::criticality(&config, &path)) {
+                        Some(in_flight) => Some(in_flight),
+                        None => {
+                            return Ok(check::shed(&config, shed, req.version(), req.headers()));
+                        }
+                    }
+                }
+                None => None,
+            };
 
-            let allowed = if config.override_mode {
-                lazy_limit::limit_override!(&ip_str, &path, map_method(method)).await
-            } else {
-                lazy_limit::limit!(&ip_str, &path, map_method(method)).await
+            let (mut parts, body) = req.into_parts();
+            let admitted = match check(&config, &parts).await {
+                Ok(admitted) => admitted,
+                Err(response) => return Ok(response),
             };
+            parts.extensions.insert(admitted.tags);
+            let _refund = config
+                .refund_on_panic
+                .then(|| RefundOnPanic(admitted.charges));
 
-            if allowed {
-                // Request is allowed, pass it to the inner service.
-                inner.call(req).await
-            } else {
-                // Request is denied, return `429 Too Many Requests`.
-                let response = Response::builder()
-                    .status(StatusCode::TOO_MANY_REQUESTS)
-                    .body(Body::from("Too Many Requests"))
-                    .unwrap();
-                Ok(response)
+            // Request is allowed, pass it to the inner service.
+            let response = inner.call(Request::from_parts(parts, body)).await?;
+            if let Some(policy) = &config.retry_policy {
+                policy.record_response(&admitted.key, response.status());
             }
+            let response = match &config.response_budget {
+                Some(budget) => response.map(|body| {
+                    Body::new(MeteredBody::new(
+                        body,
+                        budget.clone(),
+                        admitted.key,
+                        config.stream_cutoff,
+                    ))
+                }),
+                None => response,
+            };
+            // Keep the request in flight while its body is streamed.
+            let response = match in_flight {
+                Some(in_flight) => {
+                    response.map(|body| Body::new(InFlightBody::new(body, in_flight)))
+                }
+                None => response,
+            };
+            Ok(response)
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        test_util, ByteBudget, Governor, GovernorLayer, LoadShed, Rejection, REJECTION_HEADER,
+    };
+    use axum::{
+        body::Body,
+        extract::ConnectInfo,
+        http::{header::CONTENT_LENGTH, Request, StatusCode},
+        routing::post,
+        Router,
+    };
+    use std::{net::SocketAddr, time::Duration};
+    use tower::ServiceExt;
+    use tower_http::catch_panic::CatchPanicLayer;
+
+    async fn panics() -> &'static str {
+        panic!("handler bug")
+    }
+
+    fn upload() -> Request<Body> {
+        let mut request = Request::post("/export")
+            .header(CONTENT_LENGTH, 10)
+            .body(Body::from("0123456789"))
+            .unwrap();
+        let addr = SocketAddr::new(test_util::CLIENT.parse().unwrap(), 40000);
+        request.extensions_mut().insert(ConnectInfo(addr));
+        request
+    }
+
+    #[tokio::test]
+    async fn handler_panic_refunds_charges_and_releases_slot() {
+        test_util::init_limiter().await;
+        let bytes = ByteBudget::new(10, Duration::from_secs(60));
+        let governor = Governor::new().budget("exports", 1, Duration::from_secs(60));
+        let shed = LoadShed::new(4);
+        let config = test_util::config()
+            .byte_budget(bytes.clone())
+            .governor(governor.clone())
+            .budget("/export", "exports")
+            .load_shed(shed.clone())
+            .refund_on_panic(true);
+        let app = Router::new()
+            .route("/export", post(panics))
+            .layer(GovernorLayer::new(config))
+            .layer(CatchPanicLayer::new());
+
+        let response = app.oneshot(upload()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(shed.in_flight(), 0);
+        assert!(bytes.bytes().charge(test_util::CLIENT, 10));
+        assert!(governor.charge_budget("exports", test_util::CLIENT, 1));
+    }
+
+    #[tokio::test]
+    async fn handler_panic_keeps_charges_by_default() {
+        test_util::init_limiter().await;
+        let bytes = ByteBudget::new(10, Duration::from_secs(60));
+        let shed = LoadShed::new(4);
+        let config = test_util::config()
+            .byte_budget(bytes.clone())
+            .load_shed(shed.clone());
+        let app = Router::new()
+            .route("/export", post(panics))
+            .layer(GovernorLayer::new(config))
+            .layer(CatchPanicLayer::new());
+
+        let response = app.oneshot(upload()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(shed.in_flight(), 0);
+        assert!(!bytes.bytes().charge(test_util::CLIENT, 1));
+    }
+
+    #[tokio::test]
+    async fn marks_only_governor_responses() {
+        test_util::init_limiter().await;
+        let governor = Governor::new().budget("exports", 1, Duration::from_secs(60));
+        let config = test_util::config()
+            .governor(governor)
+            .budget("/export", "exports");
+        let app = Router::new()
+            .route(
+                "/export",
+                post(|| async { (StatusCode::SERVICE_UNAVAILABLE, "handler error") }),
+            )
+            .layer(GovernorLayer::new(config));
+
+        let response = app.clone().oneshot(upload()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(!response.headers().contains_key(REJECTION_HEADER));
+        assert!(response.extensions().get::<Rejection>().is_none());
+
+        let response = app.oneshot(upload()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(
+            response.extensions().get::<Rejection>(),
+            Some(&Rejection::RateLimited)
+        );
+    }
+
+    #[tokio::test]
+    async fn streamed_body_holds_slot_until_dropped() {
+        test_util::init_limiter().await;
+        let shed = LoadShed::new(4);
+        let config = test_util::config().load_shed(shed.clone());
+        let app = Router::new()
+            .route("/export", post(|| async { "done" }))
+            .layer(GovernorLayer::new(config));
+
+        let response = app.oneshot(upload()).await.unwrap();
+        assert_eq!(shed.in_flight(), 1);
+        drop(response);
+        assert_eq!(shed.in_flight(), 0);
+    }
+}
@@ -0,0 +1,113 @@
+/* src/opa.rs */
+
+use crate::{PolicyQuery, PolicyVerdict};
+use serde_json::{json, Value};
+
+/// Translates between [`PolicyQuery`]s and the Open Policy Agent data API.
+///
+/// Lets rate-limit exceptions live in Rego next to existing authorization
+/// policies. The adapter only shapes the documents; the transport to OPA
+/// (an HTTP client, a sidecar or an embedded evaluator) stays yours:
+///
+/// ```rust
+/// # use axum_governor::{ExternalPolicy, GovernorConfig, OpaAdapter, PolicyQuery};
+/// # use serde_json::Value;
+/// # async fn post_to_opa(body: Value) -> Option<Value> { None }
+/// let policy = ExternalPolicy::new(|query: PolicyQuery| async move {
+///     // POST to e.g. http://localhost:8181/v1/data/ratelimit
+///     let response = post_to_opa(OpaAdapter::input(&query)).await?;
+///     Some(OpaAdapter::verdict(&response))
+/// });
+/// let config = GovernorConfig::new().external_policy(policy);
+/// ```
+///
+/// The input document looks like:
+///
+/// ```json
+/// {"input": {"key": "203.0.113.7", "method": "POST", "path": "/api/login", "tenant": null}}
+/// ```
+///
+/// The policy result may be a boolean (`false` denies) or an object with
+/// optional `allow` and `exempt` booleans, where `exempt` overrides the local
+/// limits. An undefined result leaves the decision to the local rules.
+/// Requires the `opa` feature.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OpaAdapter;
+
+impl OpaAdapter {
+    /// Returns the body of an OPA data API request for `query`.
+    pub fn input(query: &PolicyQuery) -> Value {
+        json!({
+            "input": {
+                "key": query.key,
+                "method": query.method.as_str(),
+                "path": query.path,
+                "tenant": query.tenant,
+            }
+        })
+    }
+
+    /// Interprets the body of an OPA data API response.
+    pub fn verdict(response: &Value) -> PolicyVerdict {
+        match response.get("result") {
+            Some(Value::Bool(false)) => PolicyVerdict::Deny,
+            Some(result @ Value::Object(_)) => {
+                if result.get("allow").and_then(Value::as_bool) == Some(false) {
+                    PolicyVerdict::Deny
+                } else if result.get("exempt").and_then(Value::as_bool) == Some(true) {
+                    PolicyVerdict::Exempt
+                } else {
+                    PolicyVerdict::Allow
+                }
+            }
+            _ => PolicyVerdict::Allow,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::Method;
+
+    #[test]
+    fn shapes_the_input_document() {
+        let query = PolicyQuery {
+            key: "203.0.113.7".to_string(),
+            method: Method::POST,
+            path: "/api/login".to_string(),
+            tenant: Some("acme".to_string()),
+        };
+        assert_eq!(
+            OpaAdapter::input(&query),
+            json!({"input": {
+                "key": "203.0.113.7",
+                "method": "POST",
+                "path": "/api/login",
+                "tenant": "acme",
+            }})
+        );
+    }
+
+    #[test]
+    fn reads_verdicts() {
+        for (response, verdict) in [
+            (json!({"result": true}), PolicyVerdict::Allow),
+            (json!({"result": false}), PolicyVerdict::Deny),
+            (json!({"result": {"allow": false}}), PolicyVerdict::Deny),
+            (
+                json!({"result": {"allow": false, "exempt": true}}),
+                PolicyVerdict::Deny,
+            ),
+            (json!({"result": {"exempt": true}}), PolicyVerdict::Exempt),
+            (json!({"result": {"allow": true}}), PolicyVerdict::Allow),
+            (json!({"result": {}}), PolicyVerdict::Allow),
+            // Undefined results and unexpected shapes leave the decision to
+            // the local rules.
+            (json!({}), PolicyVerdict::Allow),
+            (json!({"result": "deny"}), PolicyVerdict::Allow),
+        ] {
+            assert_eq!(OpaAdapter::verdict(&response), verdict, "{response}");
+        }
+    }
+}
@@ -0,0 +1,108 @@
+/* src/pacer.rs */
+
+use crate::expiring::ExpiringMap;
+use std::{
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::{Duration, Instant},
+};
+
+/// Admits requests of each key at a fixed pace.
+///
+/// Each key may pass one request per `interval`. Requests arriving faster
+/// are held back until their slot comes up, with up to `queue` of them
+/// waiting per key; further ones are denied. Designed for webhook receivers
+/// that must accept bursts from a provider but protect a slow downstream
+/// processor. Clones share the same state.
+///
+/// Held requests keep their connection open while waiting, so size `queue`
+/// with the provider's timeout in mind.
+#[derive(Debug, Clone)]
+pub struct Pacer {
+    interval: Duration,
+    queue: u32,
+    next_slots: Arc<Mutex<ExpiringMap<String, Instant>>>,
+}
+
+impl Pacer {
+    /// Creates a pacer releasing one request per `interval` and key, holding
+    /// up to `queue` more.
+    pub fn new(interval: Duration, queue: u32) -> Self {
+        Self {
+            interval,
+            queue,
+            next_slots: Arc::default(),
+        }
+    }
+
+    /// Reserves the next slot of `key`, returning how long to wait for it,
+    /// or `None` if the queue is full.
+    pub(crate) fn reserve(&self, key: &str) -> Option<Duration> {
+        let now = Instant::now();
+        self.next_slots().update(
+            key,
+            now,
+            || now,
+            |next| {
+                let slot = (*next).max(now);
+                let wait = slot - now;
+                if wait > self.interval * self.queue {
+                    return None;
+                }
+                *next = slot + self.interval;
+                Some(wait)
+            },
+        )
+    }
+
+    fn next_slots(&self) -> MutexGuard<'_, ExpiringMap<String, Instant>> {
+        self.next_slots
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{check::check, test_util};
+
+    /// Whether `wait` is `expected`, give or take the time the test took.
+    fn about(wait: Duration, expected: Duration) -> bool {
+        wait <= expected && expected - wait < Duration::from_secs(1)
+    }
+
+    #[test]
+    fn spaces_requests_by_interval() {
+        let interval = Duration::from_secs(60);
+        let pacer = Pacer::new(interval, 2);
+        assert_eq!(pacer.reserve("a"), Some(Duration::ZERO));
+        assert!(about(pacer.reserve("a").unwrap(), interval));
+        assert!(about(pacer.reserve("a").unwrap(), interval * 2));
+        // The queue of two is full.
+        assert_eq!(pacer.reserve("a"), None);
+        // Other keys have their own pace.
+        assert_eq!(pacer.reserve("b"), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn denied_requests_take_no_slot() {
+        let pacer = Pacer::new(Duration::from_secs(60), 0);
+        assert_eq!(pacer.reserve("a"), Some(Duration::ZERO));
+        assert_eq!(pacer.reserve("a"), None);
+        assert_eq!(pacer.reserve("a"), None);
+        assert_eq!(pacer.next_slots().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn holds_requests_until_their_slot() {
+        test_util::init_limiter().await;
+        let interval = Duration::from_millis(50);
+        let config = test_util::config().pacer(Pacer::new(interval, 1));
+
+        let start = Instant::now();
+        assert!(check(&config, &test_util::parts("/hook")).await.is_ok());
+        assert!(start.elapsed() < interval);
+        assert!(check(&config, &test_util::parts("/hook")).await.is_ok());
+        assert!(start.elapsed() >= interval);
+    }
+}
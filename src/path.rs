@@ -0,0 +1,154 @@
+/* src/path.rs */
+
+use std::{
+    borrow::Cow,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use tracing::warn;
+
+static WARNED: AtomicBool = AtomicBool::new(false);
+
+/// Which path of a request rules are matched against.
+///
+/// Where the layer sits in the router tree changes the URI it sees: a layer
+/// on a router mounted with `Router::nest("/api", ..)` sees `/users` for a
+/// request to `/api/users`, while a layer on the outer router sees
+/// `/api/users`. Pick the source that fits, so rules mean the same wherever
+/// the layer is placed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MatchSource {
+    /// The URI the layer sees, stripped of any nest prefix. The default.
+    #[default]
+    CurrentUri,
+    /// The full external path from axum's `OriginalUri` extension.
+    OriginalUri,
+    /// The route template from axum's `MatchedPath` extension, e.g.
+    /// `/api/users/{id}`, so one rule covers every user. Only set for layers
+    /// added with `Router::layer` or `route_layer`; unmatched requests fall
+    /// back to the current URI.
+    MatchedPath,
+}
+
+/// Returns the path the limiter matches rules against.
+///
+/// With `normalize` set, repeated slashes are collapsed and trailing slashes
+/// trimmed, as tower-http's `NormalizePathLayer::trim_trailing_slash` does, so
+/// the limiter sees the same path whether that layer runs before or after it.
+/// Otherwise the path is used as is, and the first unnormalized path seen
+/// triggers a one-time warning about the layer ordering.
+pub(crate) fn rule_path(path: &str, normalize: bool) -> Cow<'_, str> {
+    if !is_unnormalized(path) {
+        return Cow::Borrowed(path);
+    }
+
+    if normalize {
+        return Cow::Owned(normalized(path));
+    }
+
+    if !WARNED.swap(true, Ordering::Relaxed) {
+        warn!(
+            "Rate limiter saw the unnormalized path {path:?}. If you use NormalizePathLayer, \
+             wrap the whole router with it so it runs before GovernorLayer, or enable \
+             GovernorConfig::normalize_path."
+        );
+    }
+    Cow::Borrowed(path)
+}
+
+fn is_unnormalized(path: &str) -> bool {
+    (path.len() > 1 && path.ends_with('/')) || path.contains("//")
+}
+
+fn normalized(path: &str) -> String {
+    let mut normalized = String::with_capacity(path.len());
+    for segment in path.split('/').filter(|segment| !segment.is_empty()) {
+        normalized.push('/');
+        normalized.push_str(segment);
+    }
+    if normalized.is_empty() {
+        normalized.push('/');
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_util, Governor, GovernorConfig, GovernorLayer};
+    use axum::{
+        body::Body,
+        extract::ConnectInfo,
+        http::{Request, StatusCode},
+        routing::get,
+        Router,
+    };
+    use std::{net::SocketAddr, time::Duration};
+    use tower::ServiceExt;
+
+    #[test]
+    fn normalizes_only_when_asked() {
+        assert_eq!(rule_path("/api//users/", true), "/api/users");
+        assert_eq!(rule_path("//", true), "/");
+        assert_eq!(rule_path("/api/users", true), "/api/users");
+        assert_eq!(rule_path("/api//users/", false), "/api//users/");
+    }
+
+    /// An app with `config` on a router nested under `/api`, where the rule
+    /// path `budget_path` may be requested once.
+    fn nested_app(config: GovernorConfig, budget_path: &str) -> Router {
+        let governor = Governor::new().budget("once", 1, Duration::from_secs(60));
+        let config = config.governor(governor).budget(budget_path, "once");
+        let users = Router::new()
+            .route("/users/{id}", get(|| async { "user" }))
+            .layer(GovernorLayer::new(config));
+        Router::new().nest("/api", users)
+    }
+
+    async fn status(app: &Router, path: &str) -> StatusCode {
+        let mut request = Request::get(path).body(Body::empty()).unwrap();
+        let addr = SocketAddr::new(test_util::CLIENT.parse().unwrap(), 40000);
+        request.extensions_mut().insert(ConnectInfo(addr));
+        app.clone().oneshot(request).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn matched_path_applies_full_nested_rule() {
+        test_util::init_limiter().await;
+        let config = test_util::config().match_on(MatchSource::MatchedPath);
+        let app = nested_app(config, "/api/users/{id}");
+
+        assert_eq!(status(&app, "/api/users/1").await, StatusCode::OK);
+        // Another user hits the same route template.
+        assert_eq!(
+            status(&app, "/api/users/2").await,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+    }
+
+    #[tokio::test]
+    async fn original_uri_applies_full_nested_path() {
+        test_util::init_limiter().await;
+        let config = test_util::config().match_on(MatchSource::OriginalUri);
+        let app = nested_app(config, "/api/users/1");
+
+        assert_eq!(status(&app, "/api/users/1").await, StatusCode::OK);
+        assert_eq!(
+            status(&app, "/api/users/1").await,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+        assert_eq!(status(&app, "/api/users/2").await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn current_uri_sees_stripped_path() {
+        test_util::init_limiter().await;
+        let app = nested_app(test_util::config(), "/users/1");
+
+        assert_eq!(status(&app, "/api/users/1").await, StatusCode::OK);
+        assert_eq!(
+            status(&app, "/api/users/1").await,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+        assert_eq!(status(&app, "/api/users/2").await, StatusCode::OK);
+    }
+}
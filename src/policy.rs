@@ -0,0 +1,232 @@
+/* src/policy.rs */
+
+use crate::expiring::ExpiringMap;
+use axum::http::Method;
+use futures_util::future::BoxFuture;
+use std::{
+    fmt,
+    future::Future,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::{Duration, Instant},
+};
+use tracing::warn;
+
+/// A request as presented to a [`PolicyService`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PolicyQuery {
+    /// The client key, e.g. the client IP.
+    pub key: String,
+    /// The request method.
+    pub method: Method,
+    /// The path matched against rules.
+    pub path: String,
+    /// The tenant of the request, if resolved.
+    pub tenant: Option<String>,
+}
+
+/// The verdict of a [`PolicyService`] on a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PolicyVerdict {
+    /// No objection; the local rules decide.
+    Allow,
+    /// Deny the request, whatever the local rules say.
+    Deny,
+    /// Let the request through without applying the local rules.
+    Exempt,
+}
+
+/// A central abuse or policy platform consulted for every limited request.
+///
+/// Returns `None` if no verdict could be obtained, e.g. because the platform
+/// is unreachable; the [`ExternalPolicy`] failure policy applies then.
+///
+/// Any `Fn(PolicyQuery) -> impl Future<Output = Option<PolicyVerdict>>`
+/// closure is also a `PolicyService`.
+pub trait PolicyService: Send + Sync + 'static {
+    /// Returns the verdict on `query`.
+    fn decide(&self, query: PolicyQuery) -> BoxFuture<'static, Option<PolicyVerdict>>;
+}
+
+impl<F, Fut> PolicyService for F
+where
+    F: Fn(PolicyQuery) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Option<PolicyVerdict>> + Send + 'static,
+{
+    fn decide(&self, query: PolicyQuery) -> BoxFuture<'static, Option<PolicyVerdict>> {
+        Box::pin(self(query))
+    }
+}
+
+/// Merges the verdicts of an external [`PolicyService`] with local rules.
+///
+/// Consulted for every request that is not banned, allowlisted or otherwise
+/// exempt, before the local limits. A `Deny` verdict denies the request like
+/// an exceeded limit, `Exempt` skips the local limits, and `Allow` leaves the
+/// decision to them.
+///
+/// Calls are bounded by a timeout (100 ms by default), and verdicts are
+/// cached per key, method and path for a while (one second by default), so
+/// the platform sees a fraction of the traffic. When no verdict arrives in
+/// time, the request is allowed (fail open, the default) or denied (fail
+/// closed). Clones share the same cache.
+#[derive(Clone)]
+pub struct ExternalPolicy {
+    service: Arc<dyn PolicyService>,
+    timeout: Duration,
+    cache_ttl: Duration,
+    fail_open: bool,
+    cache: Arc<Mutex<ExpiringMap<String, (PolicyVerdict, Instant)>>>,
+}
+
+impl fmt::Debug for ExternalPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ExternalPolicy")
+            .field("timeout", &self.timeout)
+            .field("cache_ttl", &self.cache_ttl)
+            .field("fail_open", &self.fail_open)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ExternalPolicy {
+    /// Creates a policy consulting `service`.
+    pub fn new(service: impl PolicyService) -> Self {
+        Self {
+            service: Arc::new(service),
+            timeout: Duration::from_millis(100),
+            cache_ttl: Duration::from_secs(1),
+            fail_open: true,
+            cache: Arc::default(),
+        }
+    }
+
+    /// Sets how long to wait for a verdict.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets how long verdicts are reused. `Duration::ZERO` disables caching.
+    pub fn cache_ttl(mut self, ttl: Duration) -> Self {
+        self.cache_ttl = ttl;
+        self
+    }
+
+    /// Sets whether requests are allowed (`true`, the default) or denied
+    /// when the service gives no verdict in time.
+    pub fn fail_open(mut self, fail_open: bool) -> Self {
+        self.fail_open = fail_open;
+        self
+    }
+
+    /// Returns the verdict on `query`, from the cache or the service.
+    pub(crate) async fn decide(&self, query: PolicyQuery) -> PolicyVerdict {
+        let id = format!("{} {} {}", query.key, query.method, query.path);
+        let now = Instant::now();
+        if let Some((verdict, until)) = self.cache().get(&id)
+            && *until > now
+        {
+            return *verdict;
+        }
+
+        let verdict = match tokio::time::timeout(self.timeout, self.service.decide(query)).await {
+            Ok(Some(verdict)) => verdict,
+            Ok(None) | Err(_) => {
+                warn!(
+                    "External policy gave no verdict, failing {}",
+                    self.failure_mode()
+                );
+                return self.failure_verdict();
+            }
+        };
+
+        if !self.cache_ttl.is_zero() {
+            self.cache()
+                .insert(id, (verdict, now + self.cache_ttl), now);
+        }
+        verdict
+    }
+
+    fn failure_verdict(&self) -> PolicyVerdict {
+        if self.fail_open {
+            PolicyVerdict::Allow
+        } else {
+            PolicyVerdict::Deny
+        }
+    }
+
+    fn failure_mode(&self) -> &'static str {
+        if self.fail_open {
+            "open"
+        } else {
+            "closed"
+        }
+    }
+
+    fn cache(&self) -> MutexGuard<'_, ExpiringMap<String, (PolicyVerdict, Instant)>> {
+        self.cache.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn query(path: &str) -> PolicyQuery {
+        PolicyQuery {
+            key: "203.0.113.7".to_string(),
+            method: Method::GET,
+            path: path.to_string(),
+            tenant: None,
+        }
+    }
+
+    /// A service denying `/admin` and counting its calls in `calls`.
+    fn service(calls: Arc<AtomicUsize>) -> impl PolicyService {
+        move |query: PolicyQuery| {
+            calls.fetch_add(1, Ordering::Relaxed);
+            async move {
+                Some(match query.path.as_str() {
+                    "/admin" => PolicyVerdict::Deny,
+                    _ => PolicyVerdict::Allow,
+                })
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn caches_verdicts() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let policy = ExternalPolicy::new(service(calls.clone()));
+        for _ in 0..2 {
+            assert_eq!(policy.decide(query("/admin")).await, PolicyVerdict::Deny);
+            assert_eq!(policy.decide(query("/")).await, PolicyVerdict::Allow);
+        }
+        assert_eq!(calls.load(Ordering::Relaxed), 2);
+
+        let policy = ExternalPolicy::new(service(calls.clone())).cache_ttl(Duration::ZERO);
+        for _ in 0..2 {
+            assert_eq!(policy.decide(query("/admin")).await, PolicyVerdict::Deny);
+        }
+        assert_eq!(calls.load(Ordering::Relaxed), 4);
+    }
+
+    #[tokio::test]
+    async fn applies_the_failure_policy() {
+        let silent = |_: PolicyQuery| async { None };
+        let policy = ExternalPolicy::new(silent);
+        assert_eq!(policy.decide(query("/")).await, PolicyVerdict::Allow);
+        let policy = policy.fail_open(false);
+        assert_eq!(policy.decide(query("/")).await, PolicyVerdict::Deny);
+
+        let slow = |_: PolicyQuery| async {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            Some(PolicyVerdict::Exempt)
+        };
+        let policy = ExternalPolicy::new(slow)
+            .timeout(Duration::from_millis(10))
+            .fail_open(false);
+        assert_eq!(policy.decide(query("/")).await, PolicyVerdict::Deny);
+    }
+}
@@ -0,0 +1,206 @@
+/* src/prefilter.rs */
+
+use std::{
+    fmt,
+    hash::{DefaultHasher, Hash, Hasher},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+/// A probabilistic "have we seen this key before?" filter.
+///
+/// When attached to a `GovernorConfig`, the first request of a key that the
+/// filter has never seen skips the limiter, so it neither touches the
+/// `lazy-limit` store nor the built-in engine. Only from the second request on
+/// is the key counted there. This keeps one-hit clients (scanners, crawlers
+/// that never return) from filling the store, at the cost of granting every
+/// new key one extra request. All other checks still apply to that first
+/// request: key levels, byte and named budgets, the SLO guard, the tenant cap
+/// and the pacer can deny or charge it as usual.
+///
+/// The filter keeps two Bloom filters, each sized for `expected_keys`: new
+/// keys go into the current one, and a key counts as seen if either holds it.
+/// Once the current filter is full, the older one is cleared and takes its
+/// place, so the filter never saturates and a key seen recently is still
+/// remembered after a rotation rather than getting another free request.
+/// Clones share the same underlying bits.
+#[derive(Clone)]
+pub struct FirstSeenFilter {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    generations: [Box<[AtomicU64]>; 2],
+    current: AtomicUsize,
+    mask: u64,
+    hashes: u32,
+    capacity: usize,
+    inserted: AtomicUsize,
+}
+
+impl FirstSeenFilter {
+    /// Creates a filter sized for `expected_keys` distinct keys with the given
+    /// false-positive rate (e.g. `0.01` for 1%).
+    ///
+    /// A false positive means a brand-new key is treated as already seen and
+    /// is counted right away, which is the safe direction.
+    pub fn new(expected_keys: usize, false_positive_rate: f64) -> Self {
+        let capacity = expected_keys.max(1);
+        let rate = false_positive_rate.clamp(1e-9, 0.5);
+        let ln2 = std::f64::consts::LN_2;
+
+        // A power of two, so the odd probe step of double hashing visits
+        // distinct bits.
+        let bit_count = (-(capacity as f64) * rate.ln() / (ln2 * ln2)).ceil() as usize;
+        let bit_count = bit_count.max(64).next_power_of_two();
+        let hashes = (bit_count as f64 / capacity as f64 * ln2)
+            .round()
+            .clamp(1.0, 64.0) as u32;
+        let generation = || (0..bit_count / 64).map(|_| AtomicU64::new(0)).collect();
+
+        Self {
+            inner: Arc::new(Inner {
+                generations: [generation(), generation()],
+                current: AtomicUsize::new(0),
+                mask: bit_count as u64 - 1,
+                hashes,
+                capacity,
+                inserted: AtomicUsize::new(0),
+            }),
+        }
+    }
+
+    /// Records `key` and returns whether it was (probably) seen before.
+    pub(crate) fn check_and_insert(&self, key: &str) -> bool {
+        let inner = &self.inner;
+        let current = inner.current.load(Ordering::Acquire);
+        let (h1, h2) = hash_pair(key);
+
+        let mut in_current = true;
+        let mut in_previous = true;
+        for i in 0..u64::from(inner.hashes) {
+            let bit = h1.wrapping_add(i.wrapping_mul(h2)) & inner.mask;
+            let word = (bit / 64) as usize;
+            let mask = 1u64 << (bit % 64);
+            let prev = inner.generations[current][word].fetch_or(mask, Ordering::Relaxed);
+            in_current &= prev & mask != 0;
+            if in_previous {
+                let word = inner.generations[1 - current][word].load(Ordering::Relaxed);
+                in_previous = word & mask != 0;
+            }
+        }
+
+        // Exactly one caller sees the count reach capacity and rotates.
+        if !in_current && inner.inserted.fetch_add(1, Ordering::Relaxed) + 1 == inner.capacity {
+            self.rotate(current);
+        }
+
+        in_current || in_previous
+    }
+
+    /// Clears the older generation and makes it the current one.
+    fn rotate(&self, current: usize) {
+        let inner = &self.inner;
+        let next = 1 - current;
+        for word in inner.generations[next].iter() {
+            word.store(0, Ordering::Relaxed);
+        }
+        inner.current.store(next, Ordering::Release);
+        inner.inserted.store(0, Ordering::Relaxed);
+    }
+
+    /// Forgets every key recorded so far.
+    pub fn clear(&self) {
+        for word in self.inner.generations.iter().flat_map(|bits| bits.iter()) {
+            word.store(0, Ordering::Relaxed);
+        }
+        self.inner.inserted.store(0, Ordering::Relaxed);
+    }
+}
+
+impl fmt::Debug for FirstSeenFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FirstSeenFilter")
+            .field("bits", &(self.inner.mask + 1))
+            .field("hashes", &self.inner.hashes)
+            .field("capacity", &self.inner.capacity)
+            .finish()
+    }
+}
+
+/// Derives two independent hashes for double hashing.
+fn hash_pair(key: &str) -> (u64, u64) {
+    let mut first = DefaultHasher::new();
+    key.hash(&mut first);
+
+    let mut second = DefaultHasher::new();
+    0x9e37_79b9_7f4a_7c15u64.hash(&mut second);
+    key.hash(&mut second);
+
+    // An odd step is coprime with the power-of-two bit count, so the probe
+    // sequence visits distinct bits.
+    (first.finish(), second.finish() | 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Inserts fresh keys until the current generation is full and rotates.
+    fn fill_until_rotated(filter: &FirstSeenFilter, tag: &str) {
+        let current = filter.inner.current.load(Ordering::Relaxed);
+        for i in 0.. {
+            filter.check_and_insert(&format!("{tag}-{i}"));
+            if filter.inner.current.load(Ordering::Relaxed) != current {
+                return;
+            }
+            assert!(i < 1_000, "filter never rotated");
+        }
+    }
+
+    #[test]
+    fn second_request_is_seen() {
+        let filter = FirstSeenFilter::new(1_000, 0.01);
+        assert!(!filter.check_and_insert("a"));
+        assert!(filter.check_and_insert("a"));
+        assert!(!filter.check_and_insert("b"));
+    }
+
+    #[test]
+    fn probes_distinct_bits() {
+        let filter = FirstSeenFilter::new(1_000, 0.01);
+        let inner = &filter.inner;
+        let (h1, h2) = hash_pair("key");
+        let bits = (0..u64::from(inner.hashes))
+            .map(|i| h1.wrapping_add(i.wrapping_mul(h2)) & inner.mask)
+            .collect::<std::collections::HashSet<_>>();
+        assert_eq!(bits.len(), inner.hashes as usize);
+    }
+
+    #[test]
+    fn keys_survive_one_rotation() {
+        let filter = FirstSeenFilter::new(100, 0.01);
+        assert!(!filter.check_and_insert("kept"));
+        fill_until_rotated(&filter, "a");
+        assert!(filter.check_and_insert("kept"));
+    }
+
+    #[test]
+    fn keys_expire_after_two_rotations() {
+        let filter = FirstSeenFilter::new(100, 0.01);
+        assert!(!filter.check_and_insert("dropped"));
+        fill_until_rotated(&filter, "a");
+        fill_until_rotated(&filter, "b");
+        assert!(!filter.check_and_insert("dropped"));
+    }
+
+    #[test]
+    fn clear_forgets_both_generations() {
+        let filter = FirstSeenFilter::new(100, 0.01);
+        filter.check_and_insert("a");
+        filter.clear();
+        assert!(!filter.check_and_insert("a"));
+    }
+}
@@ -0,0 +1,104 @@
+/* src/rejection.rs */
+
+use axum::{
+    body::Body,
+    http::{HeaderName, HeaderValue, Method, Response},
+};
+use std::{sync::Arc, time::Duration};
+
+/// Header added to every response produced by the governor itself.
+///
+/// Its value is the [`Rejection::as_str`] of the reason, e.g.
+/// `x-governor-rejected: rate-limited`. Responses from the inner service never
+/// carry it, so proxies and dashboards can tell throttling apart from
+/// handler errors.
+pub const REJECTION_HEADER: HeaderName = HeaderName::from_static("x-governor-rejected");
+
+/// A function building the response for rate-limited requests.
+pub type DeniedHandler = Arc<dyn Fn(&DeniedInfo) -> Response<Body> + Send + Sync>;
+
+/// Details of a rate-limited request, passed to the
+/// [`on_denied`](crate::GovernorConfig::on_denied) handler.
+#[derive(Debug, Clone)]
+pub struct DeniedInfo {
+    /// The client key, e.g. the client IP.
+    pub key: String,
+    /// The request method.
+    pub method: Method,
+    /// The path matched against rules.
+    pub path: String,
+    /// The tier of the layer, if set.
+    pub tier: Option<String>,
+    /// The tenant of the request, if resolved.
+    pub tenant: Option<String>,
+    /// How long until the limiter admits the key again, if it knows. Only
+    /// the built-in engine reports this.
+    pub retry_after: Option<Duration>,
+}
+
+/// Why the governor answered a request instead of the inner service.
+///
+/// Inserted as a response extension on every governor-produced response, so
+/// outer layers (e.g. `tower_http::classify` based tracing or metrics) can
+/// look it up with `response.extensions().get::<Rejection>()` and exclude
+/// throttled traffic from application error rates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rejection {
+    /// The client exceeded its rate limit.
+    RateLimited,
+    /// The client is banned.
+    Banned,
+    /// The limiter could not run, e.g. because `RealIpLayer` is missing.
+    Misconfigured,
+    /// The request carried malformed or conflicting forwarded-IP headers.
+    MalformedHeaders,
+    /// The request method is refused by the [`MethodPolicy`](crate::MethodPolicy).
+    ForbiddenMethod,
+    /// The server is overloaded and sheds load. See
+    /// [`LoadShed`](crate::LoadShed).
+    Overloaded,
+    /// The [`KeyExtractor`](crate::KeyExtractor) refused the request's key.
+    InvalidKey,
+    /// The request's tenant used up its [`TenantCap`](crate::TenantCap).
+    TenantQuota,
+}
+
+impl Rejection {
+    /// Returns the value used for [`REJECTION_HEADER`].
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Rejection::RateLimited => "rate-limited",
+            Rejection::Banned => "banned",
+            Rejection::Misconfigured => "misconfigured",
+            Rejection::MalformedHeaders => "malformed-headers",
+            Rejection::ForbiddenMethod => "forbidden-method",
+            Rejection::Overloaded => "overloaded",
+            Rejection::InvalidKey => "invalid-key",
+            Rejection::TenantQuota => "tenant-quota",
+        }
+    }
+
+    /// Tags `response` with this rejection as extension and header.
+    pub(crate) fn mark(self, mut response: Response<Body>) -> Response<Body> {
+        response
+            .headers_mut()
+            .insert(REJECTION_HEADER, HeaderValue::from_static(self.as_str()));
+        response.extensions_mut().insert(self);
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn marks_responses() {
+        let response = Rejection::Banned.mark(Response::new(Body::empty()));
+        assert_eq!(response.headers()[REJECTION_HEADER], "banned");
+        assert_eq!(
+            response.extensions().get::<Rejection>(),
+            Some(&Rejection::Banned)
+        );
+    }
+}
@@ -0,0 +1,313 @@
+/* src/reports.rs */
+
+use crate::Decision;
+use std::{
+    cmp::Reverse,
+    collections::HashMap,
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, MutexGuard, PoisonError,
+    },
+    time::{Duration, Instant},
+};
+use tokio::task::JoinHandle;
+
+/// Number of distinct keys or paths tallied per report period. Further ones
+/// are only counted in the totals.
+const MAX_TRACKED: usize = 4096;
+
+/// Collects sampled decisions into periodic abuse summaries.
+///
+/// Attach it with `GovernorConfig::abuse_reports` and either call
+/// [`report`](Self::report) yourself or let [`spawn`](Self::spawn) deliver a
+/// summary to a hook every period, e.g. to log it or append it to a file:
+///
+/// ```rust,no_run
+/// # use axum_governor::AbuseReports;
+/// # use std::{io::Write, time::Duration};
+/// # async {
+/// let reports = AbuseReports::new(10);
+/// reports.spawn(Duration::from_secs(3600), 10, |report| {
+///     let mut file = std::fs::OpenOptions::new()
+///         .create(true)
+///         .append(true)
+///         .open("/var/log/governor-abuse.log")
+///         .unwrap();
+///     writeln!(file, "{report}").unwrap();
+/// });
+/// # };
+/// ```
+///
+/// Only every `sample_every`-th decision is tallied, and counts are scaled
+/// back up, so reports are estimates that stay cheap under heavy traffic.
+/// Clones share the same tallies.
+#[derive(Debug, Clone)]
+pub struct AbuseReports {
+    sample_every: u64,
+    seen: Arc<AtomicU64>,
+    tallies: Arc<Mutex<Tallies>>,
+}
+
+#[derive(Debug)]
+struct Tallies {
+    since: Instant,
+    rejected_keys: HashMap<String, u64>,
+    routes: HashMap<String, RouteStats>,
+    bans: u64,
+}
+
+impl Default for Tallies {
+    fn default() -> Self {
+        Self {
+            since: Instant::now(),
+            rejected_keys: HashMap::new(),
+            routes: HashMap::new(),
+            bans: 0,
+        }
+    }
+}
+
+/// A summary of the decisions made since the previous report.
+#[derive(Debug, Clone)]
+pub struct AbuseReport {
+    /// The time covered by this report.
+    pub period: Duration,
+    /// The keys with the most rejections, most rejected first.
+    pub top_rejected_keys: Vec<(String, u64)>,
+    /// The routes with the highest rejection ratio, highest first.
+    pub top_rejected_routes: Vec<RouteStats>,
+    /// The number of requests rejected because the client was banned.
+    pub bans: u64,
+}
+
+/// Request counts of a single route.
+#[derive(Debug, Clone, Default)]
+pub struct RouteStats {
+    /// The axum route template the requests matched, e.g. `/users/{id}`, or
+    /// the rule path outside a router, prefixed with `tenant:` if the
+    /// request had a tenant.
+    pub path: String,
+    /// The estimated number of requests.
+    pub requests: u64,
+    /// The estimated number of rejected requests.
+    pub rejected: u64,
+}
+
+impl RouteStats {
+    /// Returns the share of rejected requests, between 0 and 1.
+    pub fn rejection_ratio(&self) -> f64 {
+        if self.requests == 0 {
+            0.0
+        } else {
+            self.rejected as f64 / self.requests as f64
+        }
+    }
+}
+
+impl AbuseReports {
+    /// Creates a collector tallying every `sample_every`-th decision. Use `1`
+    /// to tally all of them.
+    pub fn new(sample_every: u64) -> Self {
+        Self {
+            sample_every: sample_every.max(1),
+            seen: Arc::default(),
+            tallies: Arc::default(),
+        }
+    }
+
+    /// Returns the summary since the previous report, with up to `top` keys
+    /// and routes, and starts a new period.
+    pub fn report(&self, top: usize) -> AbuseReport {
+        let tallies = std::mem::take(&mut *self.tallies());
+
+        let mut top_rejected_keys: Vec<_> = tallies.rejected_keys.into_iter().collect();
+        top_rejected_keys.sort_by_key(|(_, count)| Reverse(*count));
+        top_rejected_keys.truncate(top);
+
+        let mut top_rejected_routes: Vec<_> = tallies
+            .routes
+            .into_values()
+            .filter(|route| route.rejected > 0)
+            .collect();
+        top_rejected_routes.sort_by(|a, b| b.rejection_ratio().total_cmp(&a.rejection_ratio()));
+        top_rejected_routes.truncate(top);
+
+        AbuseReport {
+            period: tallies.since.elapsed(),
+            top_rejected_keys,
+            top_rejected_routes,
+            bans: tallies.bans,
+        }
+    }
+
+    /// Spawns a task passing a report with up to `top` entries to `hook`
+    /// every `period`.
+    ///
+    /// Must be called within a Tokio runtime. Abort the returned handle to
+    /// stop reporting.
+    pub fn spawn<F>(&self, period: Duration, top: usize, hook: F) -> JoinHandle<()>
+    where
+        F: Fn(AbuseReport) + Send + 'static,
+    {
+        let reports = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            // The first tick completes immediately.
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                hook(reports.report(top));
+            }
+        })
+    }
+
+    pub(crate) fn record(&self, key: &str, path: &str, decision: Decision) {
+        if !self
+            .seen
+            .fetch_add(1, Ordering::Relaxed)
+            .is_multiple_of(self.sample_every)
+        {
+            return;
+        }
+
+        let weight = self.sample_every;
+        let mut tallies = self.tallies();
+        let rejected = match decision {
+            Decision::Allowed => 0,
+            Decision::Denied => weight,
+            Decision::Banned => {
+                tallies.bans += weight;
+                weight
+            }
+        };
+
+        if rejected > 0 {
+            let tracked = tallies.rejected_keys.len();
+            match tallies.rejected_keys.get_mut(key) {
+                Some(count) => *count += rejected,
+                None if tracked < MAX_TRACKED => {
+                    tallies.rejected_keys.insert(key.to_string(), rejected);
+                }
+                None => {}
+            }
+        }
+
+        let tracked = tallies.routes.len();
+        let route = match tallies.routes.get_mut(path) {
+            Some(route) => route,
+            None if tracked < MAX_TRACKED => {
+                tallies
+                    .routes
+                    .entry(path.to_string())
+                    .or_insert(RouteStats {
+                        path: path.to_string(),
+                        ..RouteStats::default()
+                    })
+            }
+            None => return,
+        };
+        route.requests += weight;
+        route.rejected += rejected;
+    }
+
+    fn tallies(&self) -> MutexGuard<'_, Tallies> {
+        self.tallies.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl fmt::Display for AbuseReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Abuse report for the last {}s: {} banned requests",
+            self.period.as_secs(),
+            self.bans
+        )?;
+        writeln!(f, "Top rejected keys:")?;
+        for (key, count) in &self.top_rejected_keys {
+            writeln!(f, "  {key}: {count}")?;
+        }
+        writeln!(f, "Routes by rejection ratio:")?;
+        for route in &self.top_rejected_routes {
+            writeln!(
+                f,
+                "  {}: {}/{} rejected ({:.1}%)",
+                route.path,
+                route.rejected,
+                route.requests,
+                route.rejection_ratio() * 100.0
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_util, Governor, GovernorLayer};
+    use axum::{body::Body, extract::ConnectInfo, http::Request, routing::get, Router};
+    use std::net::{IpAddr, SocketAddr};
+    use tower::ServiceExt;
+
+    #[test]
+    fn tallies_rejections_by_key_and_route() {
+        let reports = AbuseReports::new(1);
+        reports.record("a", "/login", Decision::Denied);
+        reports.record("a", "/login", Decision::Denied);
+        reports.record("b", "/login", Decision::Allowed);
+        reports.record("b", "/home", Decision::Allowed);
+        reports.record("c", "/admin", Decision::Banned);
+
+        let report = reports.report(10);
+        assert_eq!(report.bans, 1);
+        assert_eq!(report.top_rejected_keys[0], ("a".to_string(), 2));
+        let routes: Vec<_> = report
+            .top_rejected_routes
+            .iter()
+            .map(|route| (route.path.as_str(), route.rejected, route.requests))
+            .collect();
+        assert_eq!(routes, [("/admin", 1, 1), ("/login", 2, 3)]);
+
+        // The next report starts afresh.
+        assert!(reports.report(10).top_rejected_routes.is_empty());
+    }
+
+    #[test]
+    fn samples_and_scales_up() {
+        let reports = AbuseReports::new(2);
+        for _ in 0..4 {
+            reports.record("a", "/login", Decision::Denied);
+        }
+        assert_eq!(reports.report(10).top_rejected_keys, [("a".to_string(), 4)]);
+    }
+
+    #[tokio::test]
+    async fn tallies_route_templates() {
+        test_util::init_limiter().await;
+        let reports = AbuseReports::new(1);
+        let governor = Governor::new();
+        governor.denylist_add(test_util::CLIENT.parse::<IpAddr>().unwrap());
+        let config = test_util::config()
+            .abuse_reports(reports.clone())
+            .governor(governor);
+        let app = Router::new()
+            .route("/users/{id}", get(|| async { "user" }))
+            .layer(GovernorLayer::new(config));
+
+        for id in 0..3 {
+            let mut request = Request::get(format!("/users/{id}"))
+                .body(Body::empty())
+                .unwrap();
+            let addr = SocketAddr::new(test_util::CLIENT.parse().unwrap(), 40000);
+            request.extensions_mut().insert(ConnectInfo(addr));
+            app.clone().oneshot(request).await.unwrap();
+        }
+
+        let report = reports.report(10);
+        assert_eq!(report.top_rejected_routes.len(), 1);
+        assert_eq!(report.top_rejected_routes[0].path, "/users/{id}");
+        assert_eq!(report.top_rejected_routes[0].rejected, 3);
+    }
+}
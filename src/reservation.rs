@@ -0,0 +1,288 @@
+/* src/reservation.rs */
+
+use crate::count_budget::CountBudget;
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, MutexGuard, PoisonError, Weak,
+    },
+    time::{Duration, Instant},
+};
+use tracing::warn;
+
+/// How long a reservation may stay open before it expires by default.
+const DEFAULT_TTL: Duration = Duration::from_secs(600);
+
+/// Quota reserved from a named budget with [`Governor::reserve`](crate::Governor::reserve).
+///
+/// The reserved cost counts against the budget right away. Call
+/// [`commit`](Self::commit) once the operation went through to keep the
+/// charge, or [`cancel`](Self::cancel) to give it back. A reservation dropped
+/// without either, e.g. because the handler returned early or its future was
+/// aborted, is cancelled.
+///
+/// A reservation still open after the TTL set with
+/// [`Governor::reservation_ttl`](crate::Governor::reservation_ttl) expires: its
+/// cost is given back with a warning and counted in
+/// [`Governor::expired_reservations`](crate::Governor::expired_reservations),
+/// so a reservation leaked by buggy handler code can't hold capacity forever.
+/// Committing an expired reservation has no effect. Nothing is given back
+/// once the budget window the cost was charged to has reset.
+#[derive(Debug)]
+#[must_use = "a reservation is cancelled when dropped"]
+pub struct Reservation {
+    slot: Option<Slot>,
+}
+
+type Slot = Arc<Mutex<Option<Charge>>>;
+
+/// A cost charged to a budget, which can be given back later.
+#[derive(Debug)]
+pub(crate) struct Charge {
+    budget: CountBudget,
+    key: String,
+    cost: u64,
+    window: Instant,
+}
+
+impl Charge {
+    /// Charges `cost` to `key` and appends the charge to `charges`,
+    /// returning `false` if that would exceed `budget`.
+    pub(crate) fn record(
+        budget: &CountBudget,
+        key: &str,
+        cost: u64,
+        charges: &mut Vec<Self>,
+    ) -> bool {
+        let Some(window) = budget.charge_window(key, cost) else {
+            return false;
+        };
+        charges.push(Self {
+            budget: budget.clone(),
+            key: key.to_string(),
+            cost,
+            window,
+        });
+        true
+    }
+
+    pub(crate) fn refund(self) {
+        self.budget.refund(&self.key, self.cost, self.window);
+    }
+}
+
+/// Gives back the charges of an admitted request if dropped while its
+/// handler panics.
+///
+/// Held across the call of the inner service: a panic unwinds through the
+/// middleware future, which drops the guard while the thread is panicking,
+/// no matter whether a `CatchPanic` layer further out turns the panic into a
+/// response. A request that completes or is merely aborted keeps its charges.
+pub(crate) struct RefundOnPanic(pub(crate) Vec<Charge>);
+
+impl Drop for RefundOnPanic {
+    fn drop(&mut self) {
+        if std::thread::panicking() {
+            for charge in self.0.drain(..) {
+                charge.refund();
+            }
+        }
+    }
+}
+
+impl Reservation {
+    /// A reservation of an undefined budget, which charges nothing.
+    pub(crate) fn unlimited() -> Self {
+        Self { slot: None }
+    }
+
+    /// Keeps the reserved cost charged.
+    pub fn commit(mut self) {
+        if let Some(slot) = self.slot.take()
+            && take(&slot).is_none()
+        {
+            warn!("Committed a reservation that had already expired");
+        }
+    }
+
+    /// Gives the reserved cost back to the budget.
+    pub fn cancel(self) {
+        // Dropping refunds the charge.
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        if let Some(charge) = self.slot.as_ref().and_then(take) {
+            charge.refund();
+        }
+    }
+}
+
+fn take(slot: &Slot) -> Option<Charge> {
+    slot.lock().unwrap_or_else(PoisonError::into_inner).take()
+}
+
+/// The open reservations of a `Governor`, expired lazily whenever a budget is
+/// charged.
+#[derive(Debug)]
+pub(crate) struct Ledger {
+    state: Mutex<LedgerState>,
+    expired: AtomicU64,
+}
+
+#[derive(Debug)]
+struct LedgerState {
+    ttl: Duration,
+    /// The reservations by deadline, with a sequence number to tell apart
+    /// those sharing one. Settled reservations stay until their deadline.
+    open: BTreeMap<(Instant, u64), Weak<Mutex<Option<Charge>>>>,
+    next_id: u64,
+}
+
+impl Default for Ledger {
+    fn default() -> Self {
+        Self {
+            state: Mutex::new(LedgerState {
+                ttl: DEFAULT_TTL,
+                open: BTreeMap::new(),
+                next_id: 0,
+            }),
+            expired: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Ledger {
+    pub(crate) fn set_ttl(&self, ttl: Duration) {
+        self.state().ttl = ttl;
+    }
+
+    pub(crate) fn expired(&self) -> u64 {
+        self.expired.load(Ordering::Relaxed)
+    }
+
+    /// Charges `cost` to `key` and opens a reservation for it, returning
+    /// `None` if that would exceed `budget`.
+    pub(crate) fn reserve(&self, budget: CountBudget, key: &str, cost: u64) -> Option<Reservation> {
+        self.expire();
+        let window = budget.charge_window(key, cost)?;
+        let slot = Arc::new(Mutex::new(Some(Charge {
+            budget,
+            key: key.to_string(),
+            cost,
+            window,
+        })));
+
+        let mut state = self.state();
+        let deadline = Instant::now() + state.ttl;
+        let id = state.next_id;
+        state.next_id += 1;
+        state.open.insert((deadline, id), Arc::downgrade(&slot));
+        Some(Reservation { slot: Some(slot) })
+    }
+
+    /// Refunds the reservations past their deadline, stopping at the first
+    /// one still open, and forgets settled ones along the way.
+    pub(crate) fn expire(&self) {
+        let now = Instant::now();
+        let mut expired = Vec::new();
+        {
+            let mut state = self.state();
+            while let Some(entry) = state.open.first_entry()
+                && entry.key().0 <= now
+            {
+                if let Some(charge) = entry.remove().upgrade().as_ref().and_then(take) {
+                    expired.push(charge);
+                }
+            }
+        }
+
+        for charge in expired {
+            warn!(
+                "Reservation of {} for {} expired without commit or cancel",
+                charge.cost, charge.key
+            );
+            self.expired.fetch_add(1, Ordering::Relaxed);
+            charge.refund();
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, LedgerState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn budget() -> CountBudget {
+        CountBudget::new(2, Duration::from_secs(60))
+    }
+
+    #[test]
+    fn commit_keeps_the_charge() {
+        let (ledger, budget) = (Ledger::default(), budget());
+        ledger.reserve(budget.clone(), "a", 2).unwrap().commit();
+        assert!(!budget.charge("a", 1));
+    }
+
+    #[test]
+    fn cancel_and_drop_give_the_charge_back() {
+        let (ledger, budget) = (Ledger::default(), budget());
+        let reservation = ledger.reserve(budget.clone(), "a", 2).unwrap();
+        assert!(ledger.reserve(budget.clone(), "a", 1).is_none());
+        reservation.cancel();
+
+        drop(ledger.reserve(budget.clone(), "a", 2).unwrap());
+        assert!(budget.charge("a", 2));
+    }
+
+    #[test]
+    fn expired_reservations_are_refunded() {
+        let (ledger, budget) = (Ledger::default(), budget());
+        ledger.set_ttl(Duration::ZERO);
+        let leaked = ledger.reserve(budget.clone(), "a", 2).unwrap();
+        let late = ledger.reserve(budget.clone(), "b", 2).unwrap();
+
+        ledger.expire();
+        assert_eq!(ledger.expired(), 2);
+        assert!(budget.charge("a", 2));
+        assert!(budget.charge("b", 2));
+        // Settling an expired reservation doesn't give its cost back twice.
+        late.commit();
+        drop(leaked);
+        assert!(!budget.charge("a", 1));
+        assert!(!budget.charge("b", 1));
+    }
+
+    #[test]
+    fn expiry_stops_at_the_first_open_reservation() {
+        let (ledger, budget) = (Ledger::default(), budget());
+        ledger.set_ttl(Duration::ZERO);
+        let leaked = ledger.reserve(budget.clone(), "a", 1).unwrap();
+        ledger.set_ttl(Duration::from_secs(60));
+        let open = ledger.reserve(budget.clone(), "b", 1).unwrap();
+
+        ledger.expire();
+        assert_eq!(ledger.expired(), 1);
+        assert_eq!(ledger.state().open.len(), 1);
+        drop(leaked);
+        open.commit();
+        assert!(!budget.charge("b", 2));
+    }
+
+    #[test]
+    fn settled_reservations_are_forgotten() {
+        let (ledger, budget) = (Ledger::default(), budget());
+        ledger.set_ttl(Duration::ZERO);
+        ledger.reserve(budget.clone(), "a", 1).unwrap().commit();
+        ledger.reserve(budget, "a", 1).unwrap().cancel();
+
+        ledger.expire();
+        assert_eq!(ledger.expired(), 0);
+        assert!(ledger.state().open.is_empty());
+    }
+}
@@ -0,0 +1,187 @@
+/* src/retry.rs */
+
+use crate::{count_budget::CountBudget, expiring::ExpiringMap};
+use axum::http::{HeaderMap, HeaderName, StatusCode};
+use std::{
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+/// How long a `5xx` response makes retries of the client exempt by default.
+const DEFAULT_ERROR_WINDOW: Duration = Duration::from_secs(10);
+
+/// How requests marked as retries by internal clients are charged.
+///
+/// A request counts as a retry when it carries the configured header
+/// (`Retry-Attempt` by default) with a value other than `0`. Exempt retries
+/// pass without being recorded in the rate-limit store, so our own retry
+/// policies don't amplify throttling during partial outages.
+///
+/// By default a retry is only exempt if the same client received a `5xx`
+/// response shortly before, so a client can't opt out of its limit by
+/// sending the header. With [`quota`](Self::quota), exempt retries draw from
+/// a separate per-client quota as well, and retries beyond it are charged
+/// like any other request. A discount that charges retries a fraction of a
+/// request is not offered, as `lazy-limit` counts every request as exactly
+/// one.
+///
+/// The header is trivially spoofable.
+/// [`exempt_all_unchecked`](Self::exempt_all_unchecked) exempts every retry
+/// and so lets any client go unlimited; only use it on layers that serve
+/// internal clients, or bound it with a quota.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    header: HeaderName,
+    after_server_error: Option<Duration>,
+    /// When the exemption earned by each client's last server error ends.
+    server_errors: Arc<Mutex<ExpiringMap<String, Instant>>>,
+    quota: Option<CountBudget>,
+}
+
+impl RetryPolicy {
+    /// Exempts a retry only if the same client received a `5xx` response
+    /// within 10 seconds before it.
+    pub fn exempt() -> Self {
+        Self::exempt_after_server_error(DEFAULT_ERROR_WINDOW)
+    }
+
+    /// Exempts a retry only if the same client received a `5xx` response
+    /// within `window` before it.
+    pub fn exempt_after_server_error(window: Duration) -> Self {
+        Self {
+            after_server_error: Some(window),
+            ..Self::exempt_all_unchecked()
+        }
+    }
+
+    /// Exempts every retry, whether or not anything failed before it.
+    ///
+    /// Any client sending the header passes without being charged, so only
+    /// use this for trusted internal clients or together with a
+    /// [`quota`](Self::quota).
+    pub fn exempt_all_unchecked() -> Self {
+        Self {
+            header: HeaderName::from_static("retry-attempt"),
+            after_server_error: None,
+            server_errors: Arc::default(),
+            quota: None,
+        }
+    }
+
+    /// Sets the header identifying retries. Defaults to `Retry-Attempt`.
+    pub fn header(mut self, header: HeaderName) -> Self {
+        self.header = header;
+        self
+    }
+
+    /// Exempts at most `max_retries` retries per client and `window`, e.g.
+    /// `RetryPolicy::exempt().quota(10, Duration::from_secs(60))`. Further
+    /// retries count against the regular limits.
+    pub fn quota(mut self, max_retries: u64, window: Duration) -> Self {
+        self.quota = Some(CountBudget::new(max_retries, window));
+        self
+    }
+
+    /// Returns whether a request from `key` with `headers` is an exempt retry,
+    /// charging it to the retry quota if there is one.
+    pub(crate) fn is_exempt(&self, headers: &HeaderMap, key: &str) -> bool {
+        let is_retry = headers
+            .get(&self.header)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| !value.trim().is_empty() && value.trim() != "0");
+        if !is_retry {
+            return false;
+        }
+
+        let eligible = match self.after_server_error {
+            None => true,
+            Some(_) => self
+                .server_errors
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .get(key)
+                .is_some_and(|until| *until > Instant::now()),
+        };
+        eligible && self.quota.as_ref().is_none_or(|quota| quota.charge(key, 1))
+    }
+
+    /// Remembers server errors returned to `key`.
+    pub(crate) fn record_response(&self, key: &str, status: StatusCode) {
+        let Some(window) = self.after_server_error else {
+            return;
+        };
+        if !status.is_server_error() {
+            return;
+        }
+
+        let now = Instant::now();
+        self.server_errors
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(key.to_string(), now + window, now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    const KEY: &str = "203.0.113.7";
+
+    fn retry(attempt: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("retry-attempt", HeaderValue::from_static(attempt));
+        headers
+    }
+
+    #[test]
+    fn exempts_only_retries() {
+        let policy = RetryPolicy::exempt_all_unchecked();
+        assert!(policy.is_exempt(&retry("1"), KEY));
+        assert!(!policy.is_exempt(&retry("0"), KEY));
+        assert!(!policy.is_exempt(&retry(" "), KEY));
+        assert!(!policy.is_exempt(&HeaderMap::new(), KEY));
+    }
+
+    #[test]
+    fn custom_header() {
+        let policy = RetryPolicy::exempt_all_unchecked().header(HeaderName::from_static("x-retry"));
+        let mut headers = HeaderMap::new();
+        headers.insert("x-retry", HeaderValue::from_static("2"));
+        assert!(policy.is_exempt(&headers, KEY));
+        assert!(!policy.is_exempt(&retry("1"), KEY));
+    }
+
+    #[test]
+    fn exempts_after_server_error_only() {
+        let policy = RetryPolicy::exempt_after_server_error(Duration::from_secs(60));
+        assert!(!policy.is_exempt(&retry("1"), KEY));
+        policy.record_response(KEY, StatusCode::NOT_FOUND);
+        assert!(!policy.is_exempt(&retry("1"), KEY));
+        policy.record_response(KEY, StatusCode::BAD_GATEWAY);
+        assert!(policy.is_exempt(&retry("1"), KEY));
+        assert!(!policy.is_exempt(&retry("1"), "198.51.100.1"));
+    }
+
+    #[test]
+    fn exempts_nothing_by_default_without_a_server_error() {
+        let policy = RetryPolicy::exempt();
+        assert!(!policy.is_exempt(&retry("1"), KEY));
+        policy.record_response(KEY, StatusCode::SERVICE_UNAVAILABLE);
+        assert!(policy.is_exempt(&retry("1"), KEY));
+        assert!(!policy.is_exempt(&retry("1"), "198.51.100.1"));
+    }
+
+    #[test]
+    fn quota_bounds_exempt_retries() {
+        let policy = RetryPolicy::exempt_all_unchecked().quota(2, Duration::from_secs(60));
+        assert!(policy.is_exempt(&retry("1"), KEY));
+        assert!(policy.is_exempt(&retry("2"), KEY));
+        assert!(!policy.is_exempt(&retry("3"), KEY));
+        // Other clients have their own quota; first attempts don't use it.
+        assert!(policy.is_exempt(&retry("1"), "198.51.100.1"));
+        assert!(!policy.is_exempt(&HeaderMap::new(), "198.51.100.2"));
+        assert!(policy.is_exempt(&retry("1"), "198.51.100.2"));
+    }
+}
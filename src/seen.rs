@@ -0,0 +1,118 @@
+/* src/seen.rs */
+
+use crate::expiring::ExpiringMap;
+use std::{
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::{Duration, Instant, SystemTime},
+};
+
+/// When a key was first and last seen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeySeen {
+    /// The first request of the key.
+    pub first_seen: SystemTime,
+    /// The latest request of the key.
+    pub last_seen: SystemTime,
+}
+
+impl KeySeen {
+    /// Returns how long ago the key was first seen.
+    pub fn age(&self) -> Duration {
+        self.first_seen.elapsed().unwrap_or_default()
+    }
+}
+
+/// Tracks first-seen and last-seen timestamps per client key.
+///
+/// Attach it with `GovernorConfig::seen_tracker` to tell long-standing
+/// clients apart from fresh attack infrastructure. The timestamps show up in
+/// `DecisionRecord`s and `GovernorTags`, and can be looked up with
+/// [`get`](Self::get). A key unseen for longer than `idle` is forgotten and
+/// counts as new again. Clones share the same timestamps.
+#[derive(Debug, Clone)]
+pub struct SeenTracker {
+    idle: Duration,
+    keys: Arc<Mutex<Keys>>,
+}
+
+/// The timestamps per key, dropped once the key is idle.
+type Keys = ExpiringMap<String, (KeySeen, Instant)>;
+
+impl SeenTracker {
+    /// Creates a tracker forgetting keys after `idle` without requests.
+    pub fn new(idle: Duration) -> Self {
+        Self {
+            idle,
+            keys: Arc::default(),
+        }
+    }
+
+    /// Returns the timestamps of `key`, if it was seen within `idle`.
+    pub fn get(&self, key: &str) -> Option<KeySeen> {
+        self.keys()
+            .get(key)
+            .map(|(seen, _)| *seen)
+            .filter(|seen| !self.is_idle(seen, SystemTime::now()))
+    }
+
+    /// Records a request of `key` and returns its updated timestamps.
+    pub(crate) fn touch(&self, key: &str) -> KeySeen {
+        let now = SystemTime::now();
+        let fresh = KeySeen {
+            first_seen: now,
+            last_seen: now,
+        };
+        let idle_at = Instant::now() + self.idle;
+        self.keys().update(
+            key,
+            Instant::now(),
+            || (fresh, idle_at),
+            |(seen, expires)| {
+                if self.is_idle(seen, now) {
+                    *seen = fresh;
+                }
+                seen.last_seen = now;
+                *expires = idle_at;
+                *seen
+            },
+        )
+    }
+
+    fn is_idle(&self, seen: &KeySeen, now: SystemTime) -> bool {
+        now.duration_since(seen.last_seen).unwrap_or_default() > self.idle
+    }
+
+    fn keys(&self) -> MutexGuard<'_, Keys> {
+        self.keys.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tracks_first_and_last_seen() {
+        let tracker = SeenTracker::new(Duration::from_secs(60));
+        assert_eq!(tracker.get("203.0.113.7"), None);
+
+        let first = tracker.touch("203.0.113.7");
+        assert_eq!(first.first_seen, first.last_seen);
+        let second = tracker.clone().touch("203.0.113.7");
+        assert_eq!(second.first_seen, first.first_seen);
+        assert!(second.last_seen >= first.last_seen);
+        assert_eq!(tracker.get("203.0.113.7"), Some(second));
+        assert!(second.age() < Duration::from_secs(60));
+    }
+
+    #[test]
+    fn idle_keys_count_as_new() {
+        let tracker = SeenTracker::new(Duration::ZERO);
+        let first = tracker.touch("203.0.113.7");
+        while tracker.get("203.0.113.7").is_some() {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        let again = tracker.touch("203.0.113.7");
+        assert!(again.first_seen > first.first_seen);
+    }
+}
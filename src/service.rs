@@ -0,0 +1,200 @@
+/* src/service.rs */
+
+use axum::http::{HeaderMap, HeaderName};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::{
+    fmt,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// The default header carrying service tokens.
+const DEFAULT_HEADER: HeaderName = HeaderName::from_static("x-governor-service");
+
+/// How far in the future a token may be issued, to allow for clock skew
+/// between hosts.
+const MAX_CLOCK_SKEW: Duration = Duration::from_secs(60);
+
+/// Recognizes internal services by a signed request header.
+///
+/// A token has the form `<service>.<issued at>.<signature>`, where the
+/// issue time is in Unix seconds and the signature is the hex HMAC-SHA256 of
+/// `<service>.<issued at>` under a shared secret. Tokens older than
+/// [`max_age`](Self::max_age), one hour by default, or issued more than a
+/// minute in the future are rejected, so a leaked token stops working on its
+/// own. Services mint fresh tokens with [`sign`](Self::sign):
+///
+/// ```rust
+/// # use axum_governor::ServiceAuth;
+/// let auth = ServiceAuth::new("shared secret");
+/// let token = auth.sign("billing");
+/// // The billing service sends `x-governor-service: {token}`.
+/// ```
+///
+/// Requests with a valid token are classified as service traffic, so
+/// east-west calls don't share buckets with end users behind the same
+/// router. By default they are not limited at all; with
+/// `unlimited(false)` each service gets its own bucket keyed
+/// `service:<name>` instead of the caller's IP. Invalid tokens are ignored
+/// and the request is limited as usual.
+#[derive(Clone)]
+pub struct ServiceAuth {
+    header: HeaderName,
+    secret: Arc<[u8]>,
+    unlimited: bool,
+    max_age: Duration,
+}
+
+impl fmt::Debug for ServiceAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ServiceAuth")
+            .field("header", &self.header)
+            .field("unlimited", &self.unlimited)
+            .field("max_age", &self.max_age)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ServiceAuth {
+    /// Creates a verifier for tokens signed with `secret`, read from the
+    /// `x-governor-service` header.
+    pub fn new(secret: impl AsRef<[u8]>) -> Self {
+        Self {
+            header: DEFAULT_HEADER,
+            secret: secret.as_ref().into(),
+            unlimited: true,
+            max_age: Duration::from_secs(3600),
+        }
+    }
+
+    /// Sets the header carrying the token.
+    pub fn header(mut self, header: HeaderName) -> Self {
+        self.header = header;
+        self
+    }
+
+    /// Sets whether services are exempt from limits (the default) or limited
+    /// in a bucket of their own.
+    pub fn unlimited(mut self, unlimited: bool) -> Self {
+        self.unlimited = unlimited;
+        self
+    }
+
+    /// Sets how long tokens stay valid after they were issued.
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = max_age;
+        self
+    }
+
+    /// Returns a token for `service`, issued now.
+    pub fn sign(&self, service: &str) -> String {
+        self.sign_at(service, SystemTime::now())
+    }
+
+    /// Returns a token for `service`, issued at `issued_at`.
+    pub(crate) fn sign_at(&self, service: &str, issued_at: SystemTime) -> String {
+        let payload = format!("{service}.{}", unix_secs(issued_at));
+        let signature: String = self
+            .mac(&payload)
+            .finalize()
+            .into_bytes()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect();
+        format!("{payload}.{signature}")
+    }
+
+    pub(crate) fn is_unlimited(&self) -> bool {
+        self.unlimited
+    }
+
+    /// Returns the service name of a valid, unexpired token in `headers`.
+    pub(crate) fn verify(&self, headers: &HeaderMap) -> Option<String> {
+        let token = headers.get(&self.header)?.to_str().ok()?;
+        let (payload, signature) = token.rsplit_once('.')?;
+        let signature = decode_hex(signature)?;
+        self.mac(payload).verify_slice(&signature).ok()?;
+
+        let (service, issued_at) = payload.rsplit_once('.')?;
+        let issued_at: u64 = issued_at.parse().ok()?;
+        let now = unix_secs(SystemTime::now());
+        let fresh = issued_at <= now + MAX_CLOCK_SKEW.as_secs()
+            && now.saturating_sub(issued_at) <= self.max_age.as_secs();
+        fresh.then(|| service.to_string())
+    }
+
+    fn mac(&self, payload: &str) -> Hmac<Sha256> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.secret).expect("HMAC accepts keys of any length");
+        mac.update(payload.as_bytes());
+        mac
+    }
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn headers(token: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(DEFAULT_HEADER, HeaderValue::from_str(token).unwrap());
+        headers
+    }
+
+    #[test]
+    fn accepts_fresh_tokens() {
+        let auth = ServiceAuth::new("secret");
+        let token = auth.sign("billing.eu");
+        assert_eq!(auth.verify(&headers(&token)).as_deref(), Some("billing.eu"));
+    }
+
+    #[test]
+    fn rejects_forged_tokens() {
+        let auth = ServiceAuth::new("secret");
+        let token = ServiceAuth::new("other").sign("billing");
+        assert_eq!(auth.verify(&headers(&token)), None);
+
+        // Changing the service or the issue time breaks the signature.
+        let token = auth.sign("billing");
+        let (payload, signature) = token.rsplit_once('.').unwrap();
+        let (_, issued_at) = payload.split_once('.').unwrap();
+        let renamed = format!("search.{issued_at}.{signature}");
+        assert_eq!(auth.verify(&headers(&renamed)), None);
+        let extended = format!("billing.{}.{signature}", u64::MAX);
+        assert_eq!(auth.verify(&headers(&extended)), None);
+        assert_eq!(auth.verify(&headers("billing.deadbeef")), None);
+    }
+
+    #[test]
+    fn rejects_tokens_outside_the_window() {
+        let auth = ServiceAuth::new("secret").max_age(Duration::from_secs(600));
+        let now = SystemTime::now();
+
+        let recent = auth.sign_at("billing", now - Duration::from_secs(300));
+        assert!(auth.verify(&headers(&recent)).is_some());
+        let expired = auth.sign_at("billing", now - Duration::from_secs(900));
+        assert_eq!(auth.verify(&headers(&expired)), None);
+        let skewed = auth.sign_at("billing", now + Duration::from_secs(30));
+        assert!(auth.verify(&headers(&skewed)).is_some());
+        let future = auth.sign_at("billing", now + Duration::from_secs(900));
+        assert_eq!(auth.verify(&headers(&future)), None);
+    }
+}
@@ -0,0 +1,201 @@
+/* src/shed.rs */
+
+use crate::Criticality;
+use axum::body::{Body, Bytes};
+use http_body::{Frame, SizeHint};
+use std::{
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+
+/// Sheds load once too many requests are in flight.
+///
+/// Unlike per-client limits, this protects the server as a whole: once
+/// `max_in_flight` requests are being handled by the layers sharing this
+/// value, further requests are answered with `503 Service Unavailable` and a
+/// `Retry-After` header, so clients and load balancers treat them as server
+/// overload rather than client misbehavior. Per-client violations keep their
+/// `429`. Shed requests are not charged to any limit.
+///
+/// Routes with a lower [`Criticality`] are shed earlier: a `Sheddable`
+/// request is only admitted while fewer than half of `max_in_flight` are in
+/// flight, leaving the rest for more important routes.
+///
+/// A request counts as in flight until its response body has been sent in
+/// full, failed, or was dropped because the client went away, so long-running
+/// streaming responses keep their slot. Applies to `GovernorLayer` only, not
+/// to the `RateLimited` extractor. Clones share the same counter.
+#[derive(Debug, Clone)]
+pub struct LoadShed {
+    max_in_flight: usize,
+    retry_after: Duration,
+    in_flight: Arc<AtomicUsize>,
+}
+
+impl LoadShed {
+    /// Creates a limit of `max_in_flight` concurrent requests, advising
+    /// clients to retry after one second.
+    pub fn new(max_in_flight: usize) -> Self {
+        Self {
+            max_in_flight,
+            retry_after: Duration::from_secs(1),
+            in_flight: Arc::default(),
+        }
+    }
+
+    /// Sets the delay sent in `Retry-After`, rounded up to whole seconds.
+    pub fn retry_after(mut self, retry_after: Duration) -> Self {
+        self.retry_after = retry_after;
+        self
+    }
+
+    /// Returns the number of requests currently in flight.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    /// Returns the `Retry-After` value in seconds.
+    pub(crate) fn retry_after_secs(&self) -> u64 {
+        crate::format::whole_seconds(self.retry_after)
+    }
+
+    /// Admits a request of `criticality`, returning `None` if the server is
+    /// at capacity for that tier.
+    pub(crate) fn acquire(&self, criticality: Criticality) -> Option<InFlight> {
+        let max_in_flight = (self.max_in_flight as f64 * criticality.share()).ceil() as usize;
+        self.in_flight
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |in_flight| {
+                (in_flight < max_in_flight).then_some(in_flight + 1)
+            })
+            .ok()
+            .map(|_| InFlight {
+                in_flight: self.in_flight.clone(),
+            })
+    }
+}
+
+/// Marks a request as in flight until dropped.
+pub(crate) struct InFlight {
+    in_flight: Arc<AtomicUsize>,
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.in_flight.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// A response body keeping its request in flight until the body ends or is
+/// dropped.
+pub(crate) struct InFlightBody {
+    inner: Body,
+    in_flight: Option<InFlight>,
+}
+
+impl InFlightBody {
+    pub(crate) fn new(inner: Body, in_flight: InFlight) -> Self {
+        Self {
+            inner,
+            in_flight: Some(in_flight),
+        }
+    }
+}
+
+impl http_body::Body for InFlightBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let frame = Pin::new(&mut self.inner).poll_frame(cx);
+        if matches!(frame, Poll::Ready(None | Some(Err(_)))) {
+            // Release the slot right away instead of when the connection
+            // gets around to dropping the body.
+            self.in_flight = None;
+        }
+        frame
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body::Body as _;
+
+    #[test]
+    fn sheds_at_capacity() {
+        let shed = LoadShed::new(2);
+        let first = shed.acquire(Criticality::Normal).unwrap();
+        let _second = shed.clone().acquire(Criticality::Normal).unwrap();
+        assert_eq!(shed.in_flight(), 2);
+        assert!(shed.acquire(Criticality::Normal).is_none());
+
+        drop(first);
+        assert_eq!(shed.in_flight(), 1);
+        assert!(shed.acquire(Criticality::Normal).is_some());
+    }
+
+    #[test]
+    fn rounds_retry_after_up() {
+        assert_eq!(LoadShed::new(1).retry_after_secs(), 1);
+        let shed = LoadShed::new(1).retry_after(Duration::from_millis(2500));
+        assert_eq!(shed.retry_after_secs(), 3);
+    }
+
+    #[test]
+    fn sheds_lower_tiers_first() {
+        let shed = LoadShed::new(4);
+        let _held: Vec<_> = (0..2)
+            .map(|_| shed.acquire(Criticality::Sheddable).unwrap())
+            .collect();
+        assert!(shed.acquire(Criticality::Sheddable).is_none());
+
+        let _low = shed.acquire(Criticality::Low).unwrap();
+        assert!(shed.acquire(Criticality::Low).is_none());
+
+        let _critical = shed.acquire(Criticality::Critical).unwrap();
+        assert!(shed.acquire(Criticality::Critical).is_none());
+        assert!(shed.acquire(Criticality::Normal).is_none());
+    }
+
+    #[tokio::test]
+    async fn bodies_hold_their_slot_until_done() {
+        let shed = LoadShed::new(2);
+        let body = InFlightBody::new(
+            Body::from("done"),
+            shed.acquire(Criticality::Normal).unwrap(),
+        );
+        assert_eq!(shed.in_flight(), 1);
+        // The slot is released once the body ended, before it is dropped.
+        let mut body = std::pin::pin!(body);
+        while std::future::poll_fn(|cx| body.as_mut().poll_frame(cx))
+            .await
+            .is_some()
+        {}
+        assert_eq!(shed.in_flight(), 0);
+
+        // A body dropped early, e.g. by a client going away, releases it too.
+        let body = InFlightBody::new(
+            Body::from("aborted"),
+            shed.acquire(Criticality::Normal).unwrap(),
+        );
+        assert_eq!(shed.in_flight(), 1);
+        drop(body);
+        assert_eq!(shed.in_flight(), 0);
+    }
+}
@@ -0,0 +1,205 @@
+/* src/slo.rs */
+
+use crate::Criticality;
+use std::{
+    collections::HashSet,
+    fmt,
+    sync::{Arc, Mutex, PoisonError},
+};
+
+/// Supplies the remaining error budget of an SLO.
+///
+/// Returns the fraction of the budget left, from `1.0` (untouched) to `0.0`
+/// (burnt). Called on every limited request, so read a value your monitoring
+/// poller keeps up to date, e.g. from an atomic, instead of querying it.
+///
+/// Any `Fn() -> f64` closure is also an `ErrorBudgetSignal`.
+pub trait ErrorBudgetSignal: Send + Sync + 'static {
+    /// Returns the remaining fraction of the error budget.
+    fn remaining(&self) -> f64;
+}
+
+impl<F> ErrorBudgetSignal for F
+where
+    F: Fn() -> f64 + Send + Sync + 'static,
+{
+    fn remaining(&self) -> f64 {
+        self()
+    }
+}
+
+/// Throttles non-critical routes as an SLO error budget burns.
+///
+/// While more than `threshold` of the budget is left, nothing changes. Below
+/// it, only a share of the requests to non-critical routes gets through,
+/// shrinking linearly with the remaining budget down to `floor` once the
+/// budget is burnt, and recovering the same way. Critical routes are never
+/// throttled, which automates policies like "protect checkout by throttling
+/// search":
+///
+/// ```rust
+/// # use axum_governor::{GovernorConfig, SloGuard};
+/// # use std::sync::{Arc, atomic::{AtomicU64, Ordering}};
+/// // Updated by a task polling the monitoring system, as `f64::to_bits`.
+/// let budget = Arc::new(AtomicU64::new(1.0f64.to_bits()));
+/// let signal = budget.clone();
+/// let guard = SloGuard::new(move || f64::from_bits(signal.load(Ordering::Relaxed)))
+///     .critical("/checkout")
+///     .critical("/api/payments");
+/// let config = GovernorConfig::new().slo_guard(guard);
+/// ```
+///
+/// Routes with a lower [`Criticality`] see the budget scaled by their share
+/// and are throttled earlier; `Criticality::Critical` routes are exempt like
+/// paths marked with [`critical`](Self::critical).
+///
+/// Throttled requests are denied like rate-limited ones. Requests are
+/// admitted evenly rather than at random. Clones share the same admission
+/// state.
+#[derive(Clone)]
+pub struct SloGuard {
+    signal: Arc<dyn ErrorBudgetSignal>,
+    critical: HashSet<String>,
+    threshold: f64,
+    floor: f64,
+    credit: Arc<Mutex<f64>>,
+}
+
+impl fmt::Debug for SloGuard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SloGuard")
+            .field("critical", &self.critical)
+            .field("threshold", &self.threshold)
+            .field("floor", &self.floor)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SloGuard {
+    /// Creates a guard reading the budget from `signal`, throttling below
+    /// half of the budget down to 10% of the traffic.
+    pub fn new(signal: impl ErrorBudgetSignal) -> Self {
+        Self {
+            signal: Arc::new(signal),
+            critical: HashSet::new(),
+            threshold: 0.5,
+            floor: 0.1,
+            credit: Arc::default(),
+        }
+    }
+
+    /// Marks the rule path `path` as critical, exempting it from throttling.
+    ///
+    /// Each clone owns its critical paths, so marking a path on a clone
+    /// leaves the others unchanged.
+    pub fn critical(mut self, path: impl Into<String>) -> Self {
+        self.critical.insert(path.into());
+        self
+    }
+
+    /// Sets the remaining budget below which throttling starts.
+    pub fn threshold(mut self, threshold: f64) -> Self {
+        self.threshold = threshold.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Sets the share of non-critical traffic still admitted once the budget
+    /// is burnt.
+    pub fn floor(mut self, floor: f64) -> Self {
+        self.floor = floor.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Returns whether a request to the rule path `path` may proceed.
+    pub(crate) fn admit(&self, path: &str, criticality: Criticality) -> bool {
+        if criticality == Criticality::Critical || self.critical.contains(path) {
+            return true;
+        }
+        let remaining = self.signal.remaining() * criticality.share();
+        if remaining.is_nan() || remaining >= self.threshold {
+            return true;
+        }
+
+        let share = (remaining.max(0.0) / self.threshold).max(self.floor);
+        // Error diffusion: every request earns `share` credit, and one full
+        // credit admits a request, spreading admissions evenly.
+        let mut credit = self.credit.lock().unwrap_or_else(PoisonError::into_inner);
+        *credit += share;
+        if *credit >= 1.0 {
+            *credit -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    fn admitted(guard: &SloGuard, path: &str, criticality: Criticality, requests: usize) -> usize {
+        (0..requests)
+            .filter(|_| guard.admit(path, criticality))
+            .count()
+    }
+
+    #[test]
+    fn admits_everything_above_threshold() {
+        let guard = SloGuard::new(|| 0.6);
+        assert_eq!(admitted(&guard, "/search", Criticality::Normal, 100), 100);
+    }
+
+    #[test]
+    fn throttles_linearly_below_threshold() {
+        // 0.25 of a 0.5 threshold admits half of the requests.
+        let guard = SloGuard::new(|| 0.25);
+        assert_eq!(admitted(&guard, "/search", Criticality::Normal, 100), 50);
+    }
+
+    #[test]
+    fn burnt_budget_admits_floor() {
+        let guard = SloGuard::new(|| 0.0).floor(0.1);
+        // Float rounding may leave the last credit just short of one.
+        let count = admitted(&guard, "/search", Criticality::Normal, 100);
+        assert!((9..=10).contains(&count), "{count}");
+        let guard = SloGuard::new(|| 0.0).floor(0.0);
+        assert_eq!(admitted(&guard, "/search", Criticality::Normal, 100), 0);
+    }
+
+    #[test]
+    fn critical_routes_are_exempt() {
+        let guard = SloGuard::new(|| 0.0).floor(0.0).critical("/checkout");
+        assert_eq!(admitted(&guard, "/checkout", Criticality::Normal, 10), 10);
+        assert_eq!(admitted(&guard, "/search", Criticality::Critical, 10), 10);
+    }
+
+    #[test]
+    fn lower_tiers_throttle_earlier() {
+        // 0.6 is above the threshold, but a sheddable route sees 0.3.
+        let guard = SloGuard::new(|| 0.6);
+        assert_eq!(admitted(&guard, "/search", Criticality::Normal, 100), 100);
+        assert!(admitted(&guard, "/search", Criticality::Sheddable, 100) < 100);
+    }
+
+    #[test]
+    fn follows_the_signal() {
+        let budget = Arc::new(AtomicU64::new(1.0f64.to_bits()));
+        let signal = budget.clone();
+        let guard =
+            SloGuard::new(move || f64::from_bits(signal.load(Ordering::Relaxed))).floor(0.0);
+        assert_eq!(admitted(&guard, "/search", Criticality::Normal, 10), 10);
+        budget.store(0.0f64.to_bits(), Ordering::Relaxed);
+        assert_eq!(admitted(&guard, "/search", Criticality::Normal, 10), 0);
+    }
+
+    #[test]
+    fn paths_can_be_marked_after_cloning() {
+        let guard = SloGuard::new(|| 0.0).floor(0.0);
+        let shared = guard.clone();
+        let guard = guard.critical("/checkout");
+        assert!(guard.admit("/checkout", Criticality::Normal));
+        assert!(!shared.admit("/checkout", Criticality::Normal));
+    }
+}
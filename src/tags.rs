@@ -0,0 +1,68 @@
+/* src/tags.rs */
+
+use crate::KeySeen;
+
+/// How the governor classified an admitted request.
+///
+/// Inserted as a request extension before the inner service is called, so
+/// downstream logging or analytics can segment traffic without recomputing
+/// the classification:
+///
+/// ```rust
+/// # use axum::Extension;
+/// # use axum_governor::GovernorTags;
+/// async fn handler(Extension(tags): Extension<GovernorTags>) -> String {
+///     format!("{:?} client, tier {:?}", tags.client_class, tags.tier)
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GovernorTags {
+    /// The tier of the layer, see [`GovernorConfig::tier`](crate::GovernorConfig::tier).
+    pub tier: Option<String>,
+    /// The tenant of the request, if a [`TenantResolver`](crate::TenantResolver)
+    /// is set and resolved one.
+    pub tenant: Option<String>,
+    /// Which kind of client sent the request.
+    pub client_class: ClientClass,
+    /// The name of the [`KeyChain`](crate::KeyChain) strategy the request was
+    /// keyed by, if a chain is set and one matched.
+    pub key_source: Option<String>,
+    /// Which `lazy-limit` rules the request was checked against.
+    pub rules: AppliedRules,
+    /// `true` if the request exceeded its limit and was only let through
+    /// because of shadow mode.
+    pub shadow_hit: bool,
+    /// When the client was first and last seen, if a `SeenTracker` is
+    /// attached.
+    pub seen: Option<KeySeen>,
+}
+
+/// The kind of client a request came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientClass {
+    /// A public address.
+    Public,
+    /// A loopback, link-local or private address.
+    Private,
+    /// An address on the `Governor` allowlist.
+    Allowlisted,
+    /// A request marked trusted by its [`GovernorFlags`](crate::GovernorFlags).
+    Trusted,
+    /// An internal service with a valid [`ServiceAuth`](crate::ServiceAuth)
+    /// token.
+    Service,
+}
+
+/// The `lazy-limit` rules a request was checked against.
+///
+/// `lazy-limit` does not report which rule matched, only which set of rules
+/// was consulted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AppliedRules {
+    /// The global and route-specific rules (`limit!`).
+    GlobalAndRoute,
+    /// Only the route-specific rules (`limit_override!`).
+    RouteOnly,
+    /// No rules, e.g. for exempt clients or first-seen keys.
+    Skipped,
+}
@@ -0,0 +1,175 @@
+/* src/tarpit.rs */
+
+use crate::{
+    codec::fnv1a,
+    expiring::{Expiring, ExpiringMap},
+};
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, MutexGuard, PoisonError,
+    },
+    time::{Duration, Instant},
+};
+
+/// The artificial latency added by a [`Tarpit`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TarpitDelay {
+    /// Always the same delay.
+    Fixed(Duration),
+    /// A delay drawn uniformly between `min` and `max`, so clients can't
+    /// tell the tarpit from a slow backend by its constant timing.
+    Uniform {
+        /// The shortest delay.
+        min: Duration,
+        /// The longest delay.
+        max: Duration,
+    },
+}
+
+/// Slows down clients that keep hitting their limit.
+///
+/// A key denied at least `denials` times within `window` counts as abusive
+/// for the rest of that window. Its requests, allowed or denied, are delayed
+/// by the configured [`TarpitDelay`] before being answered. This makes
+/// scraping expensive without handing the client a clean error signal to
+/// adapt to, and is meant for keys that are not (yet) worth a ban. Clones
+/// share the same state.
+#[derive(Debug, Clone)]
+pub struct Tarpit {
+    delay: TarpitDelay,
+    denials: u32,
+    window: Duration,
+    strikes: Arc<Mutex<ExpiringMap<String, Strikes>>>,
+    seed: Arc<AtomicU64>,
+}
+
+#[derive(Debug)]
+struct Strikes {
+    count: u32,
+    resets_at: Instant,
+}
+
+impl Expiring for Strikes {
+    fn expires_at(&self) -> Instant {
+        self.resets_at
+    }
+}
+
+impl Tarpit {
+    /// Creates a tarpit adding `delay` for keys denied 10 times within a
+    /// minute.
+    pub fn new(delay: TarpitDelay) -> Self {
+        Self {
+            delay,
+            denials: 10,
+            window: Duration::from_secs(60),
+            strikes: Arc::default(),
+            seed: Arc::default(),
+        }
+    }
+
+    /// Sets how many denials within `window` make a key abusive.
+    pub fn threshold(mut self, denials: u32, window: Duration) -> Self {
+        self.denials = denials;
+        self.window = window;
+        self
+    }
+
+    /// Records a denial of `key`.
+    pub(crate) fn strike(&self, key: &str) {
+        let now = Instant::now();
+        let fresh = || Strikes {
+            count: 0,
+            resets_at: now + self.window,
+        };
+        self.strikes().update(key, now, fresh, |strikes| {
+            if strikes.resets_at <= now {
+                *strikes = fresh();
+            }
+            strikes.count = strikes.count.saturating_add(1);
+        });
+    }
+
+    /// Returns the delay for a request of `key`, if the key is abusive.
+    pub(crate) fn delay_for(&self, key: &str) -> Option<Duration> {
+        let abusive = self.strikes().get(key).is_some_and(|strikes| {
+            strikes.count >= self.denials && strikes.resets_at > Instant::now()
+        });
+        if !abusive {
+            return None;
+        }
+
+        Some(match self.delay {
+            TarpitDelay::Fixed(delay) => delay,
+            TarpitDelay::Uniform { min, max } => {
+                let spread = max.saturating_sub(min).as_nanos() as u64;
+                let seed = self.seed.fetch_add(1, Ordering::Relaxed);
+                let random = fnv1a(&format!("{key}@{seed}@{:?}", Instant::now()));
+                min + Duration::from_nanos(random.checked_rem(spread + 1).unwrap_or(0))
+            }
+        })
+    }
+
+    fn strikes(&self) -> MutexGuard<'_, ExpiringMap<String, Strikes>> {
+        self.strikes.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{check::check, test_util, Governor};
+
+    #[test]
+    fn delays_keys_past_the_threshold() {
+        let delay = Duration::from_millis(250);
+        let tarpit = Tarpit::new(TarpitDelay::Fixed(delay)).threshold(2, Duration::from_secs(60));
+        tarpit.strike("a");
+        assert_eq!(tarpit.delay_for("a"), None);
+        tarpit.strike("a");
+        assert_eq!(tarpit.delay_for("a"), Some(delay));
+        assert_eq!(tarpit.delay_for("b"), None);
+    }
+
+    #[test]
+    fn strikes_expire_with_the_window() {
+        let tarpit = Tarpit::new(TarpitDelay::Fixed(Duration::from_secs(1)))
+            .threshold(1, Duration::from_millis(20));
+        tarpit.strike("a");
+        assert!(tarpit.delay_for("a").is_some());
+        std::thread::sleep(Duration::from_millis(30));
+        assert_eq!(tarpit.delay_for("a"), None);
+        // A new strike starts a fresh window.
+        tarpit.strike("a");
+        assert!(tarpit.delay_for("a").is_some());
+    }
+
+    #[test]
+    fn uniform_delays_stay_in_range() {
+        let (min, max) = (Duration::from_millis(100), Duration::from_millis(200));
+        let tarpit =
+            Tarpit::new(TarpitDelay::Uniform { min, max }).threshold(1, Duration::from_secs(60));
+        tarpit.strike("a");
+        let delays: Vec<_> = (0..50).map(|_| tarpit.delay_for("a").unwrap()).collect();
+        assert!(delays.iter().all(|delay| (min..=max).contains(delay)));
+        assert!(delays.iter().any(|delay| *delay != delays[0]));
+    }
+
+    #[tokio::test]
+    async fn slows_down_denied_clients() {
+        test_util::init_limiter().await;
+        let delay = Duration::from_millis(50);
+        let governor = Governor::new().budget("once", 1, Duration::from_secs(60));
+        let config = test_util::config()
+            .governor(governor)
+            .budget("/scrape", "once")
+            .tarpit(Tarpit::new(TarpitDelay::Fixed(delay)).threshold(1, Duration::from_secs(60)));
+
+        let start = Instant::now();
+        assert!(check(&config, &test_util::parts("/scrape")).await.is_ok());
+        // The first denial strikes and already trips the threshold.
+        assert!(check(&config, &test_util::parts("/scrape")).await.is_err());
+        assert!(start.elapsed() >= delay);
+    }
+}
@@ -0,0 +1,195 @@
+/* src/tenant.rs */
+
+use crate::{count_budget::CountBudget, reservation::Charge};
+use axum::http::{header::HOST, request::Parts};
+use std::time::Duration;
+
+/// Resolves the tenant a request belongs to.
+///
+/// Set with `GovernorConfig::tenants` when one process hosts routers of many
+/// tenants. Every request of a tenant is isolated under its namespace: the
+/// client key becomes `<tenant>/<key>`, so limits, bans, budgets, usage and
+/// decision logs never mix across tenants, and route stats in abuse reports
+/// are kept per `<tenant>:<path>`. With the `engine` feature, a tenant can
+/// get rules of its own through `GovernorConfig::tenant_engine`. Requests
+/// without a tenant are handled as before.
+///
+/// Any `Fn(&Parts) -> Option<String>` closure is also a `TenantResolver`:
+///
+/// ```rust
+/// # use axum::http::request::Parts;
+/// # use axum_governor::GovernorConfig;
+/// let config = GovernorConfig::new().tenants(|parts: &Parts| {
+///     let host = parts.headers.get("host")?.to_str().ok()?;
+///     host.split_once('.').map(|(tenant, _)| tenant.to_string())
+/// });
+/// ```
+pub trait TenantResolver: Send + Sync + 'static {
+    /// Returns the tenant of the request described by `parts`, if any.
+    fn resolve(&self, parts: &Parts) -> Option<String>;
+}
+
+impl<F> TenantResolver for F
+where
+    F: Fn(&Parts) -> Option<String> + Send + Sync + 'static,
+{
+    fn resolve(&self, parts: &Parts) -> Option<String> {
+        self(parts)
+    }
+}
+
+/// Treats each configured virtual host as a tenant.
+///
+/// Resolves the tenant from the `Host` header, or the URI authority for
+/// HTTP/2, so one app serving several domains enforces independent quotas
+/// per domain. Set it with `GovernorConfig::per_host`; rules for single hosts
+/// go through `GovernorConfig::tenant_engine`.
+///
+/// The `Host` header is chosen by the client, so only the listed hosts become
+/// tenants: otherwise a client could send a new `Host` with every request and
+/// get fresh buckets each time. Hosts are compared lowercased, without port
+/// and without a trailing dot. An entry `*.example.com` matches every
+/// subdomain of `example.com`, all of which share the tenant
+/// `*.example.com`. Requests for any other host get no tenant and share the
+/// buckets of the layer.
+#[derive(Debug, Clone)]
+pub struct HostTenant {
+    exact: Vec<String>,
+    wildcards: Vec<String>,
+}
+
+impl HostTenant {
+    /// Creates a resolver for `hosts`, e.g.
+    /// `HostTenant::new(["api.example.com", "*.example.org"])`.
+    pub fn new<I>(hosts: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        let mut exact = Vec::new();
+        let mut wildcards = Vec::new();
+        for host in hosts {
+            let host = normalize_host(host.as_ref());
+            match host.strip_prefix("*.") {
+                Some(domain) => wildcards.push(domain.to_string()),
+                None => exact.push(host),
+            }
+        }
+        Self { exact, wildcards }
+    }
+}
+
+impl TenantResolver for HostTenant {
+    fn resolve(&self, parts: &Parts) -> Option<String> {
+        let host = match parts.headers.get(HOST) {
+            Some(host) => host.to_str().ok()?,
+            None => parts.uri.host()?,
+        };
+        let host = match host.rsplit_once(':') {
+            // Keep bracketed IPv6 literals like `[::1]` intact.
+            Some((name, port)) if !port.contains(']') => name,
+            _ => host,
+        };
+        let host = normalize_host(host);
+        if self.exact.contains(&host) {
+            return Some(host);
+        }
+        self.wildcards
+            .iter()
+            .find(|domain| {
+                host.strip_suffix(domain.as_str())
+                    .is_some_and(|sub| sub.len() > 1 && sub.ends_with('.'))
+            })
+            .map(|domain| format!("*.{domain}"))
+    }
+}
+
+/// Lowercases `host` and strips a trailing dot, so `Example.COM.` and
+/// `example.com` are the same host.
+fn normalize_host(host: &str) -> String {
+    host.strip_suffix('.').unwrap_or(host).to_ascii_lowercase()
+}
+
+/// An aggregate cap on all requests of a tenant.
+///
+/// Counts every request of a tenant, whichever of its users sent it, so one
+/// customer's user base can't consume the capacity of the whole deployment.
+/// Checked after the per-user limits; once a tenant has sent `max_requests`
+/// within the fixed `window`, its requests are answered with
+/// `429 Too Many Requests` and a "tenant quota exhausted" message, marked as
+/// [`Rejection::TenantQuota`](crate::Rejection::TenantQuota). Clones share
+/// the same counters.
+#[derive(Debug, Clone)]
+pub struct TenantCap {
+    requests: CountBudget,
+}
+
+impl TenantCap {
+    /// Creates a cap of `max_requests` per tenant and `window`.
+    pub fn new(max_requests: u64, window: Duration) -> Self {
+        Self {
+            requests: CountBudget::new(max_requests, window),
+        }
+    }
+
+    /// Counts a request of `tenant` and appends the charge to `charges`,
+    /// returning `false` if the cap is reached.
+    pub(crate) fn charge(&self, tenant: &str, charges: &mut Vec<Charge>) -> bool {
+        Charge::record(&self.requests, tenant, 1, charges)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::Request;
+
+    fn parts(host: &str) -> Parts {
+        Request::get("/")
+            .header(HOST, host)
+            .body(())
+            .unwrap()
+            .into_parts()
+            .0
+    }
+
+    #[test]
+    fn host_tenant_resolves_listed_hosts() {
+        let hosts = HostTenant::new(["api.example.com", "*.example.org"]);
+        assert_eq!(
+            hosts.resolve(&parts("API.Example.com:8443")),
+            Some("api.example.com".to_string())
+        );
+        assert_eq!(
+            hosts.resolve(&parts("eu.shop.example.org")),
+            Some("*.example.org".to_string())
+        );
+    }
+
+    #[test]
+    fn host_tenant_strips_trailing_dot() {
+        let hosts = HostTenant::new(["api.example.com", "*.example.org"]);
+        assert_eq!(
+            hosts.resolve(&parts("api.example.com.")),
+            Some("api.example.com".to_string())
+        );
+        assert_eq!(
+            hosts.resolve(&parts("eu.example.org.:443")),
+            Some("*.example.org".to_string())
+        );
+    }
+
+    #[test]
+    fn host_tenant_ignores_unknown_hosts() {
+        let hosts = HostTenant::new(["api.example.com", "*.example.org"]);
+        for host in [
+            "random-1.attacker.test",
+            "example.org",
+            "evilexample.org",
+            "api.example.com.evil",
+            "[::1]:8080",
+        ] {
+            assert_eq!(hosts.resolve(&parts(host)), None, "{host}");
+        }
+    }
+}
@@ -0,0 +1,47 @@
+/* src/test_util.rs */
+
+use crate::{GovernorConfig, MissingIpPolicy};
+use axum::{
+    extract::ConnectInfo,
+    http::{request::Parts, Request},
+};
+use lazy_limit::{init_rate_limiter, Duration, RuleConfig};
+use std::net::SocketAddr;
+use tokio::sync::OnceCell;
+
+/// The client address used by test requests.
+pub(crate) const CLIENT: &str = "203.0.113.7";
+
+/// Initializes `lazy-limit` once per test binary, with a default rule high
+/// enough to never deny, so tests only see the limits they configure.
+pub(crate) async fn init_limiter() {
+    static LIMITER: OnceCell<()> = OnceCell::const_new();
+    LIMITER
+        .get_or_init(|| async {
+            init_rate_limiter!(
+                default: RuleConfig::new(Duration::seconds(1), 1_000_000),
+                routes: []
+            )
+            .await;
+        })
+        .await;
+}
+
+/// A config keying requests by the peer address, as tests have no
+/// `RealIpLayer`.
+pub(crate) fn config() -> GovernorConfig {
+    GovernorConfig::new().on_missing_ip(MissingIpPolicy::UseConnectInfo)
+}
+
+/// The head of a `GET path` request from [`CLIENT`].
+pub(crate) fn parts(path: &str) -> Parts {
+    parts_from(path, CLIENT)
+}
+
+/// The head of a `GET path` request from `client`.
+pub(crate) fn parts_from(path: &str, client: &str) -> Parts {
+    let mut request = Request::get(path).body(()).unwrap();
+    let addr = SocketAddr::new(client.parse().unwrap(), 40000);
+    request.extensions_mut().insert(ConnectInfo(addr));
+    request.into_parts().0
+}
@@ -0,0 +1,247 @@
+/* src/usage.rs */
+
+use crate::Decision;
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::task::JoinHandle;
+use tracing::warn;
+
+/// The file format of a usage export.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UsageFormat {
+    /// A header line followed by one `key,allowed,rejected` line per key.
+    Csv,
+    /// An object with the period and a `usage` array of per-key objects.
+    Json,
+}
+
+/// Aggregates per-key usage for export to billing or BI pipelines.
+///
+/// Attach it with `GovernorConfig::usage_log`. Every [`export`](Self::export)
+/// writes the counts since the previous export and resets them, so each
+/// export covers exactly one period. [`spawn`](Self::spawn) does so on a
+/// schedule:
+///
+/// ```rust,no_run
+/// # use axum_governor::{UsageFormat, UsageLog};
+/// # use std::time::{Duration, SystemTime, UNIX_EPOCH};
+/// # async {
+/// let usage = UsageLog::new();
+/// usage.spawn(Duration::from_secs(3600), UsageFormat::Csv, || {
+///     let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+///     std::fs::File::create(format!("/var/lib/app/usage-{now}.csv"))
+/// });
+/// # };
+/// ```
+///
+/// Memory grows with the number of distinct keys seen in a period. Clones
+/// share the same counts.
+#[derive(Debug, Clone)]
+pub struct UsageLog {
+    period: Arc<Mutex<Period>>,
+}
+
+#[derive(Debug)]
+struct Period {
+    since: SystemTime,
+    keys: HashMap<String, Usage>,
+}
+
+#[derive(Debug, Default)]
+struct Usage {
+    allowed: u64,
+    rejected: u64,
+}
+
+impl Default for UsageLog {
+    fn default() -> Self {
+        Self {
+            period: Arc::new(Mutex::new(Period {
+                since: SystemTime::now(),
+                keys: HashMap::new(),
+            })),
+        }
+    }
+}
+
+impl UsageLog {
+    /// Creates an empty usage log.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Writes the usage since the previous export to `writer` and resets it.
+    ///
+    /// The counts are reset even if writing fails.
+    pub fn export<W: Write>(&self, format: UsageFormat, mut writer: W) -> io::Result<()> {
+        let now = SystemTime::now();
+        let period = std::mem::replace(
+            &mut *self.period(),
+            Period {
+                since: now,
+                keys: HashMap::new(),
+            },
+        );
+
+        let mut keys: Vec<_> = period.keys.into_iter().collect();
+        keys.sort_by(|a, b| a.0.cmp(&b.0));
+
+        match format {
+            UsageFormat::Csv => {
+                writeln!(writer, "key,allowed,rejected")?;
+                for (key, usage) in &keys {
+                    writeln!(
+                        writer,
+                        "{},{},{}",
+                        csv_field(key),
+                        usage.allowed,
+                        usage.rejected
+                    )?;
+                }
+            }
+            UsageFormat::Json => {
+                write!(
+                    writer,
+                    "{{\"from\":{},\"to\":{},\"usage\":[",
+                    unix_secs(period.since),
+                    unix_secs(now)
+                )?;
+                for (i, (key, usage)) in keys.iter().enumerate() {
+                    if i > 0 {
+                        write!(writer, ",")?;
+                    }
+                    write!(
+                        writer,
+                        "{{\"key\":\"{}\",\"allowed\":{},\"rejected\":{}}}",
+                        json_escape(key),
+                        usage.allowed,
+                        usage.rejected
+                    )?;
+                }
+                writeln!(writer, "]}}")?;
+            }
+        }
+        writer.flush()
+    }
+
+    /// Spawns a task exporting the usage every `period` to a writer returned
+    /// by `open`, e.g. a new file per period.
+    ///
+    /// Must be called within a Tokio runtime. Failed exports are logged.
+    /// Abort the returned handle to stop exporting.
+    pub fn spawn<F, W>(&self, period: Duration, format: UsageFormat, mut open: F) -> JoinHandle<()>
+    where
+        F: FnMut() -> io::Result<W> + Send + 'static,
+        W: Write,
+    {
+        let usage = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            // The first tick completes immediately.
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                if let Err(err) = open().and_then(|writer| usage.export(format, writer)) {
+                    warn!("Usage export failed: {err}");
+                }
+            }
+        })
+    }
+
+    pub(crate) fn record(&self, key: &str, decision: Decision) {
+        let mut period = self.period();
+        let usage = match period.keys.get_mut(key) {
+            Some(usage) => usage,
+            None => period.keys.entry(key.to_string()).or_default(),
+        };
+        match decision {
+            Decision::Allowed => usage.allowed += 1,
+            Decision::Denied | Decision::Banned => usage.rejected += 1,
+        }
+    }
+
+    fn period(&self) -> MutexGuard<'_, Period> {
+        self.period.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs())
+        .unwrap_or(0)
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Escapes `value` for use inside a JSON string.
+pub(crate) fn json_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn export(usage: &UsageLog, format: UsageFormat) -> String {
+        let mut out = Vec::new();
+        usage.export(format, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn exports_csv_and_resets() {
+        let usage = UsageLog::new();
+        usage.record("203.0.113.7", Decision::Allowed);
+        usage.record("203.0.113.7", Decision::Denied);
+        usage.record("api-key:a,\"b\"", Decision::Banned);
+        usage.clone().record("198.51.100.1", Decision::Allowed);
+
+        assert_eq!(
+            export(&usage, UsageFormat::Csv),
+            "key,allowed,rejected\n\
+             198.51.100.1,1,0\n\
+             203.0.113.7,1,1\n\
+             \"api-key:a,\"\"b\"\"\",0,1\n"
+        );
+        assert_eq!(export(&usage, UsageFormat::Csv), "key,allowed,rejected\n");
+    }
+
+    #[test]
+    fn exports_json() {
+        let usage = UsageLog::new();
+        usage.record("api-key:\"x\"\n", Decision::Allowed);
+
+        let exported = export(&usage, UsageFormat::Json);
+        assert!(exported.starts_with("{\"from\":"), "{exported}");
+        assert!(
+            exported.ends_with(
+                ",\"usage\":[{\"key\":\"api-key:\\\"x\\\"\\u000a\",\"allowed\":1,\"rejected\":0}]}\n"
+            ),
+            "{exported}"
+        );
+    }
+
+    #[test]
+    fn escapes_json_strings() {
+        assert_eq!(json_escape("a\"b\\c\u{1}"), "a\\\"b\\\\c\\u0001");
+    }
+}